@@ -1,5 +1,8 @@
-use client::ClientBuilder;
-use crypto::sign::{Hs256, sign_token};
+use client::{ClientBuilder, Roster};
+use crypto::{
+    key::KeyMaterial,
+    sign::{Hs256, sign_token},
+};
 use testutil::generate_token;
 
 #[tokio::main]
@@ -9,15 +12,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .init();
 
     let token = generate_token(1, String::from("test"), vec![]);
-    let signed_token = sign_token(token, &Hs256::try_new(&[0; 16])?)?;
+    let signed_token = sign_token(token, &Hs256::try_new(&[0; 32])?)?;
 
-    let _client = ClientBuilder {
-        addr: "127.0.0.1:3781".parse()?,
-        encryption_key: vec![0; 16],
-        token: signed_token,
-    }
+    let client = ClientBuilder::new(
+        "127.0.0.1:3781".parse()?,
+        KeyMaterial::from_bytes(vec![0; 16]),
+        signed_token,
+    )
     .try_build()
     .await?;
 
+    // The server announces the connected peers right after the
+    // authentication.
+    let roster = client
+        .watch_roster()
+        .wait_for(Roster::is_synced)
+        .await?
+        .clone();
+    print!("{roster}");
+
     Ok(())
 }
@@ -1,35 +1,334 @@
-use crate::{ClientBuilder, Error, connection::do_handshake};
+use crate::{
+    ClientBuilder, Error,
+    connection::do_handshake,
+    reconnect::{ConnectionEvent, ReconnectPolicy, is_recoverable},
+    roster::Roster,
+    session::{Link, Session},
+    stream::Stream,
+};
 use crypto::{symm::Aes128CbcSha256, tls::SymmTls};
-use std::sync::Arc;
-use tokio::net::TcpStream;
-use tracing::{instrument, trace};
+use proto_core::{
+    common::{KeepaliveStatus, MessageQueue, QueueLimits, QueueMetrics, Scheduler},
+    sub_protocol::{
+        Message,
+        cmd::{Authenticate, Cmd, CmdEnum},
+        cmd_response::{self, CmdResponse, CmdResponsePayload},
+        handshake::HandshakeLimits,
+    },
+    token::SignedToken,
+    tunnel::{MAX_FRAME_SIZE, RekeyPolicy, Tunnel},
+};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    net::{
+        TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    sync::{broadcast, watch},
+    task::JoinHandle,
+    time::sleep,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument, trace};
+
+type ClientTunnel = Tunnel<OwnedReadHalf, OwnedWriteHalf, SymmTls>;
+type ClientQueue = MessageQueue<OwnedReadHalf, OwnedWriteHalf, SymmTls>;
 
 /// Internal VPN client struct.
+///
+/// Messages are exchanged with the server by a background task, which is
+/// stopped when the client is dropped. Use [`Client::shutdown`] to flush the
+/// queued messages first.
+///
+/// If the connection is lost, the task reconnects according to the
+/// [`ReconnectPolicy`], see [`Client::events`].
 pub struct Client {
-    pub(crate) _tcp_stream: TcpStream,
-    pub(crate) _tls: SymmTls,
+    pub(crate) session: Arc<Session>,
+    shutdown: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+/// Settings used to establish, and re-establish, the connection.
+struct Connector {
+    addr: SocketAddr,
+    encrypter: Arc<Aes128CbcSha256>,
+    token: SignedToken,
+    handshake_limits: HandshakeLimits,
+    rekey_policy: RekeyPolicy,
+    queue_limits: QueueLimits,
+    scheduler: Scheduler,
 }
 
 impl ClientBuilder {
     #[instrument(skip(self))]
     pub async fn try_build(self) -> Result<Client, Error> {
-        let encrypter = Aes128CbcSha256::new(self.encryption_key);
+        let connector = Connector {
+            addr: self.addr,
+            encrypter: Arc::new(Aes128CbcSha256::try_new(self.encryption_key.as_bytes())?),
+            token: self.token,
+            handshake_limits: self.handshake_limits,
+            rekey_policy: self.rekey_policy,
+            queue_limits: self.queue_limits,
+            scheduler: self.scheduler,
+        };
+        let (tunnel, queue) = connector.connect().await?;
+
+        let session = Arc::new(Session::new(
+            queue.sender(),
+            self.shared_ports,
+            self.accepted_ports,
+            self.priority_hints,
+            self.keepalive_policy,
+            self.flow_policy,
+        ));
+
+        let shutdown = CancellationToken::new();
+        let task = tokio::spawn(supervise(
+            connector,
+            self.reconnect_policy,
+            Arc::clone(&session),
+            (tunnel, queue),
+            shutdown.clone(),
+        ));
+
+        Ok(Client {
+            session,
+            shutdown,
+            task,
+        })
+    }
+}
+
+impl Connector {
+    /// Connects to the server, performs the handshake and authenticates.
+    async fn connect(&self) -> Result<(Arc<ClientTunnel>, ClientQueue), Error> {
         let mut tcp_stream = TcpStream::connect(self.addr).await?;
 
         trace!("Connected to {tcp_stream:?}");
 
         let (mut r, mut w) = tcp_stream.split();
 
-        let (server_random, client_random) = do_handshake(&mut r, &mut w).await?;
-        let tls = SymmTls::new((server_random, client_random), Arc::new(encrypter));
+        let (server_random, client_random) =
+            do_handshake(&mut r, &mut w, &self.handshake_limits).await?;
+        let tls = SymmTls::new((server_random, client_random), Arc::clone(&self.encrypter));
+
+        let tunnel = Arc::new(
+            Tunnel::from_tcp(tcp_stream, tls)
+                .with_rekey_policy(self.rekey_policy)
+                .with_max_frame_size(self.handshake_limits.max_pre_auth_frame_size),
+        );
+
+        authenticate(&tunnel, self.token.clone()).await?;
+
+        let queue = MessageQueue::new(Arc::clone(&tunnel))
+            .with_limits(self.queue_limits)
+            .with_scheduler(self.scheduler.clone());
+
+        Ok((tunnel, queue))
+    }
+
+    /// Reconnects with backoff. Returns `None` once the policy gives up, an
+    /// unrecoverable error occurs or `shutdown` is cancelled.
+    async fn reconnect(
+        &self,
+        policy: &ReconnectPolicy,
+        session: &Session,
+        shutdown: &CancellationToken,
+    ) -> Option<(Arc<ClientTunnel>, ClientQueue)> {
+        let mut attempt = 0;
 
-        let client = Client {
-            _tcp_stream: tcp_stream,
-            _tls: tls,
+        while policy.allows(attempt) {
+            attempt += 1;
+            let delay = policy.backoff(attempt);
+            session.emit(ConnectionEvent::Reconnecting { attempt, delay });
+
+            let connected = tokio::select! {
+                connected = async {
+                    sleep(delay).await;
+                    self.connect().await
+                } => connected,
+                _ = shutdown.cancelled() => return None,
+            };
+
+            match connected {
+                Ok(connection) => return Some(connection),
+                Err(error) if is_recoverable(&error) => {
+                    info!(attempt, "Could not reconnect: {error}");
+                }
+                Err(error) => {
+                    info!("Giving up reconnecting: {error}");
+                    return None;
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Serves the connection, and reconnects whenever it is lost until the
+/// policy gives up or the client is shut down.
+async fn supervise(
+    connector: Connector,
+    policy: ReconnectPolicy,
+    session: Arc<Session>,
+    (mut tunnel, mut queue): (Arc<ClientTunnel>, ClientQueue),
+    shutdown: CancellationToken,
+) {
+    loop {
+        let result = tokio::select! {
+            result = queue.message_service(&shutdown) => result.map_err(Error::from),
+            result = dispatch_messages(&tunnel, &session) => result,
+            result = session.ping() => result,
         };
 
-        Ok(client)
+        session.detach();
+        let _ = tunnel.shutdown().await;
+
+        let Err(error) = result else { break };
+        info!("Connection closed: {error}");
+        if shutdown.is_cancelled() || !is_recoverable(&error) {
+            break;
+        }
+        session.emit(ConnectionEvent::Disconnected {
+            reason: error.to_string(),
+        });
+
+        let Some(connection) = connector.reconnect(&policy, &session, &shutdown).await else {
+            break;
+        };
+        (tunnel, queue) = connection;
+        session.attach(queue.sender());
+        session.emit(ConnectionEvent::Reconnected);
+        info!("Reconnected");
+    }
+
+    session.shut();
+    session.emit(ConnectionEvent::Closed);
+}
+
+/// Snapshot of the state of the connection to the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientStatus {
+    /// Round-trip time and missed pongs of the keepalive pings.
+    pub keepalive: KeepaliveStatus,
+    /// Whether the tunnel to the server is currently up.
+    pub connected: bool,
+    /// Whether the client is closed for good.
+    pub closed: bool,
+}
+
+impl Client {
+    /// Returns a snapshot of the outgoing message queue metrics.
+    pub fn queue_metrics(&self) -> QueueMetrics {
+        self.session.queue_metrics()
     }
+
+    /// Returns a snapshot of the connection state, including the round-trip
+    /// time measured by the keepalive pings.
+    pub fn status(&self) -> ClientStatus {
+        ClientStatus {
+            keepalive: self.session.keepalive.status(),
+            connected: self.session.link() == Link::Up,
+            closed: self.is_closed(),
+        }
+    }
+
+    /// Subscribes to the changes of the connection to the server.
+    pub fn events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.session.subscribe()
+    }
+
+    /// Returns a snapshot of the other clients connected to the server.
+    pub fn roster(&self) -> Roster {
+        self.session.roster.borrow().clone()
+    }
+
+    /// Subscribes to the roster, which is marked as changed whenever a peer
+    /// connects or disconnects.
+    pub fn watch_roster(&self) -> watch::Receiver<Roster> {
+        self.session.roster.subscribe()
+    }
+
+    /// Opens a stream to `port` of the client with `token_id`. The port must
+    /// be shared or accepted by that client.
+    #[instrument(skip(self))]
+    pub async fn open_stream(&self, token_id: u64, port: u16) -> Result<Stream, Error> {
+        self.session.open_stream(token_id, port).await
+    }
+
+    /// Waits for the next stream opened by another client to one of the
+    /// [`ClientBuilder::accepted_ports`]. Fails once the client is closed.
+    pub async fn accept_stream(&self) -> Result<Stream, Error> {
+        self.session.accept_stream().await
+    }
+
+    /// Returns `true` once the client is closed for good. A client waiting
+    /// to reconnect is not closed.
+    pub fn is_closed(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Terminates all streams, flushes the queued messages and closes the
+    /// tunnel.
+    pub async fn shutdown(&mut self) {
+        self.session.terminate_all().await;
+        self.shutdown.cancel();
+        let _ = (&mut self.task).await;
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.task.abort();
+        // Forwarded listeners may still hold the session.
+        self.session.shut();
+    }
+}
+
+/// Encodes and sends a message through the tunnel.
+async fn send(tunnel: &ClientTunnel, message: &Message) -> Result<(), Error> {
+    Ok(tunnel.send(&message.encode()?).await?)
+}
+
+/// Receives and decodes a message from the tunnel.
+async fn recv(tunnel: &ClientTunnel) -> Result<Message, Error> {
+    Ok(Message::decode(&tunnel.recv().await?)?)
 }
 
-impl Client {}
+/// Sends the ID token to the server and awaits its response. Once
+/// authenticated, the tunnel accepts frames up to [`MAX_FRAME_SIZE`].
+#[instrument(skip_all)]
+async fn authenticate(tunnel: &ClientTunnel, token: SignedToken) -> Result<(), Error> {
+    send(
+        tunnel,
+        &Message::Cmd(Cmd {
+            response_id: 0,
+            payload: CmdEnum::Authenticate(Authenticate { token }),
+        }),
+    )
+    .await?;
+
+    let Message::CmdResponse(CmdResponse {
+        payload: CmdResponsePayload::Authenticate(response),
+        ..
+    }) = recv(tunnel).await?
+    else {
+        return Err(Error::UnexpectedMessage);
+    };
+
+    if let cmd_response::Authenticate::Success = response {
+        info!("Authenticated");
+        tunnel.set_max_frame_size(MAX_FRAME_SIZE).await;
+        Ok(())
+    } else {
+        Err(Error::Authentication(response))
+    }
+}
+
+/// Dispatches messages from the server until the tunnel is closed.
+async fn dispatch_messages(tunnel: &ClientTunnel, session: &Arc<Session>) -> Result<(), Error> {
+    loop {
+        session.dispatch(recv(tunnel).await?).await?;
+    }
+}
@@ -2,20 +2,35 @@ use crate::Error;
 use proto_core::{
     random_bytes,
     sub_protocol::handshake::{
-        self, HandshakeAlert, HandshakeContentType, read_handshake_payload, write_handshake_payload,
+        self, HandshakeAlert, HandshakeContentType, HandshakeLimits, PayloadLimits,
+        read_handshake_payload, write_handshake_payload,
     },
 };
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time::timeout,
+};
 use tracing::{info, instrument, trace};
 
 /// Client-side implementation of the handshake protocol (version 0.1).
 ///
-/// Returns an [`Error`] if an error occurs, then the connection should
-/// terminated.
+/// Returns an [`Error`] if an error occurs or the handshake exceeds its
+/// [`HandshakeLimits`], then the connection should terminated.
 #[instrument(skip(r, w))]
 pub async fn do_handshake<R: Unpin + AsyncRead, W: Unpin + AsyncWrite>(
     r: &mut R,
     w: &mut W,
+    limits: &HandshakeLimits,
+) -> Result<([u8; 32], [u8; 32]), Error> {
+    timeout(limits.timeout, exchange_hellos(r, w, &limits.payload))
+        .await
+        .map_err(|_| Error::Handshake(HandshakeAlert::Timeout))?
+}
+
+async fn exchange_hellos<R: Unpin + AsyncRead, W: Unpin + AsyncWrite>(
+    r: &mut R,
+    w: &mut W,
+    limits: &PayloadLimits,
 ) -> Result<([u8; 32], [u8; 32]), Error> {
     let client_hello = handshake::ClientHello {
         version: 0,
@@ -29,7 +44,7 @@ pub async fn do_handshake<R: Unpin + AsyncRead, W: Unpin + AsyncWrite>(
 
     trace!("Sent client hello: {client_hello:?}");
 
-    let (content_type, payload) = read_handshake_payload(r).await?;
+    let (content_type, payload) = read_handshake_payload(r, limits).await?;
 
     // The client expects the server's first payload to be a `ServerHello`
     // or `HandshakeAlert`.
@@ -78,7 +93,9 @@ mod tests {
     use super::do_handshake;
     use proto_core::{
         random_bytes,
-        sub_protocol::handshake::{self, HandshakeContentType, read_handshake_payload},
+        sub_protocol::handshake::{
+            self, HandshakeContentType, HandshakeLimits, PayloadLimits, read_handshake_payload,
+        },
     };
     use testutil::{DynResult, send_handshake_payload};
     use tokio::io::simplex;
@@ -88,9 +105,15 @@ mod tests {
         let (mut sr, mut cw) = simplex(u16::MAX as usize);
         let (mut cr, mut sw) = simplex(u16::MAX as usize);
 
-        let task = tokio::spawn(async move { do_handshake(&mut cr, &mut cw).await.unwrap() });
+        let task = tokio::spawn(async move {
+            do_handshake(&mut cr, &mut cw, &HandshakeLimits::default())
+                .await
+                .unwrap()
+        });
 
-        let (content_type, _) = read_handshake_payload(&mut sr).await.unwrap();
+        let (content_type, _) = read_handshake_payload(&mut sr, &PayloadLimits::default())
+            .await
+            .unwrap();
 
         assert_eq!(content_type, HandshakeContentType::ClientHello);
 
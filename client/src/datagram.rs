@@ -0,0 +1,399 @@
+//! Forwarding of UDP datagrams through relayed flows.
+//!
+//! Each source address of a forwarded local socket gets its own flow, and the
+//! port-sharing client answers each flow from its own socket, like a NAT. A
+//! flow without datagrams in either direction for
+//! [`FlowPolicy::idle_timeout`] is terminated on both ends.
+
+use crate::{Client, Error, session::Session};
+use proto_core::{
+    common::Priority,
+    sub_protocol::application_data::{ApplicationDataEnum, MAX_DATAGRAM_SIZE, TerminateReason},
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc::{self, Receiver, Sender, error::TrySendError},
+    task::{JoinHandle, JoinSet},
+    time::{Instant, sleep_until},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, instrument, trace};
+
+/// Number of datagrams buffered per flow and direction. Further datagrams
+/// are dropped, as a congested network would.
+const FLOW_BACKLOG: usize = 64;
+
+/// Expiry of the datagram flows of a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowPolicy {
+    /// Time without datagrams in either direction after which a flow is
+    /// terminated.
+    pub idle_timeout: Duration,
+}
+
+impl Default for FlowPolicy {
+    /// Flows expire after a minute, in line with common NAT mappings.
+    fn default() -> Self {
+        FlowPolicy {
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// State of a flow shared with the session.
+pub(crate) struct FlowState {
+    pub(crate) connection_id: u64,
+    /// Class of all payloads of the flow, so they are never reordered.
+    pub(crate) priority: Priority,
+    datagrams: Sender<Vec<u8>>,
+    closed: CancellationToken,
+    last_activity: Mutex<Instant>,
+}
+
+impl FlowState {
+    pub(crate) fn new(connection_id: u64, priority: Priority) -> (FlowState, Receiver<Vec<u8>>) {
+        let (datagrams, receiver) = mpsc::channel(FLOW_BACKLOG);
+
+        (
+            FlowState {
+                connection_id,
+                priority,
+                datagrams,
+                closed: CancellationToken::new(),
+                last_activity: Mutex::new(Instant::now()),
+            },
+            receiver,
+        )
+    }
+
+    /// Delivers a datagram received from the peer, dropping it if the
+    /// backlog is full.
+    pub(crate) fn deliver(&self, payload: Vec<u8>) {
+        self.touch();
+        if let Err(TrySendError::Full(_)) = self.datagrams.try_send(payload) {
+            trace!(self.connection_id, "Flow backlog full, dropped datagram");
+        }
+    }
+
+    /// Wakes the flow up after it was terminated.
+    pub(crate) fn close(&self) {
+        self.closed.cancel();
+    }
+
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+}
+
+/// A datagram flow to a port of another client, owned by the task relaying
+/// it. Dropping the flow terminates it.
+pub(crate) struct Flow {
+    session: Arc<Session>,
+    state: Arc<FlowState>,
+    datagrams: Receiver<Vec<u8>>,
+    idle_timeout: Duration,
+}
+
+impl Flow {
+    pub(crate) fn new(
+        session: Arc<Session>,
+        state: Arc<FlowState>,
+        datagrams: Receiver<Vec<u8>>,
+        idle_timeout: Duration,
+    ) -> Flow {
+        Flow {
+            session,
+            state,
+            datagrams,
+            idle_timeout,
+        }
+    }
+
+    /// Queues a datagram to the peer.
+    async fn send(&self, payload: &[u8]) -> Result<(), Error> {
+        self.state.touch();
+        self.session
+            .send_application_data(
+                self.state.connection_id,
+                ApplicationDataEnum::Datagram {
+                    payload: payload.to_vec(),
+                },
+                self.state.priority,
+            )
+            .await
+    }
+
+    /// Receives the next datagram from the peer. Returns `None` once the
+    /// flow is terminated.
+    async fn recv(&mut self) -> Option<Vec<u8>> {
+        tokio::select! {
+            biased;
+            Some(payload) = self.datagrams.recv() => Some(payload),
+            _ = self.state.closed.cancelled() => None,
+        }
+    }
+
+    /// Instant at which the flow expires, unless a datagram is exchanged
+    /// before.
+    fn expiry(&self) -> Instant {
+        *self.state.last_activity.lock().unwrap() + self.idle_timeout
+    }
+
+    /// Terminates the flow, unless the peer already did.
+    async fn terminate(self, reason: TerminateReason, detail: Option<String>) {
+        if self.session.remove_flow(self.state.connection_id).is_some() {
+            let _ = self
+                .session
+                .send_application_data(
+                    self.state.connection_id,
+                    ApplicationDataEnum::TerminateConnection { reason, detail },
+                    self.state.priority,
+                )
+                .await;
+        }
+    }
+}
+
+impl Drop for Flow {
+    fn drop(&mut self) {
+        let connection_id = self.state.connection_id;
+        let priority = self.state.priority;
+
+        if self.session.remove_flow(connection_id).is_some()
+            && let Ok(handle) = tokio::runtime::Handle::try_current()
+        {
+            let session = Arc::clone(&self.session);
+            handle.spawn(async move {
+                let _ = session
+                    .send_application_data(
+                        connection_id,
+                        ApplicationDataEnum::TerminateConnection {
+                            reason: TerminateReason::Closed,
+                            detail: None,
+                        },
+                        priority,
+                    )
+                    .await;
+            });
+        }
+    }
+}
+
+/// Local UDP socket forwarding datagrams to a port shared by another client.
+/// The socket is closed, and its flows terminated, when the value is
+/// dropped.
+#[must_use]
+pub struct UdpForward {
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl UdpForward {
+    /// Returns the local address the socket is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for UdpForward {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Client {
+    /// Binds a UDP socket on `addr` and forwards the datagrams it receives to
+    /// `port` of the client with `token_id`. Replies are sent back to the
+    /// address each datagram came from.
+    #[instrument(skip(self))]
+    pub async fn forward_udp(
+        &self,
+        addr: SocketAddr,
+        token_id: u64,
+        port: u16,
+    ) -> Result<UdpForward, Error> {
+        let socket = Arc::new(UdpSocket::bind(addr).await?);
+        let local_addr = socket.local_addr()?;
+        let session = Arc::clone(&self.session);
+
+        let task = tokio::spawn(async move {
+            // Datagrams to relay, by source address.
+            let mut flows: HashMap<SocketAddr, Sender<Vec<u8>>> = HashMap::new();
+            // Flow tasks, yielding their source once the flow ended. They are
+            // aborted, terminating their flows, along with the forward.
+            let mut tasks = JoinSet::new();
+            let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+
+            loop {
+                let (n, source) = tokio::select! {
+                    received = socket.recv_from(&mut buf) => match received {
+                        Ok(received) => received,
+                        Err(io_error) => {
+                            // E.g. an ICMP error of a reply sent to a source.
+                            trace!("Could not receive datagram: {io_error}");
+                            continue;
+                        }
+                    },
+                    Some(ended) = tasks.join_next(), if !tasks.is_empty() => {
+                        // The flow of the source expired or was terminated.
+                        if let Ok(source) = ended
+                            && flows.get(&source).is_some_and(Sender::is_closed)
+                        {
+                            flows.remove(&source);
+                        }
+                        continue;
+                    }
+                };
+                let payload = buf[..n].to_vec();
+
+                let payload = match flows.get(&source) {
+                    Some(flow) => match flow.try_send(payload) {
+                        Ok(()) => continue,
+                        Err(TrySendError::Full(_)) => {
+                            trace!(%source, "Flow backlog full, dropped datagram");
+                            continue;
+                        }
+                        Err(TrySendError::Closed(payload)) => payload,
+                    },
+                    None => payload,
+                };
+
+                // The flow is opened by its own task, so the datagrams of
+                // other sources are not held up meanwhile.
+                let (sender, datagrams) = mpsc::channel(FLOW_BACKLOG);
+                let _ = sender.try_send(payload);
+                flows.insert(source, sender);
+
+                tasks.spawn(forward_flow(
+                    Arc::clone(&session),
+                    token_id,
+                    port,
+                    Arc::clone(&socket),
+                    source,
+                    datagrams,
+                ));
+            }
+        });
+
+        Ok(UdpForward { local_addr, task })
+    }
+}
+
+/// Opens a flow for one source of a forwarded socket and relays it. Returns
+/// the source once the flow ended.
+async fn forward_flow(
+    session: Arc<Session>,
+    token_id: u64,
+    port: u16,
+    socket: Arc<UdpSocket>,
+    source: SocketAddr,
+    datagrams: Receiver<Vec<u8>>,
+) -> SocketAddr {
+    match session.open_flow(token_id, port).await {
+        Ok(flow) => relay_forwarded(flow, socket, source, datagrams).await,
+        Err(error) => debug!(%source, "Could not open flow: {error}"),
+    }
+
+    source
+}
+
+/// Relays the datagrams of one source of a forwarded socket until the flow
+/// expires, is terminated, or the forward is dropped.
+async fn relay_forwarded(
+    mut flow: Flow,
+    socket: Arc<UdpSocket>,
+    source: SocketAddr,
+    mut datagrams: Receiver<Vec<u8>>,
+) {
+    loop {
+        let expiry = flow.expiry();
+
+        tokio::select! {
+            payload = datagrams.recv() => match payload {
+                Some(payload) => {
+                    if flow.send(&payload).await.is_err() {
+                        return;
+                    }
+                }
+                None => return flow.terminate(TerminateReason::Closed, None).await,
+            },
+            payload = flow.recv() => match payload {
+                Some(payload) => {
+                    if let Err(io_error) = socket.send_to(&payload, source).await {
+                        trace!(%source, "Could not send datagram: {io_error}");
+                    }
+                }
+                None => {
+                    debug!(%source, "Flow terminated");
+                    return;
+                }
+            },
+            () = sleep_until(expiry) => {
+                if flow.expiry() <= Instant::now() {
+                    trace!(%source, "Flow expired");
+                    return flow.terminate(TerminateReason::IdleTimeout, None).await;
+                }
+            }
+        }
+    }
+}
+
+/// Relays a flow to a locally shared port, from a socket of its own.
+pub(crate) async fn relay_shared(mut flow: Flow, port: u16) {
+    let socket = match UdpSocket::bind(("127.0.0.1", 0)).await {
+        Ok(socket) => socket,
+        Err(io_error) => {
+            return flow
+                .terminate(TerminateReason::LocalError, Some(io_error.to_string()))
+                .await;
+        }
+    };
+    if let Err(io_error) = socket.connect(("127.0.0.1", port)).await {
+        return flow
+            .terminate(TerminateReason::RemoteRefused, Some(io_error.to_string()))
+            .await;
+    }
+
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+
+    loop {
+        let expiry = flow.expiry();
+
+        tokio::select! {
+            received = socket.recv(&mut buf) => match received {
+                Ok(n) => {
+                    if flow.send(&buf[..n]).await.is_err() {
+                        return;
+                    }
+                }
+                // E.g. nothing listens on the port.
+                Err(io_error) => {
+                    debug!(port, "Could not receive datagram: {io_error}");
+                    return flow
+                        .terminate(TerminateReason::RemoteRefused, Some(io_error.to_string()))
+                        .await;
+                }
+            },
+            payload = flow.recv() => match payload {
+                Some(payload) => {
+                    if let Err(io_error) = socket.send(&payload).await {
+                        trace!(port, "Could not send datagram: {io_error}");
+                    }
+                }
+                None => return,
+            },
+            () = sleep_until(expiry) => {
+                if flow.expiry() <= Instant::now() {
+                    trace!(port, "Flow expired");
+                    return flow.terminate(TerminateReason::IdleTimeout, None).await;
+                }
+            }
+        }
+    }
+}
@@ -1,7 +1,14 @@
 use bincode::error::{DecodeError, EncodeError};
 use crypto::CryptoError;
-use proto_core::sub_protocol::handshake::HandshakeAlert as HandshakeError;
-use std::io::Error as IoError;
+use proto_core::{
+    common::QueueError,
+    sub_protocol::{
+        alert::Alert, application_data::TerminateReason, cmd_response::Authenticate,
+        handshake::HandshakeAlert as HandshakeError,
+    },
+    tunnel::TunnelError,
+};
+use std::io::{Error as IoError, ErrorKind};
 
 /// Client error types.
 #[derive(Debug)]
@@ -11,6 +18,22 @@ pub enum Error {
     Encode(EncodeError),
     Decode(DecodeError),
     Handshake(HandshakeError),
+    Tunnel(TunnelError),
+    Queue(QueueError),
+    /// The server sent a message that is not expected at this stage.
+    UnexpectedMessage,
+    /// The server rejected the ID token.
+    Authentication(Authenticate),
+    /// The server closed the connection with an alert.
+    Alert(Alert),
+    /// The port-sharing client refused the connection.
+    ConnectionRefused,
+    /// The connection was terminated by the peer, the server or the client
+    /// itself.
+    ConnectionTerminated {
+        reason: TerminateReason,
+        detail: Option<String>,
+    },
 }
 
 impl std::fmt::Display for Error {
@@ -21,10 +44,57 @@ impl std::fmt::Display for Error {
             Self::Encode(encode_error) => write!(f, "encode: {encode_error}"),
             Self::Decode(decode_error) => write!(f, "encode: {decode_error}"),
             Self::Handshake(handshake_alert) => write!(f, "handshake: {handshake_alert:?}"),
+            Self::Tunnel(tunnel_error) => write!(f, "tunnel: {tunnel_error}"),
+            Self::Queue(queue_error) => write!(f, "queue: {queue_error}"),
+            Self::UnexpectedMessage => write!(f, "unexpected message"),
+            Self::Authentication(response) => write!(f, "authentication: {response:?}"),
+            Self::Alert(alert) => write!(f, "alert: {alert:?}"),
+            Self::ConnectionRefused => write!(f, "connection refused"),
+            Self::ConnectionTerminated {
+                reason,
+                detail: Some(detail),
+            } => write!(f, "connection terminated: {reason} ({detail})"),
+            Self::ConnectionTerminated {
+                reason,
+                detail: None,
+            } => write!(f, "connection terminated: {reason}"),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
-proto_core::error_impl_from!(Error; Crypto, Io, Encode, Decode, Handshake);
+proto_core::error_impl_from!(Error; Crypto, Io, Encode, Decode, Handshake, Tunnel, Queue);
+
+/// Errors surfaced by the I/O traits of [`Stream`](crate::Stream).
+impl From<Error> for IoError {
+    fn from(error: Error) -> Self {
+        let kind = match error {
+            Error::Io(io_error) => return io_error,
+            Error::Tunnel(TunnelError::Disconnected) => ErrorKind::NotConnected,
+            Error::ConnectionRefused => ErrorKind::ConnectionRefused,
+            Error::ConnectionTerminated { reason, .. } => error_kind(reason),
+            _ => ErrorKind::Other,
+        };
+
+        IoError::new(kind, error)
+    }
+}
+
+/// I/O error kind matching the termination `reason` of a stream.
+pub(crate) fn error_kind(reason: TerminateReason) -> ErrorKind {
+    match reason {
+        TerminateReason::RemoteRefused
+        | TerminateReason::PortNotShared
+        | TerminateReason::TooManyConnections => ErrorKind::ConnectionRefused,
+        TerminateReason::PermissionDenied => ErrorKind::PermissionDenied,
+        TerminateReason::PeerDisconnected | TerminateReason::Shutdown => {
+            ErrorKind::ConnectionAborted
+        }
+        TerminateReason::IdleTimeout => ErrorKind::TimedOut,
+        TerminateReason::FlowControlViolation | TerminateReason::ProtocolViolation => {
+            ErrorKind::InvalidData
+        }
+        TerminateReason::Closed | TerminateReason::LocalError => ErrorKind::ConnectionReset,
+    }
+}
@@ -0,0 +1,203 @@
+//! Forwarding of local TCP connections through relayed streams.
+
+use crate::{Client, Error, stream::Stream};
+use proto_core::{common::MAX_DATA_CHUNK, sub_protocol::application_data::TerminateReason};
+use std::{
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tracing::{debug, instrument};
+
+/// Local listener forwarding accepted connections to a port shared by
+/// another client. The listener is closed when the value is dropped.
+#[must_use]
+pub struct Forward {
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl Forward {
+    /// Returns the local address the listener is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for Forward {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Target client of a [`Forward`].
+#[derive(Debug, Clone)]
+enum Target {
+    TokenId(u64),
+    /// Name or token ID resolved against the roster for each connection.
+    Peer(String),
+}
+
+impl Client {
+    /// Listens on `addr` and forwards each accepted connection to `port` of
+    /// the client with `token_id`, similar to `ssh -L`.
+    #[instrument(skip(self))]
+    pub async fn forward(
+        &self,
+        addr: SocketAddr,
+        token_id: u64,
+        port: u16,
+    ) -> Result<Forward, Error> {
+        self.spawn_forward(addr, Target::TokenId(token_id), port)
+            .await
+    }
+
+    /// Like [`Client::forward`], to the peer named `peer`, e.g.
+    /// `license-srv-01`. The name is resolved against the
+    /// [`roster`](Client::roster) for each accepted connection, so the
+    /// forward follows the peer across reconnections.
+    #[instrument(skip(self))]
+    pub async fn forward_peer(
+        &self,
+        addr: SocketAddr,
+        peer: &str,
+        port: u16,
+    ) -> Result<Forward, Error> {
+        self.spawn_forward(addr, Target::Peer(String::from(peer)), port)
+            .await
+    }
+
+    /// Listens on each port of `ports` at `ip` and forwards it to the same
+    /// port of the client with `token_id`.
+    ///
+    /// FlexLM-style license managers announce the port of their vendor
+    /// daemons to the license clients, so the local ports must match the
+    /// shared ones. Fails if any of the ports cannot be bound, closing the
+    /// listeners already bound.
+    #[instrument(skip(self))]
+    pub async fn forward_ports(
+        &self,
+        ip: IpAddr,
+        token_id: u64,
+        ports: &[RangeInclusive<u16>],
+    ) -> Result<Vec<Forward>, Error> {
+        self.spawn_forwards(ip, Target::TokenId(token_id), ports)
+            .await
+    }
+
+    /// Like [`Client::forward_ports`], to the peer named `peer`.
+    #[instrument(skip(self))]
+    pub async fn forward_peer_ports(
+        &self,
+        ip: IpAddr,
+        peer: &str,
+        ports: &[RangeInclusive<u16>],
+    ) -> Result<Vec<Forward>, Error> {
+        self.spawn_forwards(ip, Target::Peer(String::from(peer)), ports)
+            .await
+    }
+
+    async fn spawn_forwards(
+        &self,
+        ip: IpAddr,
+        target: Target,
+        ports: &[RangeInclusive<u16>],
+    ) -> Result<Vec<Forward>, Error> {
+        let mut forwards = Vec::new();
+
+        for port in ports.iter().flat_map(|ports| ports.clone()) {
+            let forward = self
+                .spawn_forward(SocketAddr::new(ip, port), target.clone(), port)
+                .await?;
+            forwards.push(forward);
+        }
+
+        Ok(forwards)
+    }
+
+    async fn spawn_forward(
+        &self,
+        addr: SocketAddr,
+        target: Target,
+        port: u16,
+    ) -> Result<Forward, Error> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let session = self.session.clone();
+        let target = Arc::new(target);
+
+        let task = tokio::spawn(async move {
+            while let Ok((socket, remote_addr)) = listener.accept().await {
+                let session = session.clone();
+                let target = Arc::clone(&target);
+
+                tokio::spawn(async move {
+                    let token_id = match &*target {
+                        Target::TokenId(token_id) => Some(*token_id),
+                        Target::Peer(peer) => session.roster.borrow().resolve(peer),
+                    };
+                    let Some(token_id) = token_id else {
+                        debug!(%remote_addr, ?target, "Unknown peer");
+                        let _ = socket.set_zero_linger();
+                        return;
+                    };
+
+                    match session.open_stream(token_id, port).await {
+                        Ok(stream) => pump(stream, socket).await,
+                        Err(error) => {
+                            debug!(%remote_addr, "Could not open stream: {error}");
+                            let _ = socket.set_zero_linger();
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(Forward { local_addr, task })
+    }
+}
+
+/// Copies data between a local socket and a stream until both directions
+/// are closed, propagating the half-closes of either side.
+///
+/// If either side fails, the stream is terminated and the local socket is
+/// reset, so the local application sees an error rather than a clean EOF.
+pub(crate) async fn pump(stream: Stream, socket: TcpStream) {
+    let (sender, mut receiver) = stream.split();
+    let (mut r, mut w) = socket.into_split();
+
+    let upstream = async {
+        let mut buf = vec![0; MAX_DATA_CHUNK];
+
+        loop {
+            let n = r.read(&mut buf).await?;
+            if n == 0 {
+                return sender.finish().await;
+            }
+
+            sender.send(&buf[..n]).await?;
+        }
+    };
+
+    let downstream = async {
+        while let Some(data) = receiver.recv().await? {
+            w.write_all(&data).await?;
+        }
+
+        Ok::<_, Error>(w.shutdown().await?)
+    };
+
+    if let Err(error) = tokio::try_join!(upstream, downstream) {
+        debug!("Stream closed: {error}");
+        let _ = r.as_ref().set_zero_linger();
+        // No-op if the peer or the server terminated the stream.
+        let _ = sender
+            .terminate(TerminateReason::LocalError, Some(error.to_string()))
+            .await;
+    }
+}
@@ -4,13 +4,32 @@
 
 mod client;
 pub mod connection;
+mod datagram;
 mod error;
+mod forward;
+mod proxy;
+mod reconnect;
+mod roster;
+mod session;
+mod stream;
 
-pub use client::Client;
+pub use client::{Client, ClientStatus};
+pub use datagram::{FlowPolicy, UdpForward};
 pub use error::Error;
+pub use forward::Forward;
+pub use proxy::Proxy;
+pub use reconnect::{ConnectionEvent, ReconnectPolicy};
+pub use roster::{PEER_DOMAIN, Peer, Roster};
+pub use stream::Stream;
 
-use proto_core::token::SignedToken;
-use std::net::SocketAddr;
+use crypto::key::KeyMaterial;
+use proto_core::{
+    common::{KeepalivePolicy, PriorityHints, QueueLimits, Scheduler},
+    sub_protocol::handshake::HandshakeLimits,
+    token::SignedToken,
+    tunnel::RekeyPolicy,
+};
+use std::{net::SocketAddr, ops::RangeInclusive};
 
 pub use proto_core;
 
@@ -22,8 +41,61 @@ pub struct ClientBuilder {
     pub addr: SocketAddr,
 
     /// Symmetric encryption key used for encryption.
-    pub encryption_key: Vec<u8>,
+    pub encryption_key: KeyMaterial,
 
     /// ID token.
     pub token: SignedToken,
+
+    /// Timeout and payload sizes of the handshake.
+    pub handshake_limits: HandshakeLimits,
+
+    /// Limits after which the traffic keys are rekeyed.
+    pub rekey_policy: RekeyPolicy,
+    /// Interval of the keepalive pings sent to the server and number of
+    /// missed pongs after which the connection is closed.
+    pub keepalive_policy: KeepalivePolicy,
+
+    /// Capacity limits of the outgoing message queue.
+    pub queue_limits: QueueLimits,
+    /// Order in which the message queue serves its priority levels.
+    pub scheduler: Scheduler,
+    /// Priority classes of relayed connections by port.
+    pub priority_hints: PriorityHints,
+
+    /// Backoff of the reconnections after the connection is lost.
+    pub reconnect_policy: ReconnectPolicy,
+    /// Expiry of idle datagram flows.
+    pub flow_policy: FlowPolicy,
+
+    /// Ranges of local ports other clients may connect to through the
+    /// server, e.g. `27000..=27009` for a license manager and its vendor
+    /// daemons.
+    pub shared_ports: Vec<RangeInclusive<u16>>,
+    /// Ports other clients may connect to, whose connections are handed to
+    /// [`Client::accept_stream`] instead of a local socket.
+    pub accepted_ports: Vec<u16>,
+}
+
+impl ClientBuilder {
+    /// Creates a builder for a client connecting to the server at `addr`.
+    ///
+    /// Limits and policies take their defaults, and no ports are shared or
+    /// accepted. Other settings are given with the struct update syntax.
+    pub fn new(addr: SocketAddr, encryption_key: KeyMaterial, token: SignedToken) -> ClientBuilder {
+        ClientBuilder {
+            addr,
+            encryption_key,
+            token,
+            handshake_limits: HandshakeLimits::default(),
+            rekey_policy: RekeyPolicy::default(),
+            keepalive_policy: KeepalivePolicy::default(),
+            queue_limits: QueueLimits::default(),
+            scheduler: Scheduler::default(),
+            priority_hints: PriorityHints::default(),
+            reconnect_policy: ReconnectPolicy::default(),
+            flow_policy: FlowPolicy::default(),
+            shared_ports: vec![],
+            accepted_ports: vec![],
+        }
+    }
 }
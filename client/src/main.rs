@@ -0,0 +1,55 @@
+//! VPN client binary.
+//!
+//! ```text
+//! client <addr> <encryption-key> <token> peers
+//! ```
+//!
+//! Keys are given as key sources, e.g. `hex:/etc/dehset/key` or
+//! `base64-env:DEHSET_KEY`. The token source holds the bincode encoded signed
+//! token of the client.
+//!
+//! Commands:
+//! - `peers`: lists the other clients connected to the server.
+
+use client::{ClientBuilder, Roster};
+use crypto::key::{KeyMaterial, KeySource};
+use proto_core::token::SignedToken;
+use std::env;
+
+const USAGE: &str = "usage: client <addr> <encryption-key> <token> peers";
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt().init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [addr, encryption_key, token, command] = args.as_slice() else {
+        return Err(USAGE.into());
+    };
+    if command != "peers" {
+        return Err(USAGE.into());
+    }
+
+    let token = KeyMaterial::load(&token.parse::<KeySource>()?)?;
+    let (token, _): (SignedToken, _) =
+        bincode::serde::decode_from_slice(token.as_bytes(), bincode::config::standard())?;
+
+    let mut client = ClientBuilder::new(
+        addr.parse()?,
+        KeyMaterial::load(&encryption_key.parse::<KeySource>()?)?,
+        token,
+    )
+    .try_build()
+    .await?;
+
+    // The server lists the connected peers right after the authentication.
+    let roster = client
+        .watch_roster()
+        .wait_for(Roster::is_synced)
+        .await?
+        .clone();
+    print!("{roster}");
+
+    client.shutdown().await;
+    Ok(())
+}
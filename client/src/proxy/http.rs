@@ -0,0 +1,189 @@
+//! HTTP/1.1 CONNECT proxy, see RFC 9110 section 9.3.6. Other methods are
+//! refused, the proxy only tunnels to `peer:port` targets.
+
+use super::Proxy;
+use crate::{Client, Error, forward::pump, session::Session};
+use proto_core::sub_protocol::application_data::TerminateReason;
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tracing::{debug, instrument};
+
+/// Maximum size of a request head.
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+
+/// Status codes answered by the proxy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    ConnectionEstablished,
+    BadRequest,
+    Forbidden,
+    MethodNotAllowed,
+    HeaderFieldsTooLarge,
+    BadGateway,
+}
+
+impl Status {
+    fn line(self) -> &'static str {
+        match self {
+            Status::ConnectionEstablished => "200 Connection Established",
+            Status::BadRequest => "400 Bad Request",
+            Status::Forbidden => "403 Forbidden",
+            Status::MethodNotAllowed => "405 Method Not Allowed",
+            Status::HeaderFieldsTooLarge => "431 Request Header Fields Too Large",
+            Status::BadGateway => "502 Bad Gateway",
+        }
+    }
+}
+
+impl From<&Error> for Status {
+    fn from(error: &Error) -> Self {
+        match error {
+            Error::ConnectionTerminated {
+                reason: TerminateReason::PermissionDenied,
+                ..
+            } => Status::Forbidden,
+            _ => Status::BadGateway,
+        }
+    }
+}
+
+impl Client {
+    /// Serves an HTTP CONNECT proxy on `addr`, opening a stream for each
+    /// `CONNECT <peer>:<port>`, where the peer is a name or a token ID.
+    ///
+    /// Requests the token may not make are answered with `403 Forbidden`,
+    /// unknown or unreachable peers with `502 Bad Gateway`.
+    #[instrument(skip(self))]
+    pub async fn http_proxy(&self, addr: SocketAddr) -> Result<Proxy, Error> {
+        Proxy::spawn(addr, Arc::clone(&self.session), serve).await
+    }
+}
+
+/// Parses a CONNECT request and relays it to the requested peer.
+async fn serve(session: Arc<Session>, mut socket: TcpStream) -> std::io::Result<()> {
+    let Some((head, rest)) = read_head(&mut socket).await? else {
+        return send_status(&mut socket, Status::HeaderFieldsTooLarge).await;
+    };
+
+    let (host, port) = match parse_request(&head) {
+        Ok(target) => target,
+        Err(status) => return send_status(&mut socket, status).await,
+    };
+
+    let Some(token_id) = session.roster.borrow().resolve(host) else {
+        debug!(host, "Unknown peer");
+        return send_status(&mut socket, Status::BadGateway).await;
+    };
+
+    match session.open_stream(token_id, port).await {
+        Ok(mut stream) => {
+            send_status(&mut socket, Status::ConnectionEstablished).await?;
+            // Data the client sent right after the head.
+            stream.write_all(&rest).await?;
+            pump(stream, socket).await;
+            Ok(())
+        }
+        Err(error) => {
+            debug!(host, port, "Could not open stream: {error}");
+            send_status(&mut socket, Status::from(&error)).await
+        }
+    }
+}
+
+/// Reads the request head. Returns the head and the bytes received after
+/// it, or `None` if the head exceeds [`MAX_HEAD_SIZE`].
+async fn read_head(socket: &mut TcpStream) -> std::io::Result<Option<(String, Vec<u8>)>> {
+    let mut buf = Vec::new();
+
+    loop {
+        if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            let rest = buf.split_off(end + 4);
+            let head = String::from_utf8(buf)
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidData))?;
+            return Ok(Some((head, rest)));
+        }
+        if buf.len() >= MAX_HEAD_SIZE {
+            return Ok(None);
+        }
+
+        let mut chunk = [0; 1024];
+        let n = socket.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// Parses the request line into the target host and port. Header fields are
+/// ignored.
+fn parse_request(head: &str) -> Result<(&str, u16), Status> {
+    let request_line = head.lines().next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(Status::BadRequest);
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(Status::BadRequest);
+    }
+    if method != "CONNECT" {
+        return Err(Status::MethodNotAllowed);
+    }
+
+    let (host, port) = target.rsplit_once(':').ok_or(Status::BadRequest)?;
+    let port = port.parse().map_err(|_| Status::BadRequest)?;
+
+    Ok((host, port))
+}
+
+async fn send_status(socket: &mut TcpStream, status: Status) -> std::io::Result<()> {
+    let response = match status {
+        Status::ConnectionEstablished => format!("HTTP/1.1 {}\r\n\r\n", status.line()),
+        Status::MethodNotAllowed => format!(
+            "HTTP/1.1 {}\r\nAllow: CONNECT\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            status.line()
+        ),
+        _ => format!(
+            "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            status.line()
+        ),
+    };
+
+    socket.write_all(response.as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Status, parse_request};
+
+    #[test]
+    fn request_line() {
+        assert_eq!(
+            parse_request("CONNECT license-srv-01:27000 HTTP/1.1\r\nHost: x\r\n\r\n"),
+            Ok(("license-srv-01", 27000))
+        );
+        assert_eq!(
+            parse_request("CONNECT 42.dehset:22 HTTP/1.0\r\n\r\n"),
+            Ok(("42.dehset", 22))
+        );
+        assert_eq!(
+            parse_request("GET http://peer/ HTTP/1.1\r\n\r\n"),
+            Err(Status::MethodNotAllowed)
+        );
+        assert_eq!(
+            parse_request("CONNECT peer HTTP/1.1\r\n\r\n"),
+            Err(Status::BadRequest)
+        );
+        assert_eq!(
+            parse_request("CONNECT peer:http HTTP/1.1\r\n\r\n"),
+            Err(Status::BadRequest)
+        );
+        assert_eq!(parse_request("\r\n\r\n"), Err(Status::BadRequest));
+    }
+}
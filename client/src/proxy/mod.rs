@@ -0,0 +1,65 @@
+//! Local proxies opening relayed streams to the peers named by their
+//! clients, instead of one [`Forward`](crate::Forward) per target.
+//!
+//! Peers are addressed by name or token ID, optionally suffixed with
+//! [`PEER_DOMAIN`](crate::PEER_DOMAIN), and resolved against the clients
+//! announced by the server.
+
+mod http;
+mod socks;
+
+use crate::{Error, session::Session};
+use std::{future::Future, net::SocketAddr, sync::Arc};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tracing::debug;
+
+/// Local proxy listener. The listener is closed when the value is dropped.
+#[must_use]
+pub struct Proxy {
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl Proxy {
+    /// Binds `addr` and serves each accepted connection with `serve`.
+    async fn spawn<F, Fut>(
+        addr: SocketAddr,
+        session: Arc<Session>,
+        serve: F,
+    ) -> Result<Proxy, Error>
+    where
+        F: Fn(Arc<Session>, TcpStream) -> Fut + Send + 'static,
+        Fut: Future<Output = std::io::Result<()>> + Send + 'static,
+    {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+
+        let task = tokio::spawn(async move {
+            while let Ok((socket, remote_addr)) = listener.accept().await {
+                let connection = serve(Arc::clone(&session), socket);
+
+                tokio::spawn(async move {
+                    if let Err(io_error) = connection.await {
+                        debug!(%remote_addr, "Proxy connection failed: {io_error}");
+                    }
+                });
+            }
+        });
+
+        Ok(Proxy { local_addr, task })
+    }
+
+    /// Returns the local address the listener is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
@@ -0,0 +1,145 @@
+//! SOCKS5 front-end, see RFC 1928. Only the CONNECT command without
+//! authentication is supported, to domain names of peers such as
+//! `license-srv-01.dehset`.
+
+use super::Proxy;
+use crate::{Client, Error, forward::pump, session::Session};
+use proto_core::sub_protocol::application_data::TerminateReason;
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tracing::{debug, instrument};
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// Reply codes, RFC 1928 section 6.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Reply {
+    Succeeded = 0,
+    GeneralFailure = 1,
+    NotAllowed = 2,
+    HostUnreachable = 4,
+    ConnectionRefused = 5,
+    CommandNotSupported = 7,
+    AddressTypeNotSupported = 8,
+}
+
+impl From<&Error> for Reply {
+    fn from(error: &Error) -> Self {
+        match error {
+            Error::ConnectionRefused => Reply::ConnectionRefused,
+            Error::ConnectionTerminated { reason, .. } => match reason {
+                TerminateReason::PermissionDenied => Reply::NotAllowed,
+                TerminateReason::RemoteRefused | TerminateReason::PortNotShared => {
+                    Reply::ConnectionRefused
+                }
+                TerminateReason::PeerDisconnected => Reply::HostUnreachable,
+                _ => Reply::GeneralFailure,
+            },
+            _ => Reply::GeneralFailure,
+        }
+    }
+}
+
+impl Client {
+    /// Serves a SOCKS5 proxy on `addr`, opening a stream for each CONNECT to
+    /// `<peer>.dehset:<port>`, where the peer is a name or a token ID.
+    #[instrument(skip(self))]
+    pub async fn socks5(&self, addr: SocketAddr) -> Result<Proxy, Error> {
+        Proxy::spawn(addr, Arc::clone(&self.session), serve).await
+    }
+}
+
+/// Negotiates a SOCKS5 connection and relays it to the requested peer.
+async fn serve(session: Arc<Session>, mut socket: TcpStream) -> std::io::Result<()> {
+    let (host, port) = match read_request(&mut socket).await? {
+        Ok(target) => target,
+        Err(reply) => return send_reply(&mut socket, reply).await,
+    };
+
+    let Some(token_id) = session.roster.borrow().resolve(&host) else {
+        debug!(host, "Unknown peer");
+        return send_reply(&mut socket, Reply::HostUnreachable).await;
+    };
+
+    match session.open_stream(token_id, port).await {
+        Ok(stream) => {
+            send_reply(&mut socket, Reply::Succeeded).await?;
+            pump(stream, socket).await;
+            Ok(())
+        }
+        Err(error) => {
+            debug!(host, port, "Could not open stream: {error}");
+            send_reply(&mut socket, Reply::from(&error)).await
+        }
+    }
+}
+
+/// Reads the method selection and the request. Returns the requested host
+/// and port, or the reply refusing the request.
+async fn read_request(socket: &mut TcpStream) -> std::io::Result<Result<(String, u16), Reply>> {
+    let [version, methods] = read_array(socket).await?;
+    let mut offered = vec![0; methods as usize];
+    socket.read_exact(&mut offered).await?;
+
+    if version != VERSION || !offered.contains(&NO_AUTHENTICATION) {
+        socket.write_all(&[VERSION, NO_ACCEPTABLE_METHOD]).await?;
+        return Err(std::io::ErrorKind::Unsupported.into());
+    }
+    socket.write_all(&[VERSION, NO_AUTHENTICATION]).await?;
+
+    let [version, command, _, address_type] = read_array(socket).await?;
+    if version != VERSION {
+        return Err(std::io::ErrorKind::InvalidData.into());
+    }
+
+    // The address is read even if the request is refused, so the reply is
+    // not mistaken for a response to a truncated request.
+    let host = match address_type {
+        ATYP_DOMAIN => {
+            let [len] = read_array(socket).await?;
+            let mut host = vec![0; len as usize];
+            socket.read_exact(&mut host).await?;
+            String::from_utf8(host).ok()
+        }
+        ATYP_IPV4 => {
+            read_array::<4>(socket).await?;
+            None
+        }
+        ATYP_IPV6 => {
+            read_array::<16>(socket).await?;
+            None
+        }
+        _ => return Ok(Err(Reply::AddressTypeNotSupported)),
+    };
+    let port = u16::from_be_bytes(read_array(socket).await?);
+
+    Ok(match (command, host) {
+        (CONNECT, Some(host)) => Ok((host, port)),
+        (CONNECT, None) => Err(Reply::AddressTypeNotSupported),
+        _ => Err(Reply::CommandNotSupported),
+    })
+}
+
+async fn read_array<const N: usize>(socket: &mut TcpStream) -> std::io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    socket.read_exact(&mut buf).await?;
+
+    Ok(buf)
+}
+
+/// Sends a reply with an unspecified bound address.
+async fn send_reply(socket: &mut TcpStream, reply: Reply) -> std::io::Result<()> {
+    socket
+        .write_all(&[VERSION, reply as u8, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await
+}
@@ -0,0 +1,127 @@
+//! Reconnection of a client whose connection to the server was lost.
+//!
+//! While the client reconnects, [`Forward`](crate::Forward) listeners stay
+//! bound and new connections wait for the tunnel to come back, so
+//! applications see a stall instead of a refused connection. Streams of the
+//! lost connection are terminated; applications can resume them once
+//! [`ConnectionEvent::Reconnected`] is received.
+
+use crate::Error;
+use proto_core::sub_protocol::{alert::Alert, cmd_response::Authenticate};
+use std::time::Duration;
+
+/// Backoff between reconnection attempts.
+///
+/// The `n`-th consecutive attempt waits a random delay between half and all
+/// of `initial_backoff * 2^(n - 1)`, capped at `max_backoff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Number of consecutive failed attempts after which the client gives up.
+    /// `None` retries forever, `Some(0)` never reconnects.
+    pub max_attempts: Option<u32>,
+    /// Delay before the first attempt.
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between two attempts.
+    pub max_backoff: Duration,
+}
+
+impl ReconnectPolicy {
+    /// Policy that closes the client as soon as its connection is lost.
+    pub const NEVER: ReconnectPolicy = ReconnectPolicy {
+        max_attempts: Some(0),
+        initial_backoff: Duration::from_millis(500),
+        max_backoff: Duration::from_secs(30),
+    };
+
+    /// Policy that reconnects forever, suited for always-on nodes.
+    pub const ALWAYS: ReconnectPolicy = ReconnectPolicy {
+        max_attempts: None,
+        ..ReconnectPolicy::NEVER
+    };
+
+    /// Jittered delay before the `attempt`-th consecutive attempt, starting
+    /// at 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let base = self
+            .initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+
+        base.mul_f64(rand::random_range(0.5..=1.0))
+    }
+
+    /// Whether another attempt is allowed after `attempts` failed ones.
+    pub(crate) fn allows(&self, attempts: u32) -> bool {
+        self.max_attempts.is_none_or(|max| attempts < max)
+    }
+}
+
+impl Default for ReconnectPolicy {
+    /// [`ReconnectPolicy::NEVER`]. Always-on nodes should opt into
+    /// [`ReconnectPolicy::ALWAYS`].
+    fn default() -> Self {
+        ReconnectPolicy::NEVER
+    }
+}
+
+/// Changes of the connection to the server, see [`Client::events`](crate::Client::events).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The connection was lost and its streams were terminated.
+    Disconnected { reason: String },
+    /// A reconnection attempt starts after `delay`.
+    Reconnecting { attempt: u32, delay: Duration },
+    /// The handshake and authentication succeeded again. Shared ports accept
+    /// connections and streams can be reopened.
+    Reconnected,
+    /// The client is closed for good, either shut down or out of attempts.
+    Closed,
+}
+
+/// Whether the connection may be restored after `error`. Rejected tokens and
+/// closure alerts other than a server shutdown end the client.
+///
+/// A token reported as already connected is retried, as the server may not
+/// have noticed yet that the previous connection is dead.
+pub(crate) fn is_recoverable(error: &Error) -> bool {
+    match error {
+        Error::Authentication(response) => matches!(response, Authenticate::AlreadyConnected),
+        Error::Crypto(_) => false,
+        Error::Alert(alert) => matches!(alert, Alert::ServerShutdown),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReconnectPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff() {
+        let policy = ReconnectPolicy {
+            max_attempts: Some(3),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+        };
+
+        for (attempt, base) in [
+            (1, 100),
+            (2, 200),
+            (3, 400),
+            (4, 800),
+            (5, 1000),
+            (64, 1000),
+        ] {
+            let delay = policy.backoff(attempt);
+            let base = Duration::from_millis(base);
+            assert!(delay >= base / 2 && delay <= base, "{attempt}: {delay:?}");
+        }
+
+        assert!(policy.allows(2));
+        assert!(!policy.allows(3));
+        assert!(!ReconnectPolicy::NEVER.allows(0));
+        assert!(ReconnectPolicy::ALWAYS.allows(u32::MAX));
+    }
+}
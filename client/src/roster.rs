@@ -0,0 +1,246 @@
+//! Live list of the other clients connected to the server, maintained from
+//! the server's events.
+
+use proto_core::sub_protocol::event::{self, Event};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::debug;
+
+/// Domain under which peers are addressed by the proxies, e.g.
+/// `license-srv-01.dehset` or `42.dehset`.
+pub const PEER_DOMAIN: &str = "dehset";
+
+/// Another client connected to the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub token_id: u64,
+    /// Name of the peer's token.
+    pub name: String,
+    /// Tags of the peer's token.
+    pub tags: Vec<String>,
+    /// When the server accepted the peer's connection.
+    pub connected_at: SystemTime,
+}
+
+impl From<event::Client> for Peer {
+    fn from(client: event::Client) -> Self {
+        Peer {
+            token_id: client.token_id,
+            name: client.name,
+            tags: client.tags,
+            connected_at: UNIX_EPOCH + Duration::from_secs(client.timestamp),
+        }
+    }
+}
+
+/// Snapshot of the peers connected to the server, see
+/// [`Client::roster`](crate::Client::roster).
+///
+/// The roster is replaced by the list the server sends after each
+/// (re)connection, and kept up to date by its connection events.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Roster {
+    peers: BTreeMap<u64, Peer>,
+    /// Whether the server's list was received.
+    synced: bool,
+}
+
+impl Roster {
+    /// Applies an event of the server.
+    pub(crate) fn apply(&mut self, event: Event) {
+        match event {
+            Event::ListClients(clients) => {
+                self.peers = clients
+                    .into_iter()
+                    .map(|client| (client.token_id, Peer::from(client)))
+                    .collect();
+                self.synced = true;
+            }
+            Event::ClientConnected(client) => {
+                self.peers.insert(client.token_id, Peer::from(client));
+            }
+            Event::ClientDisconnected { token_id, .. } => {
+                self.peers.remove(&token_id);
+            }
+        }
+    }
+
+    /// Returns the peer with `token_id`.
+    pub fn get(&self, token_id: u64) -> Option<&Peer> {
+        self.peers.get(&token_id)
+    }
+
+    /// Returns the peers named `name`. Names are not unique.
+    pub fn by_name<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Peer> {
+        self.peers.values().filter(move |peer| peer.name == name)
+    }
+
+    /// Returns the peers tagged with `tag`.
+    pub fn by_tag<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a Peer> {
+        self.peers
+            .values()
+            .filter(move |peer| peer.tags.iter().any(|peer_tag| peer_tag == tag))
+    }
+
+    /// Returns the peers ordered by token ID.
+    pub fn iter(&self) -> impl Iterator<Item = &Peer> {
+        self.peers.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Whether the roster holds the list the server sends after connecting.
+    /// Until then, it is empty regardless of the connected peers.
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// Resolves a peer by name or token ID, optionally suffixed with
+    /// [`PEER_DOMAIN`]. Names shared by several peers are not resolved, and
+    /// take precedence over token IDs.
+    ///
+    /// A token ID resolves even if the peer is not in the roster, the server
+    /// refuses streams to disconnected peers.
+    pub fn resolve(&self, host: &str) -> Option<u64> {
+        let host = host
+            .strip_suffix(PEER_DOMAIN)
+            .and_then(|host| host.strip_suffix('.'))
+            .unwrap_or(host);
+
+        let mut named = self.by_name(host);
+        match (named.next(), named.next()) {
+            (Some(peer), None) => return Some(peer.token_id),
+            (Some(_), Some(_)) => {
+                debug!(host, "Ambiguous peer name");
+                return None;
+            }
+            (None, _) => {}
+        }
+
+        host.parse().ok()
+    }
+}
+
+impl fmt::Display for Roster {
+    /// Lists the peers ordered by token ID, one per line, with their name,
+    /// tags and the time since they connected.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let now = SystemTime::now();
+
+        for peer in self.iter() {
+            let connected_for = now.duration_since(peer.connected_at).unwrap_or_default();
+            writeln!(
+                f,
+                "{:>6}  {:<24} [{}]  connected {}s ago",
+                peer.token_id,
+                peer.name,
+                peer.tags.join(", "),
+                connected_for.as_secs()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Roster;
+    use proto_core::sub_protocol::event::{Client, Event};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn client(name: &str, token_id: u64, tags: &[&str]) -> Client {
+        Client {
+            name: String::from(name),
+            tags: tags.iter().map(|tag| String::from(*tag)).collect(),
+            token_id,
+            timestamp: 1000 + token_id,
+        }
+    }
+
+    #[test]
+    fn lookups() {
+        let mut roster = Roster::default();
+        roster.apply(Event::ListClients(vec![
+            client("license-srv-01", 1, &["license"]),
+            client("twin", 2, &["build", "linux"]),
+            client("twin", 3, &["build"]),
+        ]));
+        roster.apply(Event::ClientConnected(client("7", 4, &[])));
+
+        assert_eq!(roster.len(), 4);
+        assert_eq!(
+            roster.get(1).unwrap().connected_at,
+            UNIX_EPOCH + Duration::from_secs(1001)
+        );
+        assert_eq!(roster.by_name("twin").count(), 2);
+        let tagged: Vec<_> = roster.by_tag("build").map(|peer| peer.token_id).collect();
+        assert_eq!(tagged, [2, 3]);
+
+        roster.apply(Event::ClientDisconnected {
+            token_id: 2,
+            timestamp: 0,
+        });
+        assert!(roster.get(2).is_none());
+        assert_eq!(roster.by_tag("linux").count(), 0);
+
+        // A new list replaces the roster.
+        roster.apply(Event::ListClients(vec![client("7", 4, &[])]));
+        assert_eq!(
+            roster.iter().map(|peer| peer.token_id).collect::<Vec<_>>(),
+            [4]
+        );
+    }
+
+    #[test]
+    fn display() {
+        let mut roster = Roster::default();
+        assert!(!roster.is_synced());
+        roster.apply(Event::ListClients(vec![
+            client("twin", 3, &["build"]),
+            client("license-srv-01", 1, &["license", "linux"]),
+        ]));
+        assert!(roster.is_synced());
+
+        let lines: Vec<_> = roster
+            .to_string()
+            .lines()
+            .map(|line| line.split(" connected").next().unwrap().to_owned())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "     1  license-srv-01           [license, linux] ",
+                "     3  twin                     [build] ",
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve() {
+        let mut roster = Roster::default();
+        roster.apply(Event::ListClients(vec![
+            client("license-srv-01", 1, &[]),
+            client("twin", 2, &[]),
+            client("twin", 3, &[]),
+        ]));
+        roster.apply(Event::ClientConnected(client("7", 4, &[])));
+
+        assert_eq!(roster.resolve("license-srv-01"), Some(1));
+        assert_eq!(roster.resolve("license-srv-01.dehset"), Some(1));
+        assert_eq!(roster.resolve("twin.dehset"), None);
+        assert_eq!(roster.resolve("3.dehset"), Some(3));
+        // Names take precedence over token IDs.
+        assert_eq!(roster.resolve("7"), Some(4));
+        assert_eq!(roster.resolve("unknown.dehset"), None);
+    }
+}
@@ -0,0 +1,685 @@
+//! Dispatching of messages received from the server to streams and flows.
+
+use crate::{
+    Error,
+    datagram::{Flow, FlowPolicy, FlowState, relay_shared},
+    forward::pump,
+    reconnect::ConnectionEvent,
+    roster::Roster,
+    stream::{Stream, StreamEvent, StreamState},
+};
+use proto_core::{
+    common::{
+        FlowControlError, KeepaliveMonitor, KeepalivePolicy, MessageSender, Priority,
+        PriorityHints, PushStatus, QueueMetrics,
+    },
+    sub_protocol::{
+        Message,
+        application_data::{
+            ApplicationData, ApplicationDataEnum, SERVER_ASSIGNED_ID, TerminateReason,
+        },
+        keepalive::Keepalive,
+    },
+    tunnel::TunnelError,
+};
+use std::{
+    collections::HashMap,
+    ops::RangeInclusive,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use tokio::{
+    net::TcpStream,
+    sync::{
+        broadcast,
+        mpsc::{self, error::TrySendError},
+        watch,
+    },
+};
+use tracing::{debug, trace, warn};
+
+/// Capacity of the connection event channel. Lagging subscribers miss the
+/// oldest events.
+const EVENT_CAPACITY: usize = 16;
+
+/// Number of incoming streams waiting for [`Session::accept_stream`]. Further
+/// connections are refused.
+const ACCEPT_BACKLOG: usize = 64;
+
+/// State of the link between the session and the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Link {
+    /// Messages are queued to the current connection.
+    Up,
+    /// The connection was lost, and messages wait for a reconnection.
+    Down,
+    /// The client is closed, and messages are rejected.
+    Closed,
+}
+
+/// State shared between the client, its streams and its background task.
+///
+/// The session outlives the connections to the server, so forwarded
+/// listeners and shared ports survive reconnections.
+pub(crate) struct Session {
+    /// Sender of the message queue of the current connection.
+    sender: Mutex<MessageSender>,
+    link: watch::Sender<Link>,
+    events: broadcast::Sender<ConnectionEvent>,
+    streams: Mutex<HashMap<u64, Arc<StreamState>>>,
+    flows: Mutex<HashMap<u64, Arc<FlowState>>>,
+    next_connection_id: AtomicU64,
+    shared_ports: Vec<RangeInclusive<u16>>,
+    accepted_ports: Vec<u16>,
+    incoming: (
+        mpsc::Sender<Stream>,
+        tokio::sync::Mutex<mpsc::Receiver<Stream>>,
+    ),
+    priority_hints: PriorityHints,
+    flow_policy: FlowPolicy,
+    pub(crate) keepalive: KeepaliveMonitor,
+    pub(crate) roster: watch::Sender<Roster>,
+}
+
+impl Session {
+    pub(crate) fn new(
+        sender: MessageSender,
+        shared_ports: Vec<RangeInclusive<u16>>,
+        accepted_ports: Vec<u16>,
+        priority_hints: PriorityHints,
+        keepalive_policy: KeepalivePolicy,
+        flow_policy: FlowPolicy,
+    ) -> Session {
+        let (incoming, accept) = mpsc::channel(ACCEPT_BACKLOG);
+
+        Session {
+            keepalive: KeepaliveMonitor::new(keepalive_policy),
+            roster: watch::Sender::default(),
+            sender: Mutex::new(sender),
+            link: watch::Sender::new(Link::Up),
+            events: broadcast::Sender::new(EVENT_CAPACITY),
+            streams: Mutex::new(HashMap::new()),
+            flows: Mutex::new(HashMap::new()),
+            next_connection_id: AtomicU64::new(0),
+            shared_ports,
+            accepted_ports,
+            incoming: (incoming, tokio::sync::Mutex::new(accept)),
+            priority_hints,
+            flow_policy,
+        }
+    }
+
+    /// Encodes and queues a message. While the connection is down, waits for
+    /// a reconnection.
+    pub(crate) async fn send(&self, message: &Message, priority: Priority) -> Result<(), Error> {
+        let link = *self
+            .link
+            .subscribe()
+            .wait_for(|link| *link != Link::Down)
+            .await
+            .map_err(|_| TunnelError::Disconnected)?;
+        if link == Link::Closed {
+            return Err(TunnelError::Disconnected.into());
+        }
+
+        let sender = self.sender.lock().unwrap().clone();
+        let payload = message.encode()?;
+        let status = if message.is_droppable() {
+            sender.push_droppable(payload, priority).await?
+        } else {
+            sender.push_message(payload, priority).await?
+        };
+        if status != PushStatus::Queued {
+            trace!(?status, "Message queue overflow");
+        }
+
+        Ok(())
+    }
+
+    /// Returns a snapshot of the outgoing message queue metrics.
+    pub(crate) fn queue_metrics(&self) -> QueueMetrics {
+        self.sender.lock().unwrap().metrics()
+    }
+
+    /// Returns the state of the link to the server.
+    pub(crate) fn link(&self) -> Link {
+        *self.link.borrow()
+    }
+
+    /// Subscribes to the connection events.
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Publishes a connection event to the current subscribers.
+    pub(crate) fn emit(&self, event: ConnectionEvent) {
+        let _ = self.events.send(event);
+    }
+
+    /// Routes messages to the queue of a new connection and releases the
+    /// messages waiting for it.
+    pub(crate) fn attach(&self, sender: MessageSender) {
+        *self.sender.lock().unwrap() = sender;
+        self.keepalive.reset();
+        self.link.send_replace(Link::Up);
+    }
+
+    /// Marks the connection as lost and terminates its streams. New messages
+    /// wait for [`Session::attach`].
+    pub(crate) fn detach(&self) {
+        self.link.send_replace(Link::Down);
+        self.close(
+            TerminateReason::LocalError,
+            Some("connection to the server lost"),
+        );
+    }
+
+    /// Closes the session for good, rejecting new messages.
+    pub(crate) fn shut(&self) {
+        self.link.send_replace(Link::Closed);
+        self.close(TerminateReason::Shutdown, None);
+    }
+
+    /// Queues an application data payload of a connection.
+    pub(crate) async fn send_application_data(
+        &self,
+        connection_id: u64,
+        payload: ApplicationDataEnum,
+        priority: Priority,
+    ) -> Result<(), Error> {
+        self.send(
+            &Message::ApplicationData(ApplicationData {
+                connection_id,
+                payload,
+            }),
+            priority,
+        )
+        .await
+    }
+
+    /// Chooses the ID of a connection or flow requested by this client.
+    fn next_connection_id(&self) -> u64 {
+        self.next_connection_id.fetch_add(1, Ordering::Relaxed) & !SERVER_ASSIGNED_ID
+    }
+
+    /// Requests a connection to a port shared by another client.
+    pub(crate) async fn open_stream(
+        self: &Arc<Self>,
+        token_id: u64,
+        port: u16,
+    ) -> Result<Stream, Error> {
+        let connection_id = self.next_connection_id();
+        let priority = self.priority_hints.for_port(port);
+        let mut stream = self.register_stream(connection_id, port, priority);
+
+        self.send_application_data(
+            connection_id,
+            ApplicationDataEnum::RequestConnection { token_id, port },
+            priority,
+        )
+        .await?;
+
+        match stream.receiver.next_event().await {
+            Some(StreamEvent::Connection { accept: true }) => Ok(stream),
+            Some(StreamEvent::Connection { accept: false }) => {
+                self.remove_stream(connection_id);
+                Err(Error::ConnectionRefused)
+            }
+            Some(StreamEvent::Terminated(reason, detail)) => {
+                Err(Error::ConnectionTerminated { reason, detail })
+            }
+            Some(StreamEvent::Data(_) | StreamEvent::Eof) | None => {
+                self.remove_stream(connection_id);
+                Err(Error::ConnectionTerminated {
+                    reason: TerminateReason::ProtocolViolation,
+                    detail: Some(String::from("data before acceptance")),
+                })
+            }
+        }
+    }
+
+    /// Waits for the next stream connected to one of the accepted ports.
+    /// Fails once the session is closed.
+    pub(crate) async fn accept_stream(&self) -> Result<Stream, Error> {
+        let mut accept = self.incoming.1.lock().await;
+        let mut link = self.link.subscribe();
+
+        tokio::select! {
+            Some(stream) = accept.recv() => Ok(stream),
+            _ = link.wait_for(|link| *link == Link::Closed) => {
+                Err(TunnelError::Disconnected.into())
+            }
+        }
+    }
+
+    fn register_stream(
+        self: &Arc<Self>,
+        connection_id: u64,
+        port: u16,
+        priority: Priority,
+    ) -> Stream {
+        let (state, events) = StreamState::new(connection_id, port, priority);
+        let state = Arc::new(state);
+
+        self.streams
+            .lock()
+            .unwrap()
+            .insert(connection_id, Arc::clone(&state));
+
+        Stream::new(Arc::clone(self), state, events)
+    }
+
+    /// Removes a stream, closing its send window.
+    pub(crate) fn remove_stream(&self, connection_id: u64) -> Option<Arc<StreamState>> {
+        let state = self.streams.lock().unwrap().remove(&connection_id)?;
+        state.send_window.close();
+
+        Some(state)
+    }
+
+    pub(crate) fn stream(&self, connection_id: u64) -> Option<Arc<StreamState>> {
+        self.streams.lock().unwrap().get(&connection_id).cloned()
+    }
+
+    /// Requests a datagram flow to a port shared by another client.
+    /// Datagrams can be sent right away, the flow is terminated if the peer
+    /// refuses it.
+    pub(crate) async fn open_flow(
+        self: &Arc<Self>,
+        token_id: u64,
+        port: u16,
+    ) -> Result<Flow, Error> {
+        let connection_id = self.next_connection_id();
+        let priority = self.priority_hints.for_port(port);
+        let flow = self.register_flow(connection_id, priority);
+
+        self.send_application_data(
+            connection_id,
+            ApplicationDataEnum::RequestFlow { token_id, port },
+            priority,
+        )
+        .await?;
+
+        Ok(flow)
+    }
+
+    fn register_flow(self: &Arc<Self>, connection_id: u64, priority: Priority) -> Flow {
+        let (state, datagrams) = FlowState::new(connection_id, priority);
+        let state = Arc::new(state);
+
+        self.flows
+            .lock()
+            .unwrap()
+            .insert(connection_id, Arc::clone(&state));
+
+        Flow::new(
+            Arc::clone(self),
+            state,
+            datagrams,
+            self.flow_policy.idle_timeout,
+        )
+    }
+
+    /// Removes a flow, waking up its task.
+    pub(crate) fn remove_flow(&self, connection_id: u64) -> Option<Arc<FlowState>> {
+        let state = self.flows.lock().unwrap().remove(&connection_id)?;
+        state.close();
+
+        Some(state)
+    }
+
+    /// Handles a message received from the server.
+    pub(crate) async fn dispatch(self: &Arc<Self>, message: Message) -> Result<(), Error> {
+        match message {
+            Message::ApplicationData(ApplicationData {
+                connection_id,
+                payload,
+            }) => self.dispatch_application_data(connection_id, payload).await,
+            Message::Event(event) => {
+                trace!(?event, "Got event");
+                self.roster.send_modify(|roster| roster.apply(event));
+                Ok(())
+            }
+            Message::Alert(alert) => Err(Error::Alert(alert)),
+            Message::Keepalive(Keepalive::Ping { id }) => {
+                let pong = Message::Keepalive(Keepalive::Pong { id });
+                self.send(&pong, Priority::from(pong.content_type())).await
+            }
+            Message::Keepalive(Keepalive::Pong { id }) => {
+                self.keepalive.pong(id);
+                Ok(())
+            }
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    /// Pings the server at each keepalive interval until it misses too many
+    /// pongs.
+    pub(crate) async fn ping(&self) -> Result<(), Error> {
+        let Some(mut interval) = self.keepalive.interval() else {
+            return std::future::pending().await;
+        };
+
+        loop {
+            interval.tick().await;
+            let ping = Message::Keepalive(self.keepalive.ping()?);
+            self.send(&ping, Priority::from(ping.content_type()))
+                .await?;
+        }
+    }
+
+    async fn dispatch_application_data(
+        self: &Arc<Self>,
+        connection_id: u64,
+        payload: ApplicationDataEnum,
+    ) -> Result<(), Error> {
+        match payload {
+            ApplicationDataEnum::NewConnection { port } => {
+                if self.shared_ports.iter().any(|ports| ports.contains(&port)) {
+                    tokio::spawn(Arc::clone(self).accept_shared(connection_id, port));
+                } else if self.accepted_ports.contains(&port) {
+                    self.accept_incoming(connection_id, port).await?;
+                } else {
+                    debug!(connection_id, port, "Refused connection to unshared port");
+                    self.refuse(connection_id, port, TerminateReason::PortNotShared, None)
+                        .await?;
+                }
+            }
+            ApplicationDataEnum::Connection { accept } => {
+                let state = if accept {
+                    self.stream(connection_id)
+                } else {
+                    self.remove_stream(connection_id)
+                };
+
+                if let Some(state) = state {
+                    state.notify(StreamEvent::Connection { accept });
+                }
+            }
+            ApplicationDataEnum::Data { payload } => {
+                let Some(state) = self.stream(connection_id) else {
+                    return Ok(());
+                };
+
+                match state.recv_window.receive(payload.len()) {
+                    Ok(()) => state.notify(StreamEvent::Data(payload)),
+                    Err(flow_control_error) => {
+                        self.violation(&state, flow_control_error).await?;
+                    }
+                }
+            }
+            ApplicationDataEnum::WindowUpdate { increment } => {
+                if let Some(state) = self.stream(connection_id)
+                    && let Err(flow_control_error) = state.send_window.grant(increment)
+                {
+                    self.violation(&state, flow_control_error).await?;
+                }
+            }
+            ApplicationDataEnum::Eof => {
+                if let Some(state) = self.stream(connection_id) {
+                    state.eof_received.store(true, Ordering::SeqCst);
+                    if state.eof_sent.load(Ordering::SeqCst) {
+                        self.remove_stream(connection_id);
+                    }
+                    state.notify(StreamEvent::Eof);
+                }
+            }
+            ApplicationDataEnum::TerminateConnection { reason, detail } => {
+                if let Some(state) = self.remove_stream(connection_id) {
+                    debug!(connection_id, ?detail, "Stream terminated: {reason}");
+                    state.notify(StreamEvent::Terminated(reason, detail));
+                } else if self.remove_flow(connection_id).is_some() {
+                    debug!(connection_id, ?detail, "Flow terminated: {reason}");
+                }
+            }
+            ApplicationDataEnum::NewFlow { port } => {
+                if self.shared_ports.iter().any(|ports| ports.contains(&port)) {
+                    // The flow is registered before its first datagram is
+                    // dispatched.
+                    let flow =
+                        self.register_flow(connection_id, self.priority_hints.for_port(port));
+                    tokio::spawn(relay_shared(flow, port));
+                } else {
+                    debug!(connection_id, port, "Refused flow to unshared port");
+                    self.refuse(connection_id, port, TerminateReason::PortNotShared, None)
+                        .await?;
+                }
+            }
+            ApplicationDataEnum::Datagram { payload } => {
+                // Datagrams of terminated flows are dropped.
+                if let Some(state) = self.flows.lock().unwrap().get(&connection_id) {
+                    state.deliver(payload);
+                }
+            }
+            ApplicationDataEnum::RequestConnection { .. }
+            | ApplicationDataEnum::RequestFlow { .. } => {
+                return Err(Error::UnexpectedMessage);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Terminates a stream whose peer did not respect the flow control
+    /// windows.
+    async fn violation(
+        &self,
+        state: &StreamState,
+        flow_control_error: FlowControlError,
+    ) -> Result<(), Error> {
+        warn!(
+            state.connection_id,
+            "Flow control violation: {flow_control_error}"
+        );
+        let (reason, detail) = (
+            TerminateReason::FlowControlViolation,
+            Some(flow_control_error.to_string()),
+        );
+
+        self.remove_stream(state.connection_id);
+        state.notify(StreamEvent::Terminated(reason, detail.clone()));
+        self.send_application_data(
+            state.connection_id,
+            ApplicationDataEnum::TerminateConnection { reason, detail },
+            state.priority,
+        )
+        .await
+    }
+
+    /// Accepts a relayed connection to an accepted port, queuing its stream
+    /// for [`Session::accept_stream`]. The connection is refused when the
+    /// backlog is full.
+    async fn accept_incoming(self: &Arc<Self>, connection_id: u64, port: u16) -> Result<(), Error> {
+        let priority = self.priority_hints.for_port(port);
+        let permit = match self.incoming.0.try_reserve() {
+            Ok(permit) => permit,
+            Err(TrySendError::Full(()) | TrySendError::Closed(())) => {
+                debug!(connection_id, port, "Refused connection, backlog full");
+                return self
+                    .refuse(
+                        connection_id,
+                        port,
+                        TerminateReason::RemoteRefused,
+                        Some(String::from("accept backlog full")),
+                    )
+                    .await;
+            }
+        };
+
+        // The acceptance is queued before the stream is handed out, so it
+        // precedes any data written to the stream.
+        let stream = self.register_stream(connection_id, port, priority);
+        self.send_application_data(
+            connection_id,
+            ApplicationDataEnum::Connection { accept: true },
+            priority,
+        )
+        .await?;
+        permit.send(stream);
+
+        Ok(())
+    }
+
+    /// Refuses a relayed connection to `port` with a reason.
+    async fn refuse(
+        &self,
+        connection_id: u64,
+        port: u16,
+        reason: TerminateReason,
+        detail: Option<String>,
+    ) -> Result<(), Error> {
+        self.send_application_data(
+            connection_id,
+            ApplicationDataEnum::TerminateConnection { reason, detail },
+            self.priority_hints.for_port(port),
+        )
+        .await
+    }
+
+    /// Connects a relayed connection to a locally shared port.
+    async fn accept_shared(self: Arc<Self>, connection_id: u64, port: u16) {
+        let priority = self.priority_hints.for_port(port);
+        let socket = match TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(socket) => socket,
+            Err(io_error) => {
+                debug!(
+                    connection_id,
+                    port, "Could not connect to shared port: {io_error}"
+                );
+                let _ = self
+                    .refuse(
+                        connection_id,
+                        port,
+                        TerminateReason::RemoteRefused,
+                        Some(io_error.to_string()),
+                    )
+                    .await;
+                return;
+            }
+        };
+
+        // The stream is registered before accepting, so data sent right after
+        // the acceptance is not lost.
+        let stream = self.register_stream(connection_id, port, priority);
+        if self
+            .send_application_data(
+                connection_id,
+                ApplicationDataEnum::Connection { accept: true },
+                priority,
+            )
+            .await
+            .is_ok()
+        {
+            pump(stream, socket).await;
+        }
+    }
+
+    /// Terminates all streams and flows, and notifies their peers of the
+    /// shutdown.
+    pub(crate) async fn terminate_all(&self) {
+        let streams: Vec<_> = self.streams.lock().unwrap().drain().collect();
+        let flows: Vec<_> = self.flows.lock().unwrap().drain().collect();
+
+        for (connection_id, state) in flows {
+            state.close();
+
+            let _ = self
+                .send_application_data(
+                    connection_id,
+                    ApplicationDataEnum::TerminateConnection {
+                        reason: TerminateReason::Shutdown,
+                        detail: None,
+                    },
+                    state.priority,
+                )
+                .await;
+        }
+
+        for (connection_id, state) in streams {
+            state.send_window.close();
+            state.notify(StreamEvent::Terminated(TerminateReason::Shutdown, None));
+
+            let _ = self
+                .send_application_data(
+                    connection_id,
+                    ApplicationDataEnum::TerminateConnection {
+                        reason: TerminateReason::Shutdown,
+                        detail: None,
+                    },
+                    state.priority,
+                )
+                .await;
+        }
+    }
+
+    /// Terminates all streams and flows after the connection to the server
+    /// is lost, or the session is closed.
+    fn close(&self, reason: TerminateReason, detail: Option<&str>) {
+        for (_, state) in self.streams.lock().unwrap().drain() {
+            state.send_window.close();
+            state.notify(StreamEvent::Terminated(reason, detail.map(String::from)));
+        }
+
+        for (_, state) in self.flows.lock().unwrap().drain() {
+            state.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Session;
+    use crate::datagram::FlowPolicy;
+    use crypto::{symm::Aes128CbcSha256, tls::SymmTls};
+    use proto_core::{
+        common::{KeepalivePolicy, MessageQueue, Priority, PriorityHints},
+        sub_protocol::{
+            Message,
+            application_data::{ApplicationData, ApplicationDataEnum, SERVER_ASSIGNED_ID},
+        },
+        tunnel::Tunnel,
+    };
+    use std::{collections::HashMap, sync::Arc};
+    use tokio::io::{AsyncWriteExt, simplex};
+
+    const PORT: u16 = 9;
+
+    #[tokio::test]
+    async fn stream_payloads_share_one_level() {
+        let (r, w) = simplex(usize::MAX);
+        let encrypter = Arc::new(Aes128CbcSha256::try_new(&[0; 16]).unwrap());
+        let tls = SymmTls::new(([0; 32], [0; 32]), encrypter);
+        // The queue is never served, so its depth shows where each payload
+        // was queued.
+        let queue = MessageQueue::new(Arc::new(Tunnel::new(r, w, tls)));
+        let priority_hints = PriorityHints {
+            ports: HashMap::from([(PORT, Priority::Bulk)]),
+        };
+        let session = Arc::new(Session::new(
+            queue.sender(),
+            vec![],
+            vec![PORT],
+            priority_hints,
+            KeepalivePolicy::DISABLED,
+            FlowPolicy::default(),
+        ));
+
+        let new_connection = Message::ApplicationData(ApplicationData {
+            connection_id: SERVER_ASSIGNED_ID | 1,
+            payload: ApplicationDataEnum::NewConnection { port: PORT },
+        });
+        session.dispatch(new_connection).await.unwrap();
+        let mut stream = session.accept_stream().await.unwrap();
+
+        stream.write_all(&[7; 64 * 1024]).await.unwrap();
+        stream.flush().await.unwrap();
+        // The termination of a dropped stream is queued by a task.
+        drop(stream);
+        tokio::task::yield_now().await;
+
+        // Acceptance, four chunks and the termination, in order.
+        assert_eq!(queue.metrics().depth, [0, 0, 6]);
+    }
+}
@@ -0,0 +1,424 @@
+//! Relayed connections multiplexed over the tunnel.
+//!
+//! Each stream has its own flow control windows, so a stream whose receiver
+//! stalls only blocks its own sender, while the other streams of the client
+//! keep flowing.
+
+use crate::{Error, session::Session};
+use proto_core::{
+    common::{INITIAL_WINDOW_SIZE, MAX_DATA_CHUNK, Priority, RecvWindow, SendWindow},
+    sub_protocol::application_data::{ApplicationDataEnum, TerminateReason},
+};
+use std::{
+    future::{Future, poll_fn},
+    io,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll, ready},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+};
+
+/// Message queued on behalf of a stream, polled by its I/O methods.
+type SendFuture = Pin<Box<dyn Future<Output = Result<(), Error>> + Send>>;
+
+/// Events delivered by the session to a stream.
+#[derive(Debug)]
+pub(crate) enum StreamEvent {
+    /// The port-sharing client answered the connection request.
+    Connection { accept: bool },
+    /// Data received from the peer.
+    Data(Vec<u8>),
+    /// The peer sends no more data.
+    Eof,
+    /// The peer, the server or the client itself terminated the connection.
+    Terminated(TerminateReason, Option<String>),
+}
+
+/// State of a stream shared with the session.
+pub(crate) struct StreamState {
+    pub(crate) connection_id: u64,
+    /// Port of the sharing client the stream is connected to.
+    pub(crate) port: u16,
+    /// Class of all payloads of the stream, so they are never reordered.
+    pub(crate) priority: Priority,
+    pub(crate) send_window: SendWindow,
+    pub(crate) recv_window: RecvWindow,
+    /// Whether [`ApplicationDataEnum::Eof`] was sent to the peer.
+    pub(crate) eof_sent: AtomicBool,
+    /// Whether the peer sent [`ApplicationDataEnum::Eof`].
+    pub(crate) eof_received: AtomicBool,
+    events: UnboundedSender<StreamEvent>,
+}
+
+impl StreamState {
+    pub(crate) fn new(
+        connection_id: u64,
+        port: u16,
+        priority: Priority,
+    ) -> (StreamState, UnboundedReceiver<StreamEvent>) {
+        let (events, receiver) = unbounded_channel();
+
+        (
+            StreamState {
+                connection_id,
+                port,
+                priority,
+                send_window: SendWindow::new(INITIAL_WINDOW_SIZE),
+                recv_window: RecvWindow::new(INITIAL_WINDOW_SIZE),
+                eof_sent: AtomicBool::new(false),
+                eof_received: AtomicBool::new(false),
+                events,
+            },
+            receiver,
+        )
+    }
+
+    /// Delivers an event to the receiving half of the stream.
+    pub(crate) fn notify(&self, event: StreamEvent) {
+        // The receiving half may already be dropped.
+        let _ = self.events.send(event);
+    }
+}
+
+/// A relayed connection to a port of another client.
+///
+/// Streams are opened with [`Client::open_stream`](crate::Client::open_stream)
+/// and accepted with [`Client::accept_stream`](crate::Client::accept_stream).
+/// They implement [`AsyncRead`] and [`AsyncWrite`], so they can be used with
+/// [`tokio::io::copy_bidirectional`] or any protocol library.
+///
+/// [`AsyncWrite::poll_shutdown`] only closes the writing direction, like a TCP
+/// half-close: the stream keeps reading until the peer shuts down its own
+/// direction, and reads return EOF. Reads fail with
+/// [`io::ErrorKind::ConnectionReset`] if the peer, or the server, terminates
+/// the stream instead. Dropping the stream terminates it.
+#[must_use]
+pub struct Stream {
+    pub(crate) sender: StreamSender,
+    pub(crate) receiver: StreamReceiver,
+    /// Received data not yet read, and the offset of its unread part.
+    read_buf: (Vec<u8>, usize),
+    /// Window update to send before reading more data.
+    window_update: Option<SendFuture>,
+    /// Chunk accepted by the last write, until it is queued.
+    write: Option<SendFuture>,
+    /// Termination sent by the shutdown.
+    shutdown: Option<SendFuture>,
+}
+
+/// Sending half of a [`Stream`].
+#[derive(Clone)]
+pub(crate) struct StreamSender {
+    session: Arc<Session>,
+    state: Arc<StreamState>,
+}
+
+/// Receiving half of a [`Stream`]. Dropping it terminates the stream.
+pub(crate) struct StreamReceiver {
+    session: Arc<Session>,
+    state: Arc<StreamState>,
+    events: UnboundedReceiver<StreamEvent>,
+    /// Whether the end of the stream, or its termination, was received.
+    finished: bool,
+}
+
+impl Stream {
+    pub(crate) fn new(
+        session: Arc<Session>,
+        state: Arc<StreamState>,
+        events: UnboundedReceiver<StreamEvent>,
+    ) -> Stream {
+        Stream {
+            sender: StreamSender {
+                session: Arc::clone(&session),
+                state: Arc::clone(&state),
+            },
+            receiver: StreamReceiver {
+                session,
+                state,
+                events,
+                finished: false,
+            },
+            read_buf: (Vec::new(), 0),
+            window_update: None,
+            write: None,
+            shutdown: None,
+        }
+    }
+
+    /// Returns the port of the sharing client the stream is connected to.
+    pub fn port(&self) -> u16 {
+        self.sender.state.port
+    }
+
+    /// Splits the stream into its sending and receiving halves. Data
+    /// buffered by the [`AsyncRead`] implementation is lost.
+    pub(crate) fn split(self) -> (StreamSender, StreamReceiver) {
+        (self.sender, self.receiver)
+    }
+}
+
+impl StreamSender {
+    /// Sends data to the peer, waiting for window updates when the peer's
+    /// receive window is exhausted.
+    pub(crate) async fn send(&self, data: &[u8]) -> Result<(), Error> {
+        for chunk in data.chunks(MAX_DATA_CHUNK) {
+            self.state
+                .send_window
+                .acquire(chunk.len())
+                .await
+                .map_err(|_| Error::ConnectionTerminated {
+                    reason: TerminateReason::Closed,
+                    detail: None,
+                })?;
+
+            self.session
+                .send_application_data(
+                    self.state.connection_id,
+                    ApplicationDataEnum::Data {
+                        payload: chunk.to_vec(),
+                    },
+                    self.state.priority,
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Signals the peer that no more data is sent, while data is still
+    /// received. The stream is closed once the peer did the same.
+    pub(crate) async fn finish(&self) -> Result<(), Error> {
+        let connection_id = self.state.connection_id;
+
+        if self.session.stream(connection_id).is_none()
+            || self.state.eof_sent.swap(true, Ordering::SeqCst)
+        {
+            return Ok(());
+        }
+
+        self.session
+            .send_application_data(connection_id, ApplicationDataEnum::Eof, self.state.priority)
+            .await?;
+        if self.state.eof_received.load(Ordering::SeqCst) {
+            self.session.remove_stream(connection_id);
+        }
+
+        Ok(())
+    }
+
+    /// Terminates the stream, ending the receiving half as well. Streams that
+    /// are already terminated are left untouched.
+    pub(crate) async fn terminate(
+        &self,
+        reason: TerminateReason,
+        detail: Option<String>,
+    ) -> Result<(), Error> {
+        let connection_id = self.state.connection_id;
+
+        if let Some(state) = self.session.remove_stream(connection_id) {
+            state.notify(StreamEvent::Terminated(reason, detail.clone()));
+            self.session
+                .send_application_data(
+                    connection_id,
+                    ApplicationDataEnum::TerminateConnection { reason, detail },
+                    self.state.priority,
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StreamReceiver {
+    /// Receives the next raw event of the stream.
+    pub(crate) async fn next_event(&mut self) -> Option<StreamEvent> {
+        self.events.recv().await
+    }
+
+    /// Receives data from the peer. Returns `None` once the peer sent all
+    /// its data, and fails if the stream is terminated.
+    ///
+    /// Received data is released from the receive window, granting the peer
+    /// more credit.
+    pub(crate) async fn recv(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let Some(data) = poll_fn(|cx| self.poll_data(cx)).await? else {
+            return Ok(None);
+        };
+        if let Some(window_update) = self.release(data.len()) {
+            window_update.await?;
+        }
+
+        Ok(Some(data))
+    }
+
+    /// Polls the next data of the peer, without releasing it from the
+    /// receive window. Returns `None` once the peer sent all its data, and
+    /// fails if the stream is terminated. Once finished, the stream keeps
+    /// returning `None`.
+    fn poll_data(&mut self, cx: &mut Context<'_>) -> Poll<Result<Option<Vec<u8>>, Error>> {
+        while !self.finished {
+            match ready!(self.events.poll_recv(cx)) {
+                Some(StreamEvent::Data(data)) => return Poll::Ready(Ok(Some(data))),
+                Some(StreamEvent::Connection { .. }) => {}
+                Some(StreamEvent::Eof) => self.finished = true,
+                Some(StreamEvent::Terminated(reason, detail)) => {
+                    self.finished = true;
+                    return Poll::Ready(Err(Error::ConnectionTerminated { reason, detail }));
+                }
+                None => {
+                    self.finished = true;
+                    return Poll::Ready(Err(Error::ConnectionTerminated {
+                        reason: TerminateReason::Closed,
+                        detail: None,
+                    }));
+                }
+            }
+        }
+
+        Poll::Ready(Ok(None))
+    }
+
+    /// Releases `len` consumed bytes from the receive window, returning the
+    /// window update to send, if any. No more credit is granted once the
+    /// peer sent all its data.
+    fn release(&self, len: usize) -> Option<SendFuture> {
+        let increment = self.state.recv_window.release(len)?;
+        if self.state.eof_received.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        let session = Arc::clone(&self.session);
+        let connection_id = self.state.connection_id;
+        let priority = self.state.priority;
+
+        Some(Box::pin(async move {
+            session
+                .send_application_data(
+                    connection_id,
+                    ApplicationDataEnum::WindowUpdate { increment },
+                    priority,
+                )
+                .await
+        }))
+    }
+}
+
+/// Drives a pending message to completion, clearing it once done.
+fn poll_pending(pending: &mut Option<SendFuture>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    if let Some(future) = pending {
+        let result = ready!(future.as_mut().poll(cx));
+        *pending = None;
+        result?;
+    }
+
+    Poll::Ready(Ok(()))
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+
+        while this.read_buf.1 == this.read_buf.0.len() {
+            ready!(poll_pending(&mut this.window_update, cx))?;
+
+            let Some(data) = ready!(this.receiver.poll_data(cx))? else {
+                return Poll::Ready(Ok(()));
+            };
+            this.window_update = this.receiver.release(data.len());
+            this.read_buf = (data, 0);
+        }
+
+        let (data, offset) = &mut this.read_buf;
+        let n = buf.remaining().min(data.len() - *offset);
+        buf.put_slice(&data[*offset..*offset + n]);
+        *offset += n;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Stream {
+    /// Accepts up to [`MAX_DATA_CHUNK`] bytes once the previous chunk is
+    /// queued. The chunk waits for the send window of the peer in the
+    /// background, see [`AsyncWrite::poll_flush`].
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(poll_pending(&mut self.write, cx))?;
+        if self.shutdown.is_some() {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+
+        let chunk = buf[..buf.len().min(MAX_DATA_CHUNK)].to_vec();
+        let sender = self.sender.clone();
+        let mut write: SendFuture = Box::pin(async move { sender.send(&chunk).await });
+
+        if let Poll::Ready(result) = write.as_mut().poll(cx) {
+            result?;
+        } else {
+            self.write = Some(write);
+        }
+
+        Poll::Ready(Ok(buf.len().min(MAX_DATA_CHUNK)))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        poll_pending(&mut self.write, cx)
+    }
+
+    /// Flushes the pending chunk and signals the end of the data to the
+    /// peer. Reading continues until the peer does the same.
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        ready!(poll_pending(&mut this.write, cx))?;
+
+        let shutdown = this.shutdown.get_or_insert_with(|| {
+            let sender = this.sender.clone();
+            Box::pin(async move { sender.finish().await })
+        });
+        // Later calls complete immediately once the termination is queued.
+        let result = ready!(shutdown.as_mut().poll(cx));
+        *shutdown = Box::pin(std::future::ready(Ok(())));
+
+        Poll::Ready(Ok(result?))
+    }
+}
+
+impl Drop for StreamReceiver {
+    fn drop(&mut self) {
+        let connection_id = self.state.connection_id;
+        let priority = self.state.priority;
+
+        if self.session.remove_stream(connection_id).is_some()
+            && let Ok(handle) = tokio::runtime::Handle::try_current()
+        {
+            let session = Arc::clone(&self.session);
+            handle.spawn(async move {
+                let _ = session
+                    .send_application_data(
+                        connection_id,
+                        ApplicationDataEnum::TerminateConnection {
+                            reason: TerminateReason::Closed,
+                            detail: None,
+                        },
+                        priority,
+                    )
+                    .await;
+            });
+        }
+    }
+}
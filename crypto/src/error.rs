@@ -1,3 +1,4 @@
+use crate::key::KeyError;
 use openssl::error::{Error as OpenSslError, ErrorStack as OpenSslStackError};
 use proto_core::token::TokenError;
 
@@ -7,7 +8,14 @@ pub enum CryptoError {
     OpenSslStack(OpenSslStackError),
     OpenSsl(OpenSslError),
     Token(TokenError),
+    Key(KeyError),
     InvalidShasum,
+    /// The ciphertext is shorter than a cipher block.
+    InvalidCiphertext,
+    /// The key is malformed or does not belong to the expected algorithm.
+    InvalidKey,
+    /// The token is signed with an algorithm other than the verifier's.
+    AlgorithmMismatch,
 }
 
 impl std::fmt::Display for CryptoError {
@@ -18,11 +26,15 @@ impl std::fmt::Display for CryptoError {
             }
             Self::OpenSsl(openssl_error) => write!(f, "openssl error: {openssl_error}"),
             Self::Token(token_error) => write!(f, "token error: {token_error}"),
+            Self::Key(key_error) => write!(f, "key error: {key_error}"),
             Self::InvalidShasum => write!(f, "could not verify shasum"),
+            Self::InvalidCiphertext => write!(f, "invalid ciphertext"),
+            Self::InvalidKey => write!(f, "invalid key"),
+            Self::AlgorithmMismatch => write!(f, "signature algorithm mismatch"),
         }
     }
 }
 
 impl std::error::Error for CryptoError {}
 
-proto_core::error_impl_from!(CryptoError; OpenSsl, OpenSslStack, Token);
+proto_core::error_impl_from!(CryptoError; OpenSsl, OpenSslStack, Token, Key);
@@ -0,0 +1,54 @@
+use base64::DecodeError as Base64Error;
+use hex::FromHexError as HexError;
+use std::{env::VarError as EnvError, io::Error as IoError, path::PathBuf};
+
+/// Key loading error types.
+#[derive(Debug)]
+pub enum KeyError {
+    Io(IoError),
+    Env(EnvError),
+    Hex(HexError),
+    Base64(Base64Error),
+    /// The key is not a PEM encoded document.
+    InvalidPem,
+    /// The key source specification could not be parsed.
+    InvalidSource(String),
+    /// The key length does not match the length required by the algorithm.
+    InvalidLength {
+        expected: usize,
+        got: usize,
+    },
+    /// The key is shorter than the minimum length required by the algorithm.
+    TooShort {
+        min: usize,
+        got: usize,
+    },
+    /// The key file can be read by any user on the system.
+    WorldReadable(PathBuf),
+}
+
+impl std::fmt::Display for KeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(io_error) => write!(f, "io: {io_error}"),
+            Self::Env(env_error) => write!(f, "env: {env_error}"),
+            Self::Hex(hex_error) => write!(f, "hex: {hex_error}"),
+            Self::Base64(base64_error) => write!(f, "base64: {base64_error}"),
+            Self::InvalidPem => write!(f, "invalid pem document"),
+            Self::InvalidSource(source) => write!(f, "invalid key source: {source}"),
+            Self::InvalidLength { expected, got } => {
+                write!(f, "expected a {expected} byte key, got {got} bytes")
+            }
+            Self::TooShort { min, got } => {
+                write!(f, "expected a key of at least {min} bytes, got {got} bytes")
+            }
+            Self::WorldReadable(path) => {
+                write!(f, "refusing world-readable key file {}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+proto_core::error_impl_from!(KeyError; Io, Env, Hex, Base64);
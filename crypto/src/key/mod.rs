@@ -0,0 +1,183 @@
+//! Key material loading from files and environment variables.
+//!
+//! Keys can be stored as hex, base64 or PEM encoded text. Decoded key bytes
+//! are held in [`KeyMaterial`], which wipes its buffer on drop. Lengths are
+//! validated by the algorithm constructors, e.g.
+//! [`Aes128CbcSha256::try_new`](crate::symm::Aes128CbcSha256::try_new).
+
+mod error;
+
+#[cfg(test)]
+mod tests;
+
+pub use error::KeyError;
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use zeroize::Zeroizing;
+
+/// Text encoding of a stored key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyFormat {
+    /// Hexadecimal encoded raw key bytes.
+    Hex,
+    /// Standard base64 encoded raw key bytes.
+    Base64,
+    /// PEM encoded document, used for asymmetric keys.
+    Pem,
+}
+
+/// Location of a stored key.
+///
+/// Parsed from `<format>:<path>` for files and `<format>-env:<name>` for
+/// environment variables, where `<format>` is one of `hex`, `base64` or `pem`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeySource {
+    File { path: PathBuf, format: KeyFormat },
+    Env { name: String, format: KeyFormat },
+}
+
+/// Secret key bytes, wiped from memory on drop.
+pub struct KeyMaterial {
+    bytes: Zeroizing<Vec<u8>>,
+}
+
+impl KeyFormat {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Hex => "hex",
+            Self::Base64 => "base64",
+            Self::Pem => "pem",
+        }
+    }
+}
+
+impl FromStr for KeySource {
+    type Err = KeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || KeyError::InvalidSource(String::from(s));
+        let (kind, location) = s.split_once(':').ok_or_else(invalid)?;
+
+        if location.is_empty() {
+            return Err(invalid());
+        }
+
+        for format in [KeyFormat::Hex, KeyFormat::Base64, KeyFormat::Pem] {
+            if kind == format.name() {
+                return Ok(Self::File {
+                    path: PathBuf::from(location),
+                    format,
+                });
+            }
+            if kind.strip_suffix("-env") == Some(format.name()) {
+                return Ok(Self::Env {
+                    name: String::from(location),
+                    format,
+                });
+            }
+        }
+
+        Err(invalid())
+    }
+}
+
+impl KeyMaterial {
+    /// Takes ownership of raw key bytes.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes: Zeroizing::new(bytes),
+        }
+    }
+
+    /// Decodes key text in the given format.
+    pub fn decode(encoded: &[u8], format: KeyFormat) -> Result<Self, KeyError> {
+        let encoded = encoded.trim_ascii();
+
+        let bytes = match format {
+            KeyFormat::Hex => hex::decode(encoded)?,
+            KeyFormat::Base64 => BASE64.decode(encoded)?,
+            KeyFormat::Pem => {
+                if !encoded.starts_with(b"-----BEGIN ")
+                    || !encoded.windows(9).any(|window| window == b"-----END ")
+                {
+                    return Err(KeyError::InvalidPem);
+                }
+                Vec::from(encoded)
+            }
+        };
+
+        Ok(Self::from_bytes(bytes))
+    }
+
+    /// Reads and decodes a key file. Files readable by any user are refused.
+    ///
+    /// The permissions are checked on the opened file, so the file cannot be
+    /// swapped between the check and the read.
+    pub fn from_file(path: impl AsRef<Path>, format: KeyFormat) -> Result<Self, KeyError> {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            if metadata.permissions().mode() & 0o004 != 0 {
+                return Err(KeyError::WorldReadable(PathBuf::from(path)));
+            }
+        }
+
+        // Sized up front, so the buffer is not reallocated and copies of the
+        // key are not left behind unzeroized.
+        let mut encoded = Zeroizing::new(Vec::with_capacity(
+            usize::try_from(metadata.len()).unwrap_or(0) + 1,
+        ));
+        file.read_to_end(&mut encoded)?;
+
+        Self::decode(&encoded, format)
+    }
+
+    /// Reads and decodes a key from an environment variable.
+    pub fn from_env(name: &str, format: KeyFormat) -> Result<Self, KeyError> {
+        let encoded = Zeroizing::new(std::env::var(name)?);
+
+        Self::decode(encoded.as_bytes(), format)
+    }
+
+    /// Loads a key from the given source.
+    pub fn load(source: &KeySource) -> Result<Self, KeyError> {
+        match source {
+            KeySource::File { path, format } => Self::from_file(path, *format),
+            KeySource::Env { name, format } => Self::from_env(name, *format),
+        }
+    }
+
+    /// Returns the key bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Length of the key in bytes.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Whether the key is empty.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl std::fmt::Debug for KeyMaterial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyMaterial")
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,167 @@
+use super::*;
+use crate::{
+    CryptoError,
+    sign::{Ed25519Signer, Hs256, TokenVerifier},
+    symm::Aes128CbcSha256,
+};
+use proto_core::{algorithms::SignatureAlgorithm, random_bytes};
+use testutil::DynResult;
+
+/// Writes `contents` into a fresh file in the temporary directory with the
+/// given permission bits.
+fn write_key_file(name: &str, contents: &[u8], mode: u32) -> DynResult<PathBuf> {
+    let path = std::env::temp_dir().join(format!(
+        "dehset-key-{name}-{}",
+        hex::encode(random_bytes!(8))
+    ));
+    std::fs::write(&path, contents)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    Ok(path)
+}
+
+#[test]
+fn decode_formats() -> DynResult<()> {
+    let key = random_bytes!(16);
+
+    let hex_key = KeyMaterial::decode(
+        format!(" {}\n", hex::encode(key)).as_bytes(),
+        KeyFormat::Hex,
+    )?;
+    assert_eq!(hex_key.as_bytes(), key);
+
+    let base64_key = KeyMaterial::decode(
+        format!("{}\n", BASE64.encode(key)).as_bytes(),
+        KeyFormat::Base64,
+    )?;
+    assert_eq!(base64_key.as_bytes(), key);
+
+    assert!(matches!(
+        KeyMaterial::decode(b"not hex", KeyFormat::Hex),
+        Err(KeyError::Hex(_))
+    ));
+    assert!(matches!(
+        KeyMaterial::decode(b"not pem", KeyFormat::Pem),
+        Err(KeyError::InvalidPem)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn parse_source() {
+    assert_eq!(
+        "hex:/etc/dehset/key".parse::<KeySource>().unwrap(),
+        KeySource::File {
+            path: PathBuf::from("/etc/dehset/key"),
+            format: KeyFormat::Hex
+        }
+    );
+    assert_eq!(
+        "base64-env:DEHSET_KEY".parse::<KeySource>().unwrap(),
+        KeySource::Env {
+            name: String::from("DEHSET_KEY"),
+            format: KeyFormat::Base64
+        }
+    );
+
+    assert!("hex".parse::<KeySource>().is_err());
+    assert!("hex:".parse::<KeySource>().is_err());
+    assert!("raw:/key".parse::<KeySource>().is_err());
+}
+
+#[test]
+fn load_file() -> DynResult<()> {
+    let key = random_bytes!(16);
+    let path = write_key_file("hex", hex::encode(key).as_bytes(), 0o600)?;
+
+    let loaded = KeyMaterial::load(&KeySource::File {
+        path: path.clone(),
+        format: KeyFormat::Hex,
+    })?;
+    assert_eq!(loaded.as_bytes(), key);
+
+    std::fs::remove_file(path)?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn refuse_world_readable() -> DynResult<()> {
+    let path = write_key_file("world", hex::encode(random_bytes!(16)).as_bytes(), 0o644)?;
+
+    let result = KeyMaterial::from_file(&path, KeyFormat::Hex);
+    std::fs::remove_file(path)?;
+
+    assert!(matches!(result, Err(KeyError::WorldReadable(_))));
+
+    Ok(())
+}
+
+#[test]
+fn load_env() -> DynResult<()> {
+    let key = random_bytes!(32);
+    let name = format!("DEHSET_TEST_KEY_{}", hex::encode(random_bytes!(4)));
+
+    // SAFETY: The variable name is unique to this test.
+    unsafe { std::env::set_var(&name, BASE64.encode(key)) };
+
+    assert_eq!(
+        KeyMaterial::from_env(&name, KeyFormat::Base64)?.as_bytes(),
+        key
+    );
+    assert!(matches!(
+        KeyMaterial::from_env("DEHSET_TEST_KEY_UNSET", KeyFormat::Base64),
+        Err(KeyError::Env(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn enforce_lengths() -> DynResult<()> {
+    assert!(Aes128CbcSha256::try_new(&random_bytes!(16)).is_ok());
+    assert!(matches!(
+        Aes128CbcSha256::try_new(&random_bytes!(15)),
+        Err(CryptoError::Key(KeyError::InvalidLength {
+            expected: 16,
+            got: 15
+        }))
+    ));
+
+    assert!(Hs256::try_new(&random_bytes!(32)).is_ok());
+    assert!(matches!(
+        Hs256::try_new(&random_bytes!(16)),
+        Err(CryptoError::Key(KeyError::TooShort { min: 32, got: 16 }))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn token_verifier_from_pem() -> DynResult<()> {
+    let signer = Ed25519Signer::generate()?;
+    let pem = KeyMaterial::decode(&signer.public_key_to_pem()?, KeyFormat::Pem)?;
+
+    let verifier = TokenVerifier::from_key(SignatureAlgorithm::Ed25519, &pem)?;
+    assert_eq!(verifier.algorithm(), SignatureAlgorithm::Ed25519);
+
+    assert!(TokenVerifier::from_key(SignatureAlgorithm::EcdsaP256Sha256, &pem).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn redacted_debug() {
+    let key = KeyMaterial::from_bytes(vec![0xab; 16]);
+
+    assert!(!format!("{key:?}").contains("171"));
+}
@@ -4,6 +4,7 @@
 use testutil::*;
 
 mod error;
+pub mod key;
 pub mod sign;
 pub mod symm;
 pub mod tls;
@@ -0,0 +1,120 @@
+use super::{SignatureAlgorithm as TSignatureAlgorithm, Signer as TSigner, Verifier as TVerifier};
+use crate::CryptoError;
+use openssl::{
+    ec::{EcGroup, EcKey},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{HasPublic, PKey, PKeyRef, Private, Public},
+    sign::{Signer, Verifier},
+};
+use proto_core::algorithms::SignatureAlgorithm;
+
+/// ECDSA over NIST P-256 with SHA-256 signer holding the private key. Used to
+/// issue tokens.
+#[derive(Debug)]
+pub struct EcdsaP256Sha256Signer {
+    key: PKey<Private>,
+}
+
+/// ECDSA over NIST P-256 with SHA-256 verifier holding only the public key.
+#[derive(Debug)]
+pub struct EcdsaP256Sha256Verifier {
+    key: PKey<Public>,
+}
+
+/// Checks whether the key lies on the P-256 curve.
+fn is_p256<T: HasPublic>(key: &PKeyRef<T>) -> bool {
+    key.ec_key()
+        .is_ok_and(|ec_key| ec_key.group().curve_name() == Some(Nid::X9_62_PRIME256V1))
+}
+
+impl EcdsaP256Sha256Signer {
+    /// Generates a new random P-256 private key.
+    pub fn generate() -> Result<Self, CryptoError> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+
+        Ok(Self {
+            key: PKey::from_ec_key(EcKey::generate(&group)?)?,
+        })
+    }
+
+    /// Loads a PEM encoded PKCS#8 or SEC1 private key.
+    pub fn from_pem(pem: &[u8]) -> Result<Self, CryptoError> {
+        let key = PKey::private_key_from_pem(pem)?;
+
+        if !is_p256(&key) {
+            return Err(CryptoError::InvalidKey);
+        }
+
+        Ok(Self { key })
+    }
+
+    /// Encodes the private key in PEM format.
+    pub fn private_key_to_pem(&self) -> Result<Vec<u8>, CryptoError> {
+        Ok(self.key.private_key_to_pem_pkcs8()?)
+    }
+
+    /// Encodes the public key in PEM format.
+    pub fn public_key_to_pem(&self) -> Result<Vec<u8>, CryptoError> {
+        Ok(self.key.public_key_to_pem()?)
+    }
+
+    /// Returns a verifier for the public half of the key.
+    pub fn verifier(&self) -> Result<EcdsaP256Sha256Verifier, CryptoError> {
+        EcdsaP256Sha256Verifier::from_pem(&self.public_key_to_pem()?)
+    }
+}
+
+impl EcdsaP256Sha256Verifier {
+    /// Loads a PEM encoded public key.
+    pub fn from_pem(pem: &[u8]) -> Result<Self, CryptoError> {
+        let key = PKey::public_key_from_pem(pem)?;
+
+        if !is_p256(&key) {
+            return Err(CryptoError::InvalidKey);
+        }
+
+        Ok(Self { key })
+    }
+}
+
+impl TSignatureAlgorithm for EcdsaP256Sha256Signer {
+    fn algorithm() -> SignatureAlgorithm {
+        SignatureAlgorithm::EcdsaP256Sha256
+    }
+}
+
+impl TSignatureAlgorithm for EcdsaP256Sha256Verifier {
+    fn algorithm() -> SignatureAlgorithm {
+        SignatureAlgorithm::EcdsaP256Sha256
+    }
+}
+
+impl TSigner for EcdsaP256Sha256Signer {
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let mut signer = Signer::new(MessageDigest::sha256(), &self.key)?;
+        signer.update(data)?;
+
+        Ok(signer.sign_to_vec()?)
+    }
+}
+
+impl TVerifier for EcdsaP256Sha256Signer {
+    fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool, CryptoError> {
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &self.key)?;
+        verifier.update(data)?;
+
+        // Malformed DER signatures are reported as errors by OpenSSL.
+        Ok(verifier.verify(signature).unwrap_or(false))
+    }
+}
+
+impl TVerifier for EcdsaP256Sha256Verifier {
+    fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool, CryptoError> {
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &self.key)?;
+        verifier.update(data)?;
+
+        // Malformed DER signatures are reported as errors by OpenSSL.
+        Ok(verifier.verify(signature).unwrap_or(false))
+    }
+}
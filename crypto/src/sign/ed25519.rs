@@ -0,0 +1,106 @@
+use super::{SignatureAlgorithm as TSignatureAlgorithm, Signer as TSigner, Verifier as TVerifier};
+use crate::CryptoError;
+use openssl::{
+    pkey::{Id, PKey, Private, Public},
+    sign::{Signer, Verifier},
+};
+use proto_core::algorithms::SignatureAlgorithm;
+
+/// Ed25519 signer holding the private key. Used to issue tokens.
+#[derive(Debug)]
+pub struct Ed25519Signer {
+    key: PKey<Private>,
+}
+
+/// Ed25519 verifier holding only the public key. Nodes that verify tokens do
+/// not need the key that mints them.
+#[derive(Debug)]
+pub struct Ed25519Verifier {
+    key: PKey<Public>,
+}
+
+impl Ed25519Signer {
+    /// Generates a new random Ed25519 private key.
+    pub fn generate() -> Result<Self, CryptoError> {
+        Ok(Self {
+            key: PKey::generate_ed25519()?,
+        })
+    }
+
+    /// Loads a PEM encoded PKCS#8 private key.
+    pub fn from_pem(pem: &[u8]) -> Result<Self, CryptoError> {
+        let key = PKey::private_key_from_pem(pem)?;
+
+        if key.id() != Id::ED25519 {
+            return Err(CryptoError::InvalidKey);
+        }
+
+        Ok(Self { key })
+    }
+
+    /// Encodes the private key in PEM format.
+    pub fn private_key_to_pem(&self) -> Result<Vec<u8>, CryptoError> {
+        Ok(self.key.private_key_to_pem_pkcs8()?)
+    }
+
+    /// Encodes the public key in PEM format.
+    pub fn public_key_to_pem(&self) -> Result<Vec<u8>, CryptoError> {
+        Ok(self.key.public_key_to_pem()?)
+    }
+
+    /// Returns a verifier for the public half of the key.
+    pub fn verifier(&self) -> Result<Ed25519Verifier, CryptoError> {
+        Ed25519Verifier::from_pem(&self.public_key_to_pem()?)
+    }
+}
+
+impl Ed25519Verifier {
+    /// Loads a PEM encoded public key.
+    pub fn from_pem(pem: &[u8]) -> Result<Self, CryptoError> {
+        let key = PKey::public_key_from_pem(pem)?;
+
+        if key.id() != Id::ED25519 {
+            return Err(CryptoError::InvalidKey);
+        }
+
+        Ok(Self { key })
+    }
+}
+
+impl TSignatureAlgorithm for Ed25519Signer {
+    fn algorithm() -> SignatureAlgorithm {
+        SignatureAlgorithm::Ed25519
+    }
+}
+
+impl TSignatureAlgorithm for Ed25519Verifier {
+    fn algorithm() -> SignatureAlgorithm {
+        SignatureAlgorithm::Ed25519
+    }
+}
+
+impl TSigner for Ed25519Signer {
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let mut signer = Signer::new_without_digest(&self.key)?;
+
+        Ok(signer.sign_oneshot_to_vec(data)?)
+    }
+}
+
+impl TVerifier for Ed25519Signer {
+    fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool, CryptoError> {
+        let mut verifier = Verifier::new_without_digest(&self.key)?;
+
+        // Signatures of invalid length are reported as errors by OpenSSL.
+        Ok(verifier.verify_oneshot(signature, data).unwrap_or(false))
+    }
+}
+
+impl TVerifier for Ed25519Verifier {
+    fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool, CryptoError> {
+        let mut verifier = Verifier::new_without_digest(&self.key)?;
+
+        // Signatures of invalid length are reported as errors by OpenSSL.
+        Ok(verifier.verify_oneshot(signature, data).unwrap_or(false))
+    }
+}
@@ -1,5 +1,5 @@
 use super::{SignatureAlgorithm as TSignatureAlgorithm, Signer as TSigner, Verifier as TVerifier};
-use crate::CryptoError;
+use crate::{CryptoError, key::KeyError};
 use openssl::{
     hash::MessageDigest,
     memcmp,
@@ -9,19 +9,42 @@ use openssl::{
 use proto_core::algorithms::SignatureAlgorithm;
 
 /// HMAC with SHA 256 symmetric keyed signature algorithm.
-#[derive(Debug)]
+///
+/// The key is owned by OpenSSL, which clears it when the key is freed. It is
+/// never printed by [`Debug`], and the type is intentionally not [`Clone`].
 pub struct Hs256 {
     key: PKey<Private>,
 }
 
 impl Hs256 {
+    /// Minimum key length in bytes, matching the SHA-256 output size.
+    pub const MIN_KEY_LEN: usize = 32;
+
+    /// Length of a signature in bytes.
+    pub const SIGNATURE_LEN: usize = 32;
+
+    /// Creates a new signer, rejecting keys shorter than
+    /// [`Self::MIN_KEY_LEN`] bytes.
     pub fn try_new(key: &[u8]) -> Result<Self, CryptoError> {
+        if key.len() < Self::MIN_KEY_LEN {
+            return Err(KeyError::TooShort {
+                min: Self::MIN_KEY_LEN,
+                got: key.len(),
+            })?;
+        }
+
         Ok(Self {
             key: PKey::hmac(key)?,
         })
     }
 }
 
+impl std::fmt::Debug for Hs256 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hs256").finish_non_exhaustive()
+    }
+}
+
 impl TSignatureAlgorithm for Hs256 {
     fn algorithm() -> SignatureAlgorithm {
         SignatureAlgorithm::HmacSha256
@@ -39,6 +62,12 @@ impl TSigner for Hs256 {
 
 impl TVerifier for Hs256 {
     fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool, CryptoError> {
+        // The constant-time comparison panics on slices of different lengths,
+        // and the signature comes from unauthenticated peers.
+        if signature.len() != Self::SIGNATURE_LEN {
+            return Ok(false);
+        }
+
         let mut signer = Signer::new(MessageDigest::sha256(), &self.key)?;
         signer.update(data)?;
 
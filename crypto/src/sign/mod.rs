@@ -1,5 +1,7 @@
 //! Signing and verifying primitives used during handshake.
 
+mod ecdsa_p256;
+mod ed25519;
 mod hs256;
 mod token;
 
@@ -8,6 +10,8 @@ mod tests;
 
 use crate::CryptoError;
 
+pub use ecdsa_p256::*;
+pub use ed25519::*;
 pub use hs256::*;
 pub use token::*;
 
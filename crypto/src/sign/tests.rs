@@ -5,13 +5,15 @@ use testutil::DynResult;
 
 #[test]
 fn signature() -> DynResult<()> {
-    let signer = Hs256::try_new(&hex::decode("01598d62e7028e14aa6a4bc148a9f4b4").unwrap())?;
+    let signer = Hs256::try_new(
+        &hex::decode("01598d62e7028e14aa6a4bc148a9f4b401598d62e7028e14aa6a4bc148a9f4b4").unwrap(),
+    )?;
 
     let signature = signer.sign(&hex::decode("0ceea336b1b2eb9bbd895688a3fc0208").unwrap())?;
 
     assert_eq!(
         signature,
-        hex::decode("db945088ccfb16fae0c4d329f56a7e60387fbb74156b0b6771688c344a843ee5").unwrap()
+        hex::decode("d374d8d0bd3c38db33b8603357e51ebbed1b66ebfb78d703fbe83016e697a657").unwrap()
     );
 
     Ok(())
@@ -32,6 +34,23 @@ fn random() -> DynResult<()> {
     Ok(())
 }
 
+#[test]
+fn wrong_signature_length() -> DynResult<()> {
+    let signer = Hs256::try_new(&random_bytes!(32))?;
+
+    let data = random_bytes!(512);
+    let mut signature = signer.sign(&data)?;
+    assert_eq!(signature.len(), Hs256::SIGNATURE_LEN);
+
+    assert!(!signer.verify(&data, &signature[..16])?);
+    assert!(!signer.verify(&data, &[])?);
+
+    signature.push(0);
+    assert!(!signer.verify(&data, &signature)?);
+
+    Ok(())
+}
+
 #[test]
 fn sign_token() -> DynResult<()> {
     let token = testutil::generate_token(1, String::from("Test"), vec![String::from("test")]);
@@ -39,15 +58,83 @@ fn sign_token() -> DynResult<()> {
 
     let signed_token = super::token::sign_token(token, &signer)?;
 
-    assert_eq!(
-        signed_token.signature,
-        signer.sign(&signed_token.token.encode()?[..])?
-    );
+    assert_eq!(signed_token.signature, signer.sign(&signed_token.token)?);
+
+    assert!(signer.verify(&signed_token.token, &signed_token.signature[..])?);
+
+    Ok(())
+}
+
+#[test]
+fn ed25519() -> DynResult<()> {
+    let signer = Ed25519Signer::from_pem(&Ed25519Signer::generate()?.private_key_to_pem()?)?;
+    let verifier = Ed25519Verifier::from_pem(&signer.public_key_to_pem()?)?;
+
+    let mut data = random_bytes!(512);
+    let signature = signer.sign(&data)?;
+
+    assert!(verifier.verify(&data, &signature)?);
+
+    data[0] = data[0].wrapping_add(1);
+    assert!(!verifier.verify(&data, &signature)?);
+    assert!(!verifier.verify(&data, &[])?);
+
+    Ok(())
+}
+
+#[test]
+fn ecdsa_p256_sha256() -> DynResult<()> {
+    let signer =
+        EcdsaP256Sha256Signer::from_pem(&EcdsaP256Sha256Signer::generate()?.private_key_to_pem()?)?;
+    let verifier = EcdsaP256Sha256Verifier::from_pem(&signer.public_key_to_pem()?)?;
+
+    let mut data = random_bytes!(512);
+    let signature = signer.sign(&data)?;
+
+    assert!(verifier.verify(&data, &signature)?);
+
+    data[0] = data[0].wrapping_add(1);
+    assert!(!verifier.verify(&data, &signature)?);
+    assert!(!verifier.verify(&data, &[])?);
+
+    Ok(())
+}
+
+#[test]
+fn wrong_key_type() -> DynResult<()> {
+    let ed25519 = Ed25519Signer::generate()?;
+    let ecdsa = EcdsaP256Sha256Signer::generate()?;
+
+    assert!(Ed25519Signer::from_pem(&ecdsa.private_key_to_pem()?).is_err());
+    assert!(Ed25519Verifier::from_pem(&ecdsa.public_key_to_pem()?).is_err());
+    assert!(EcdsaP256Sha256Signer::from_pem(&ed25519.private_key_to_pem()?).is_err());
+    assert!(EcdsaP256Sha256Verifier::from_pem(&ed25519.public_key_to_pem()?).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn verify_asymmetric_token() -> DynResult<()> {
+    let token = testutil::generate_token(1, String::from("Test"), vec![String::from("test")]);
+    let signer = Ed25519Signer::generate()?;
+    let verifier = TokenVerifier::from(signer.verifier()?);
+
+    let mut signed_token = super::token::sign_token(token, &signer)?;
+    assert!(verifier.verify_token(&signed_token)?);
+
+    let other = TokenVerifier::from(Ed25519Signer::generate()?.verifier()?);
+    assert!(!other.verify_token(&signed_token)?);
+
+    let mut token = signed_token.decode()?;
+    token.level -= 1;
+    signed_token.token = token.encode()?;
+    assert!(!verifier.verify_token(&signed_token)?);
 
-    assert!(signer.verify(
-        &signed_token.token.encode()?[..],
-        &signed_token.signature[..]
-    )?);
+    let ecdsa = TokenVerifier::from(EcdsaP256Sha256Signer::generate()?.verifier()?);
+    assert!(matches!(
+        ecdsa.verify_token(&signed_token),
+        Err(CryptoError::AlgorithmMismatch)
+    ));
 
     Ok(())
 }
@@ -1,14 +1,99 @@
-use super::{SignatureAlgorithm, Signer};
-use crate::CryptoError;
-use proto_core::token::{SignedToken, Token};
+use super::{
+    EcdsaP256Sha256Verifier, Ed25519Verifier, Hs256, SignatureAlgorithm as TSignatureAlgorithm,
+    Signer, Verifier,
+};
+use crate::{CryptoError, key::KeyMaterial};
+use proto_core::{
+    algorithms::SignatureAlgorithm,
+    token::{SignedToken, Token},
+};
 
-pub fn sign_token<S: Signer + SignatureAlgorithm>(
+pub fn sign_token<S: Signer + TSignatureAlgorithm>(
     token: Token,
     signer: &S,
 ) -> Result<SignedToken, CryptoError> {
+    let token = token.encode()?;
+
     Ok(SignedToken {
-        signature: signer.sign(&token.encode()?[..])?,
+        signature: signer.sign(&token)?,
         token,
         signature_algorithm: S::algorithm(),
     })
 }
+
+/// Verifies the signature of the token over its encoded bytes, without
+/// decoding it. Tokens signed with an algorithm other than the verifier's are
+/// rejected with [`CryptoError::AlgorithmMismatch`].
+pub fn verify_token<V: Verifier + TSignatureAlgorithm>(
+    signed_token: &SignedToken,
+    verifier: &V,
+) -> Result<bool, CryptoError> {
+    if signed_token.signature_algorithm != V::algorithm() {
+        return Err(CryptoError::AlgorithmMismatch);
+    }
+
+    verifier.verify(&signed_token.token, &signed_token.signature)
+}
+
+/// Token verifier of any supported signature algorithm.
+///
+/// Asymmetric variants only hold public keys, so nodes that authenticate
+/// clients cannot mint tokens themselves.
+#[derive(Debug)]
+pub enum TokenVerifier {
+    HmacSha256(Hs256),
+    Ed25519(Ed25519Verifier),
+    EcdsaP256Sha256(EcdsaP256Sha256Verifier),
+}
+
+impl TokenVerifier {
+    /// Creates a verifier from stored key material. HMAC keys are raw bytes,
+    /// whereas asymmetric algorithms expect a PEM encoded public key.
+    pub fn from_key(algorithm: SignatureAlgorithm, key: &KeyMaterial) -> Result<Self, CryptoError> {
+        Ok(match algorithm {
+            SignatureAlgorithm::HmacSha256 => Self::HmacSha256(Hs256::try_new(key.as_bytes())?),
+            SignatureAlgorithm::Ed25519 => {
+                Self::Ed25519(Ed25519Verifier::from_pem(key.as_bytes())?)
+            }
+            SignatureAlgorithm::EcdsaP256Sha256 => {
+                Self::EcdsaP256Sha256(EcdsaP256Sha256Verifier::from_pem(key.as_bytes())?)
+            }
+        })
+    }
+
+    /// Signature algorithm of the underlying verifier.
+    pub fn algorithm(&self) -> SignatureAlgorithm {
+        match self {
+            Self::HmacSha256(_) => Hs256::algorithm(),
+            Self::Ed25519(_) => Ed25519Verifier::algorithm(),
+            Self::EcdsaP256Sha256(_) => EcdsaP256Sha256Verifier::algorithm(),
+        }
+    }
+
+    /// Verifies the signature of the token. See [`verify_token`].
+    pub fn verify_token(&self, signed_token: &SignedToken) -> Result<bool, CryptoError> {
+        match self {
+            Self::HmacSha256(verifier) => verify_token(signed_token, verifier),
+            Self::Ed25519(verifier) => verify_token(signed_token, verifier),
+            Self::EcdsaP256Sha256(verifier) => verify_token(signed_token, verifier),
+        }
+    }
+}
+
+impl From<Hs256> for TokenVerifier {
+    fn from(verifier: Hs256) -> Self {
+        Self::HmacSha256(verifier)
+    }
+}
+
+impl From<Ed25519Verifier> for TokenVerifier {
+    fn from(verifier: Ed25519Verifier) -> Self {
+        Self::Ed25519(verifier)
+    }
+}
+
+impl From<EcdsaP256Sha256Verifier> for TokenVerifier {
+    fn from(verifier: EcdsaP256Sha256Verifier) -> Self {
+        Self::EcdsaP256Sha256(verifier)
+    }
+}
@@ -1,31 +1,153 @@
-use crate::CryptoError;
+use crate::{CryptoError, key::KeyError};
+use bytes::BytesMut;
 use openssl::{
+    cipher,
+    cipher_ctx::CipherCtx,
     sha,
     symm::{Cipher, decrypt, encrypt},
 };
+use zeroize::Zeroize;
 
 /// AES 128 with CBC encryption + SHA256 algorithm.
-#[derive(Debug, Clone)]
+///
+/// The key is wiped from memory on drop and never printed by [`Debug`]. The
+/// type is intentionally not [`Clone`]; share it through an
+/// [`Arc`](std::sync::Arc) instead.
 pub struct Aes128CbcSha256 {
-    key: Vec<u8>,
+    key: [u8; Self::KEY_LEN],
 }
 
 impl Aes128CbcSha256 {
-    pub fn new(key: Vec<u8>) -> Self {
-        Aes128CbcSha256 { key }
+    /// Length of the AES-128 key in bytes.
+    pub const KEY_LEN: usize = 16;
+
+    /// Length of an AES block in bytes.
+    const BLOCK_LEN: usize = 16;
+
+    /// Creates a new cipher, rejecting keys that are not exactly
+    /// [`Self::KEY_LEN`] bytes long.
+    pub fn try_new(key: &[u8]) -> Result<Self, CryptoError> {
+        let key = key.try_into().map_err(|_| KeyError::InvalidLength {
+            expected: Self::KEY_LEN,
+            got: key.len(),
+        })?;
+
+        Ok(Aes128CbcSha256 { key })
     }
 
+    /// Encrypts `payload`. Without an IV, an all-zero IV is used, as CBC
+    /// requires one.
     pub fn encrypt(&self, iv: Option<&[u8]>, payload: &[u8]) -> Result<Vec<u8>, CryptoError> {
-        Ok(encrypt(Cipher::aes_128_cbc(), &self.key, iv, payload)?)
+        let iv = iv.unwrap_or(&[0; Self::BLOCK_LEN]);
+        Ok(encrypt(
+            Cipher::aes_128_cbc(),
+            &self.key,
+            Some(iv),
+            payload,
+        )?)
     }
 
+    /// Decrypts `payload`, with an all-zero IV if none is given.
     pub fn decrypt(&self, iv: Option<&[u8]>, payload: &[u8]) -> Result<Vec<u8>, CryptoError> {
-        Ok(decrypt(Cipher::aes_128_cbc(), &self.key, iv, payload)?)
+        let iv = iv.unwrap_or(&[0; Self::BLOCK_LEN]);
+        Ok(decrypt(
+            Cipher::aes_128_cbc(),
+            &self.key,
+            Some(iv),
+            payload,
+        )?)
+    }
+
+    /// Encrypts `buf[offset..]` in place, growing the buffer by the padding.
+    pub fn encrypt_in_place(
+        &self,
+        iv: Option<&[u8]>,
+        buf: &mut BytesMut,
+        offset: usize,
+    ) -> Result<(), CryptoError> {
+        let mut ctx = CipherCtx::new()?;
+        ctx.encrypt_init(Some(cipher::Cipher::aes_128_cbc()), Some(&self.key), iv)?;
+
+        let len = buf.len() - offset;
+        // Room for the partial block held back by the update and the final
+        // padded block.
+        buf.resize(buf.len() + 2 * Self::BLOCK_LEN, 0);
+
+        let data = &mut buf[offset..];
+        let mut written = ctx.cipher_update_inplace(data, len)?;
+        written += ctx.cipher_final(&mut data[written..])?;
+        buf.truncate(offset + written);
+
+        Ok(())
+    }
+
+    /// Decrypts `buf[offset..]` in place, shrinking the buffer by the
+    /// padding.
+    ///
+    /// Unlike encryption, decryption never grows the buffer, so it also works
+    /// on buffers split off a larger one.
+    pub fn decrypt_in_place(
+        &self,
+        iv: Option<&[u8]>,
+        buf: &mut BytesMut,
+        offset: usize,
+    ) -> Result<(), CryptoError> {
+        let data = &mut buf[offset..];
+        let Some(body) = data.len().checked_sub(Self::BLOCK_LEN) else {
+            return Err(CryptoError::InvalidCiphertext);
+        };
+
+        let mut ctx = CipherCtx::new()?;
+        ctx.decrypt_init(Some(cipher::Cipher::aes_128_cbc()), Some(&self.key), iv)?;
+
+        // The last block is the room the in-place update needs, so it is
+        // decrypted separately once the update has consumed the body.
+        let mut written = ctx.cipher_update_inplace(data, body)?;
+        let mut last = [0; 2 * Self::BLOCK_LEN];
+        let mut tail = ctx.cipher_update(&data[body..], Some(&mut last))?;
+        tail += ctx.cipher_final(&mut last[tail..])?;
+
+        data[written..written + tail].copy_from_slice(&last[..tail]);
+        written += tail;
+        last.zeroize();
+
+        buf.truncate(offset + written);
+
+        Ok(())
+    }
+
+    /// Derives a new cipher whose key is the hash of `label` and the current
+    /// key. Used for traffic key updates.
+    pub fn derive(&self, label: &[u8]) -> Self {
+        let mut hasher = sha::Sha256::new();
+        hasher.update(label);
+        hasher.update(&self.key);
+        let mut digest = hasher.finish();
+
+        let mut key = [0; Self::KEY_LEN];
+        key.copy_from_slice(&digest[..Self::KEY_LEN]);
+        digest.zeroize();
+
+        Aes128CbcSha256 { key }
     }
 
-    pub fn shasum(&self, payload: &[u8]) -> [u8; 32] {
+    /// SHA-256 digest of the associated data and the payload.
+    pub fn shasum(&self, aad: &[u8], payload: &[u8]) -> [u8; 32] {
         let mut hasher = sha::Sha256::new();
+        hasher.update(aad);
         hasher.update(payload);
         hasher.finish()
     }
 }
+
+impl std::fmt::Debug for Aes128CbcSha256 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Aes128CbcSha256").finish_non_exhaustive()
+    }
+}
+
+impl Drop for Aes128CbcSha256 {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
@@ -1,10 +1,11 @@
 use super::*;
-use crate::DynResult;
+use crate::{CryptoError, DynResult};
+use bytes::BytesMut;
 use proto_core::random_bytes;
 
 #[test]
 fn random() -> DynResult<()> {
-    let aes128_cbc = Aes128CbcSha256::new(Vec::from(random_bytes!(16)));
+    let aes128_cbc = Aes128CbcSha256::try_new(&random_bytes!(16))?;
 
     let data = random_bytes!(1024);
     let payload = aes128_cbc.encrypt(None, &data)?;
@@ -13,3 +14,45 @@ fn random() -> DynResult<()> {
 
     Ok(())
 }
+
+#[test]
+fn in_place() -> DynResult<()> {
+    let aes128_cbc = Aes128CbcSha256::try_new(&random_bytes!(16))?;
+
+    let iv = random_bytes!(16);
+    for len in [0, 1, 15, 16, 17, 1024] {
+        let data = vec![0xab; len];
+
+        let mut buf = BytesMut::from(&b"header"[..]);
+        buf.extend_from_slice(&data);
+        aes128_cbc.encrypt_in_place(Some(&iv), &mut buf, 6)?;
+
+        assert_eq!(&buf[..6], b"header");
+        assert_eq!(buf[6..], aes128_cbc.encrypt(Some(&iv), &data)?);
+
+        // Decryption works on a buffer split off a shared one, without
+        // reallocating it.
+        let mut split = buf.split_to(buf.len());
+        let ptr = split.as_ptr();
+        aes128_cbc.decrypt_in_place(Some(&iv), &mut split, 6)?;
+        assert_eq!(&split[6..], &data[..]);
+        assert_eq!(split.as_ptr(), ptr);
+    }
+
+    let mut short = BytesMut::from(&[0; 15][..]);
+    assert!(matches!(
+        aes128_cbc.decrypt_in_place(Some(&iv), &mut short, 0),
+        Err(CryptoError::InvalidCiphertext)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn redacted_debug() -> DynResult<()> {
+    let aes128_cbc = Aes128CbcSha256::try_new(&[0xab; 16])?;
+
+    assert_eq!(format!("{aes128_cbc:?}"), "Aes128CbcSha256 { .. }");
+
+    Ok(())
+}
@@ -1,13 +1,52 @@
 //! Handshake TLS providers.
 
 use crate::{CryptoError, symm::Aes128CbcSha256};
-use proto_core::tls_provider::TlsProvider;
-use std::sync::{Arc, Mutex};
+use bytes::BytesMut;
+use proto_core::tls_provider::{Decrypter, Encrypter, TlsProvider};
+use std::sync::Arc;
+use zeroize::Zeroize;
+
+/// Label mixed into keys and IVs when deriving fresh traffic keys.
+const KEY_UPDATE_LABEL: &[u8] = b"dehset key update";
+
+/// Length of the IV of a single record.
+const RECORD_IV_LEN: usize = 16;
 
 /// Encrption layer implementing symmetric encrption.
+///
+/// A record is the ciphertext followed by the SHA-256 sum of the associated
+/// data and the plaintext.
+///
+/// Each direction holds its own IV and key, so either side can rekey its
+/// sending direction independently, and splits off into a [`SymmEncrypter`]
+/// and a [`SymmDecrypter`]. The IV of a record is the direction's IV combined
+/// with the explicit sequence number of the record, which is also
+/// authenticated as associated data. The directions themselves keep no
+/// record count, so a rejected record cannot shift the IVs of the records
+/// after it. IV states are wiped from memory on drop and never printed by
+/// [`Debug`].
+///
+/// Records only read the traffic keys. Rekeying takes a direction mutably,
+/// so the record layer switches keys under the same lock that orders and
+/// numbers its records, and no other lock or counter is involved.
 pub struct SymmTls {
-    encrpyt_iv: Mutex<[u8; 32]>,
-    decrypt_iv: Mutex<[u8; 32]>,
+    encrpyt: TrafficKeys,
+    decrypt: TrafficKeys,
+}
+
+/// Sending direction of a [`SymmTls`].
+pub struct SymmEncrypter {
+    keys: TrafficKeys,
+}
+
+/// Receiving direction of a [`SymmTls`].
+pub struct SymmDecrypter {
+    keys: TrafficKeys,
+}
+
+/// IV and key of a single direction.
+struct TrafficKeys {
+    iv: [u8; 32],
     encrpter: Arc<Aes128CbcSha256>,
 }
 
@@ -20,87 +59,186 @@ impl SymmTls {
         let mut hasher = openssl::sha::Sha256::new();
         hasher.update(&server_iv);
         hasher.update(&client_iv);
-        let iv = hasher.finish();
+        let mut iv = hasher.finish();
 
-        SymmTls {
-            decrypt_iv: Mutex::new(iv),
-            encrpyt_iv: Mutex::new(iv),
-            encrpter,
-        }
+        let symm_tls = SymmTls {
+            decrypt: TrafficKeys {
+                iv,
+                encrpter: Arc::clone(&encrpter),
+            },
+            encrpyt: TrafficKeys { iv, encrpter },
+        };
+        iv.zeroize();
+
+        symm_tls
     }
 }
 
-macro_rules! increment_iv {
-    ($iv:expr) => {{
-        let mut i = 0;
-        loop {
-            if $iv[i] == 255 {
-                $iv[i] = 0;
-                i += 1;
-                if $iv.len() == i {
-                    i = 0;
-                }
-                continue;
-            }
-            $iv[i] += 1;
-            break;
+impl TrafficKeys {
+    /// IV of the record with the given sequence number: the direction's IV
+    /// with the sequence number mixed into its last bytes.
+    fn record_iv(&self, seq: u64) -> [u8; RECORD_IV_LEN] {
+        let mut iv = [0; RECORD_IV_LEN];
+        iv.copy_from_slice(&self.iv[..RECORD_IV_LEN]);
+
+        for (byte, seq) in iv[RECORD_IV_LEN - 8..].iter_mut().zip(seq.to_be_bytes()) {
+            *byte ^= seq;
         }
-    }};
+
+        iv
+    }
+
+    /// Replaces the key and IV with ones derived from the current state.
+    fn update(&mut self) {
+        self.encrpter = Arc::new(self.encrpter.derive(KEY_UPDATE_LABEL));
+
+        let mut hasher = openssl::sha::Sha256::new();
+        hasher.update(KEY_UPDATE_LABEL);
+        hasher.update(&self.iv);
+        let mut iv = hasher.finish();
+
+        self.iv = iv;
+        iv.zeroize();
+    }
+}
+
+impl std::fmt::Debug for SymmTls {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SymmTls").finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Debug for SymmEncrypter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SymmEncrypter").finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Debug for SymmDecrypter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SymmDecrypter").finish_non_exhaustive()
+    }
+}
+
+impl Drop for TrafficKeys {
+    fn drop(&mut self) {
+        self.iv.zeroize();
+    }
 }
 
 impl TlsProvider for SymmTls {
+    type Encrypter = SymmEncrypter;
+    type Decrypter = SymmDecrypter;
+
+    fn split(self) -> (SymmEncrypter, SymmDecrypter) {
+        (
+            SymmEncrypter { keys: self.encrpyt },
+            SymmDecrypter { keys: self.decrypt },
+        )
+    }
+}
+
+impl Encrypter for SymmEncrypter {
     type Error = CryptoError;
 
-    fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, Self::Error> {
-        let mut iv = self.encrpyt_iv.lock().unwrap();
-        increment_iv!(iv);
+    fn encrypt(&self, seq: u64, buf: &mut BytesMut, offset: usize) -> Result<(), Self::Error> {
+        let keys = &self.keys;
+        let mut iv = keys.record_iv(seq);
 
-        let iv = iv.clone();
+        let shasum = keys.encrpter.shasum(&seq.to_be_bytes(), &buf[offset..]);
+        let encrypted = keys.encrpter.encrypt_in_place(Some(&iv), buf, offset);
+        iv.zeroize();
+        encrypted?;
+        buf.extend_from_slice(&shasum);
 
-        let mut shasum = Vec::from(self.encrpter.shasum(data));
-        shasum.append(&mut self.encrpter.encrypt(Some(&iv), data)?);
+        Ok(())
+    }
 
-        Ok(shasum)
+    fn rekey(&mut self) -> Result<(), Self::Error> {
+        self.keys.update();
+        Ok(())
     }
+}
 
-    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, Self::Error> {
-        let mut iv = self.decrypt_iv.lock().unwrap();
-        increment_iv!(iv);
+impl Decrypter for SymmDecrypter {
+    type Error = CryptoError;
 
-        let iv = iv.clone();
+    fn decrypt(&self, seq: u64, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        let Some(payload_len) = buf.len().checked_sub(32) else {
+            return Err(CryptoError::InvalidShasum);
+        };
 
-        let payload = self.encrpter.decrypt(Some(&iv), &ciphertext[32..])?;
-        let shasum = self.encrpter.shasum(&payload);
+        let keys = &self.keys;
 
-        if shasum == &ciphertext[0..32] {
-            Ok(payload)
+        let mut expected = [0; 32];
+        expected.copy_from_slice(&buf[payload_len..]);
+        buf.truncate(payload_len);
+
+        let mut iv = keys.record_iv(seq);
+        let decrypted = keys.encrpter.decrypt_in_place(Some(&iv), buf, 0);
+        iv.zeroize();
+        decrypted?;
+
+        if keys.encrpter.shasum(&seq.to_be_bytes(), buf) == expected {
+            Ok(())
         } else {
             Err(CryptoError::InvalidShasum)
         }
     }
+
+    fn rekey(&mut self) -> Result<(), Self::Error> {
+        self.keys.update();
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::SymmTls;
+    use super::{SymmDecrypter, SymmEncrypter, SymmTls};
     use crate::{CryptoError, symm::Aes128CbcSha256};
-    use proto_core::{random_bytes, tls_provider::TlsProvider};
+    use bytes::BytesMut;
+    use proto_core::{
+        random_bytes,
+        tls_provider::{Decrypter, Encrypter, TlsProvider},
+    };
     use std::sync::Arc;
     use testutil::DynResult;
 
+    fn symm_tls(iv: [u8; 32], key: &[u8]) -> Result<(SymmEncrypter, SymmDecrypter), CryptoError> {
+        Ok(SymmTls::new((iv, iv), Arc::new(Aes128CbcSha256::try_new(key)?)).split())
+    }
+
+    fn seal(tls: &SymmEncrypter, seq: u64, payload: &[u8]) -> Result<BytesMut, CryptoError> {
+        let mut buf = BytesMut::from(payload);
+        tls.encrypt(seq, &mut buf, 0)?;
+        Ok(buf)
+    }
+
+    fn open(tls: &SymmDecrypter, seq: u64, ciphertext: &[u8]) -> Result<BytesMut, CryptoError> {
+        let mut buf = BytesMut::from(ciphertext);
+        tls.decrypt(seq, &mut buf)?;
+        Ok(buf)
+    }
+
     #[test]
     fn symm_tls_fuzz() -> DynResult<()> {
         for _ in 0..128 {
             let iv = random_bytes!(32);
-            let key = Vec::from(random_bytes!(16));
-            let server_tls = SymmTls::new((iv, iv), Arc::new(Aes128CbcSha256::new(key.clone())));
-            let client_tls = SymmTls::new((iv, iv), Arc::new(Aes128CbcSha256::new(key)));
+            let key = random_bytes!(16);
+            let (server_tx, server_rx) = symm_tls(iv, &key)?;
+            let (client_tx, client_rx) = symm_tls(iv, &key)?;
 
-            for _ in 0..16 {
+            for seq in 0..16 {
                 let payload = Vec::from(random_bytes!(16));
 
-                assert_eq!(payload, client_tls.decrypt(&server_tls.encrypt(&payload)?)?);
-                assert_eq!(payload, server_tls.decrypt(&client_tls.encrypt(&payload)?)?);
+                assert_eq!(
+                    payload,
+                    open(&client_rx, seq, &seal(&server_tx, seq, &payload)?)?
+                );
+                assert_eq!(
+                    payload,
+                    open(&server_rx, seq, &seal(&client_tx, seq, &payload)?)?
+                );
             }
         }
 
@@ -109,20 +247,164 @@ mod tests {
 
     #[test]
     fn symm_tls_invalid() -> DynResult<()> {
-        let iv = random_bytes!(32);
-        let key = Vec::from(random_bytes!(16));
-        let symm_tls = SymmTls::new((iv, iv), Arc::new(Aes128CbcSha256::new(key)));
+        let (tx, rx) = symm_tls(random_bytes!(32), &random_bytes!(16))?;
 
         let payload1 = random_bytes!(32);
         let payload2 = random_bytes!(32);
 
-        let _ciphertext1 = symm_tls.encrypt(&payload1)?;
-        let ciphertext2 = symm_tls.encrypt(&payload2)?;
+        let _ciphertext1 = seal(&tx, 0, &payload1)?;
+        let ciphertext2 = seal(&tx, 1, &payload2)?;
 
-        if let Err(CryptoError::InvalidShasum) = symm_tls.decrypt(&ciphertext2) {
+        if let Err(CryptoError::InvalidShasum) = open(&rx, 0, &ciphertext2) {
             Ok(())
         } else {
             panic!("Expected CryptoError::InvalidShasum");
         }
     }
+
+    #[test]
+    fn symm_tls_rekey() -> DynResult<()> {
+        let iv = random_bytes!(32);
+        let key = random_bytes!(16);
+        let (mut server_tx, server_rx) = symm_tls(iv, &key)?;
+        let (client_tx, mut client_rx) = symm_tls(iv, &key)?;
+
+        let payload = random_bytes!(64);
+        let in_flight = seal(&server_tx, 0, &payload)?;
+
+        server_tx.rekey()?;
+        let rekeyed = seal(&server_tx, 1, &payload)?;
+
+        // Records encrypted before the switch decrypt with the old keys.
+        assert_eq!(open(&client_rx, 0, &in_flight)?, &payload[..]);
+        client_rx.rekey()?;
+        assert_eq!(open(&client_rx, 1, &rekeyed)?, &payload[..]);
+
+        // The other direction is unaffected.
+        assert_eq!(
+            open(&server_rx, 0, &seal(&client_tx, 0, &payload)?)?,
+            &payload[..]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn symm_tls_rekey_mismatch() -> DynResult<()> {
+        let iv = random_bytes!(32);
+        let key = random_bytes!(16);
+        let (mut server_tx, _) = symm_tls(iv, &key)?;
+        let (_, client_rx) = symm_tls(iv, &key)?;
+
+        server_tx.rekey()?;
+
+        assert!(open(&client_rx, 0, &seal(&server_tx, 0, &random_bytes!(64))?).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn symm_tls_associated_data() -> DynResult<()> {
+        let iv = random_bytes!(32);
+        let key = random_bytes!(16);
+        let (server_tx, _) = symm_tls(iv, &key)?;
+        let (_, client_rx) = symm_tls(iv, &key)?;
+
+        let ciphertext = seal(&server_tx, 1, &random_bytes!(64))?;
+
+        assert!(matches!(
+            open(&client_rx, 2, &ciphertext),
+            Err(CryptoError::InvalidShasum)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn symm_tls_rejected_record() -> DynResult<()> {
+        let iv = random_bytes!(32);
+        let key = random_bytes!(16);
+        let (server_tx, _) = symm_tls(iv, &key)?;
+        let (_, client_rx) = symm_tls(iv, &key)?;
+
+        let payload = random_bytes!(64);
+        let first = seal(&server_tx, 0, &payload)?;
+        let second = seal(&server_tx, 1, &payload)?;
+
+        assert!(matches!(
+            open(&client_rx, 0, &first[..16]),
+            Err(CryptoError::InvalidShasum)
+        ));
+        assert!(open(&client_rx, 0, &second).is_err());
+
+        // Rejected records leave the direction untouched, and each record
+        // decrypts with its own sequence number only.
+        assert_eq!(open(&client_rx, 1, &second)?, &payload[..]);
+        assert_eq!(open(&client_rx, 0, &first)?, &payload[..]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn symm_tls_concurrent() -> DynResult<()> {
+        let (tx, _) = symm_tls(random_bytes!(32), &random_bytes!(16))?;
+
+        let tx = &tx;
+        let ciphertexts = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4)
+                .map(|thread| {
+                    scope.spawn(move || {
+                        (0..64)
+                            .map(|record| seal(tx, thread * 64 + record, &[0; 64]))
+                            .collect::<Result<Vec<_>, _>>()
+                    })
+                })
+                .collect();
+
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+
+        // Every record got its own IV.
+        let unique: std::collections::HashSet<_> = ciphertexts.iter().flatten().collect();
+        assert_eq!(unique.len(), 4 * 64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn symm_tls_offset() -> DynResult<()> {
+        let iv = random_bytes!(32);
+        let key = random_bytes!(16);
+        let (server_tx, _) = symm_tls(iv, &key)?;
+        let (_, client_rx) = symm_tls(iv, &key)?;
+
+        let payload = random_bytes!(100);
+        let mut buf = BytesMut::from(&b"header"[..]);
+        buf.extend_from_slice(&payload);
+
+        server_tx.encrypt(0, &mut buf, 6)?;
+        assert_eq!(&buf[..6], b"header");
+        assert_eq!(open(&client_rx, 0, &buf[6..])?, &payload[..]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn symm_tls_redacted_debug() -> DynResult<()> {
+        let symm_tls = SymmTls::new(
+            ([0xab; 32], [0xcd; 32]),
+            Arc::new(Aes128CbcSha256::try_new(&[0xef; 16])?),
+        );
+
+        assert_eq!(format!("{symm_tls:?}"), "SymmTls { .. }");
+
+        let (tx, rx) = symm_tls.split();
+        assert_eq!(format!("{tx:?}"), "SymmEncrypter { .. }");
+        assert_eq!(format!("{rx:?}"), "SymmDecrypter { .. }");
+
+        Ok(())
+    }
 }
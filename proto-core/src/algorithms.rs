@@ -14,8 +14,12 @@ pub enum EncryptionAlgorithm {
 }
 
 /// Supported signature algorithms.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SignatureAlgorithm {
     /// HMAC with SHA-256.
     HmacSha256,
+    /// Edwards-curve digital signature algorithm over Curve25519.
+    Ed25519,
+    /// ECDSA over the NIST P-256 curve with SHA-256.
+    EcdsaP256Sha256,
 }
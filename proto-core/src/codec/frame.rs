@@ -0,0 +1,97 @@
+use crate::tunnel::{MAX_FRAME_SIZE, TunnelError};
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Length of the content length and sequence number preceding a record.
+pub const FRAME_HEADER_LEN: usize = 4 + 8;
+
+/// Tunnel frame: an encrypted record and its sequence number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub seq: u64,
+    pub record: BytesMut,
+}
+
+/// Codec of tunnel frames, see [`tunnel`](crate::tunnel) for their
+/// structure.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameCodec {
+    max_frame_size: usize,
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        FrameCodec {
+            max_frame_size: MAX_FRAME_SIZE,
+        }
+    }
+}
+
+impl FrameCodec {
+    /// Sets the maximum size of an encrypted record. Defaults to
+    /// [`MAX_FRAME_SIZE`].
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> FrameCodec {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Maximum size of an encrypted record.
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// Length of the frame at the start of `src`, header included, once the
+    /// header is complete. Fails if the record exceeds the maximum size.
+    pub fn frame_len(&self, src: &[u8]) -> Result<Option<usize>, TunnelError> {
+        if src.len() < FRAME_HEADER_LEN {
+            return Ok(None);
+        }
+
+        let content_length = (&src[..4]).get_u32() as usize;
+        if content_length > self.max_frame_size {
+            return Err(TunnelError::PayloadTooLarge);
+        }
+
+        Ok(Some(FRAME_HEADER_LEN + content_length))
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = TunnelError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, TunnelError> {
+        let Some(frame_len) = self.frame_len(src)? else {
+            src.reserve(FRAME_HEADER_LEN - src.len());
+            return Ok(None);
+        };
+
+        if src.len() < frame_len {
+            src.reserve(frame_len - src.len());
+            return Ok(None);
+        }
+
+        let mut record = src.split_to(frame_len);
+        record.advance(4);
+        let seq = record.get_u64();
+
+        Ok(Some(Frame { seq, record }))
+    }
+}
+
+impl Encoder<Frame> for FrameCodec {
+    type Error = TunnelError;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> Result<(), TunnelError> {
+        if frame.record.len() > self.max_frame_size {
+            return Err(TunnelError::PayloadTooLarge);
+        }
+
+        dst.reserve(FRAME_HEADER_LEN + frame.record.len());
+        dst.put_u32(frame.record.len() as u32);
+        dst.put_u64(frame.seq);
+        dst.extend_from_slice(&frame.record);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use crate::sub_protocol::handshake::{HandshakeAlert, HandshakeContentType, PayloadLimits};
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Length of the content length and content type preceding a handshake
+/// payload.
+pub const HANDSHAKE_HEADER_LEN: usize = 2 + 1;
+
+/// Codec of handshake payloads, see
+/// [`handshake`](crate::sub_protocol::handshake) for their structure.
+///
+/// Payloads are limited per content type by [`PayloadLimits`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HandshakeCodec {
+    limits: PayloadLimits,
+}
+
+impl HandshakeCodec {
+    /// Creates a codec enforcing `limits`.
+    pub fn new(limits: PayloadLimits) -> HandshakeCodec {
+        HandshakeCodec { limits }
+    }
+
+    /// Maximum payload size of each content type.
+    pub fn limits(&self) -> &PayloadLimits {
+        &self.limits
+    }
+
+    /// Length of the frame at the start of `src`, header included, once the
+    /// header is complete. Fails if the content type is unknown or the
+    /// payload exceeds its maximum size.
+    pub fn frame_len(&self, src: &[u8]) -> Result<Option<usize>, HandshakeAlert> {
+        if src.len() < HANDSHAKE_HEADER_LEN {
+            return Ok(None);
+        }
+
+        let content_type = HandshakeContentType::try_from(src[2])?;
+        let content_length = (&src[..2]).get_u16();
+        self.check_size(content_type, content_length as usize)?;
+
+        Ok(Some(HANDSHAKE_HEADER_LEN + content_length as usize))
+    }
+
+    fn check_size(
+        &self,
+        content_type: HandshakeContentType,
+        len: usize,
+    ) -> Result<(), HandshakeAlert> {
+        let max_size = self.limits.max_size(content_type);
+        if len > max_size as usize {
+            return Err(HandshakeAlert::PayloadTooLarge {
+                content_type,
+                max_size,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Decoder for HandshakeCodec {
+    type Item = (HandshakeContentType, BytesMut);
+    type Error = HandshakeAlert;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, HandshakeAlert> {
+        let Some(frame_len) = self.frame_len(src)? else {
+            src.reserve(HANDSHAKE_HEADER_LEN - src.len());
+            return Ok(None);
+        };
+
+        if src.len() < frame_len {
+            src.reserve(frame_len - src.len());
+            return Ok(None);
+        }
+
+        let mut payload = src.split_to(frame_len);
+        payload.advance(2);
+        let content_type = HandshakeContentType::try_from(payload.get_u8())?;
+
+        Ok(Some((content_type, payload)))
+    }
+}
+
+impl Encoder<(HandshakeContentType, &[u8])> for HandshakeCodec {
+    type Error = HandshakeAlert;
+
+    fn encode(
+        &mut self,
+        (content_type, payload): (HandshakeContentType, &[u8]),
+        dst: &mut BytesMut,
+    ) -> Result<(), HandshakeAlert> {
+        self.check_size(content_type, payload.len())?;
+
+        dst.reserve(HANDSHAKE_HEADER_LEN + payload.len());
+        dst.put_u16(payload.len() as u16);
+        dst.put_u8(content_type as u8);
+        dst.extend_from_slice(payload);
+
+        Ok(())
+    }
+}
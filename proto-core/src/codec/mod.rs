@@ -0,0 +1,24 @@
+//! [`tokio_util::codec`] implementations of the protocol framings, usable
+//! with [`Framed`](tokio_util::codec::Framed) and composable with
+//! `Stream`/`Sink` combinators.
+//!
+//! - [`HandshakeCodec`]: pre-encryption handshake payloads.
+//! - [`FrameCodec`]: tunnel frames carrying an encrypted record and its
+//!   sequence number.
+//! - [`RecordCodec`]: the record layer on top of tunnel frames. It encrypts
+//!   and decrypts records, checks their sequence numbers and applies key
+//!   updates.
+//!
+//! Decoders validate the header of a frame before reserving room for its
+//! body, so oversized frames are rejected without being allocated.
+
+mod frame;
+mod handshake;
+mod record;
+
+#[cfg(test)]
+mod tests;
+
+pub use frame::{FRAME_HEADER_LEN, Frame, FrameCodec};
+pub use handshake::{HANDSHAKE_HEADER_LEN, HandshakeCodec};
+pub use record::{RecordCodec, RecordDecoder, RecordEncoder};
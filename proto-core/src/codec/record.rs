@@ -0,0 +1,203 @@
+use super::{FRAME_HEADER_LEN, Frame, FrameCodec};
+use crate::{
+    tls_provider::{Decrypter, Encrypter, TlsProvider},
+    tunnel::{MAX_PAYLOAD_SIZE, MAX_RECORD_OVERHEAD, RecordType, TunnelError},
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Record layer encoder. Numbers records and encrypts them in place with the
+/// sending direction of a [`TlsProvider`].
+///
+/// Encoding a [`RecordType::KeyUpdate`] record switches the encrypter to
+/// fresh traffic keys for the records following it.
+#[derive(Debug)]
+pub struct RecordEncoder<E> {
+    tls: E,
+    /// Sequence number of the next outgoing record.
+    seq: u64,
+    frames: FrameCodec,
+}
+
+/// Record layer decoder. Checks sequence numbers and decrypts records in place
+/// with the receiving direction of a [`TlsProvider`].
+///
+/// Key update records are applied transparently, so only the payloads of data
+/// records are yielded.
+#[derive(Debug)]
+pub struct RecordDecoder<D> {
+    tls: D,
+    /// Sequence number of the next incoming record.
+    seq: u64,
+    frames: FrameCodec,
+}
+
+/// Both directions of the record layer, e.g. for use with
+/// [`Framed`](tokio_util::codec::Framed).
+#[derive(Debug)]
+pub struct RecordCodec<E, D> {
+    encoder: RecordEncoder<E>,
+    decoder: RecordDecoder<D>,
+}
+
+impl<E> RecordEncoder<E> {
+    /// Creates an encoder whose first record has sequence number zero.
+    pub fn new(tls: E) -> RecordEncoder<E> {
+        RecordEncoder {
+            tls,
+            seq: 0,
+            frames: FrameCodec::default(),
+        }
+    }
+
+    /// Returns the encrypter.
+    pub fn encrypter(&self) -> &E {
+        &self.tls
+    }
+}
+
+impl<D> RecordDecoder<D> {
+    /// Creates a decoder that expects sequence number zero first.
+    pub fn new(tls: D) -> RecordDecoder<D> {
+        RecordDecoder {
+            tls,
+            seq: 0,
+            frames: FrameCodec::default(),
+        }
+    }
+
+    /// Returns the decrypter.
+    pub fn decrypter(&self) -> &D {
+        &self.tls
+    }
+
+    /// Sets the maximum size of the encrypted records accepted from the
+    /// peer. Defaults to [`MAX_FRAME_SIZE`](crate::tunnel::MAX_FRAME_SIZE).
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.frames = self.frames.with_max_frame_size(max_frame_size);
+    }
+}
+
+impl<E, D> RecordCodec<E, D> {
+    /// Creates a codec from both directions of `tls`.
+    pub fn new<T>(tls: T) -> RecordCodec<E, D>
+    where
+        T: TlsProvider<Encrypter = E, Decrypter = D>,
+    {
+        let (encrypter, decrypter) = tls.split();
+
+        RecordCodec {
+            encoder: RecordEncoder::new(encrypter),
+            decoder: RecordDecoder::new(decrypter),
+        }
+    }
+
+    /// Splits the codec into its directions.
+    pub fn split(self) -> (RecordEncoder<E>, RecordDecoder<D>) {
+        (self.encoder, self.decoder)
+    }
+}
+
+impl<E: Encrypter> Encoder<(RecordType, &[u8])> for RecordEncoder<E> {
+    type Error = TunnelError;
+
+    fn encode(
+        &mut self,
+        (record_type, payload): (RecordType, &[u8]),
+        dst: &mut BytesMut,
+    ) -> Result<(), TunnelError> {
+        if payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(TunnelError::PayloadTooLarge);
+        }
+
+        let seq = self.seq;
+
+        let start = dst.len();
+        dst.reserve(FRAME_HEADER_LEN + 1 + payload.len() + MAX_RECORD_OVERHEAD);
+        // The content length is filled in once the record is encrypted.
+        dst.put_u32(0);
+        dst.put_u64(seq);
+        dst.put_u8(record_type as u8);
+        dst.extend_from_slice(payload);
+
+        let encrypted = self
+            .tls
+            .encrypt(seq, dst, start + FRAME_HEADER_LEN)
+            .map_err(|_| TunnelError::Crypto);
+        let content_length = dst.len() - start - FRAME_HEADER_LEN;
+
+        if let Err(tunnel_error) = encrypted {
+            dst.truncate(start);
+            return Err(tunnel_error);
+        }
+        if content_length > self.frames.max_frame_size() {
+            dst.truncate(start);
+            return Err(TunnelError::PayloadTooLarge);
+        }
+        dst[start..start + 4].copy_from_slice(&(content_length as u32).to_be_bytes());
+        // Only encoded records use up a sequence number, so the peer still
+        // expects the next one after a failure.
+        self.seq += 1;
+
+        if record_type == RecordType::KeyUpdate {
+            self.tls.rekey().map_err(|_| TunnelError::Crypto)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<D: Decrypter> Decoder for RecordDecoder<D> {
+    type Item = Bytes;
+    type Error = TunnelError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Bytes>, TunnelError> {
+        while let Some(Frame { seq, mut record }) = self.frames.decode(src)? {
+            let expected = self.seq;
+
+            if seq < expected {
+                return Err(TunnelError::Replayed { expected, got: seq });
+            }
+            if seq > expected {
+                return Err(TunnelError::OutOfOrder { expected, got: seq });
+            }
+
+            self.tls
+                .decrypt(seq, &mut record)
+                .map_err(|_| TunnelError::Crypto)?;
+            self.seq += 1;
+            if record.is_empty() {
+                return Err(TunnelError::InvalidRecord);
+            }
+
+            match RecordType::try_from(record[0])? {
+                RecordType::Data => {
+                    record.advance(1);
+                    return Ok(Some(record.freeze()));
+                }
+                RecordType::KeyUpdate => {
+                    self.tls.rekey().map_err(|_| TunnelError::Crypto)?;
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl<E: Encrypter, D> Encoder<(RecordType, &[u8])> for RecordCodec<E, D> {
+    type Error = TunnelError;
+
+    fn encode(&mut self, item: (RecordType, &[u8]), dst: &mut BytesMut) -> Result<(), TunnelError> {
+        self.encoder.encode(item, dst)
+    }
+}
+
+impl<E, D: Decrypter> Decoder for RecordCodec<E, D> {
+    type Item = Bytes;
+    type Error = TunnelError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Bytes>, TunnelError> {
+        self.decoder.decode(src)
+    }
+}
@@ -0,0 +1,222 @@
+use super::{
+    FRAME_HEADER_LEN, Frame, FrameCodec, HANDSHAKE_HEADER_LEN, HandshakeCodec, RecordCodec,
+    RecordDecoder, RecordEncoder,
+};
+use crate::{
+    sub_protocol::handshake::{HandshakeAlert, HandshakeContentType, PayloadLimits},
+    tls_provider::{Encrypter, MockDirection, MockTls},
+    tunnel::{MAX_FRAME_SIZE, MAX_PAYLOAD_SIZE, RecordType, TunnelError},
+};
+use bytes::{BufMut, BytesMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use testutil::DynResult;
+use tokio_util::codec::{Decoder, Encoder};
+
+#[test]
+pub fn frame_codec() -> DynResult<()> {
+    let mut codec = FrameCodec::default();
+    let mut buf = BytesMut::new();
+
+    for seq in 0..3 {
+        let record = BytesMut::from(&[seq as u8; 10][..]);
+        codec.encode(Frame { seq, record }, &mut buf)?;
+    }
+    assert_eq!(buf.len(), 3 * (FRAME_HEADER_LEN + 10));
+
+    // Frames are only decoded once complete.
+    let mut src = buf.split_to(FRAME_HEADER_LEN + 5);
+    assert!(codec.decode(&mut src)?.is_none());
+    src.unsplit(buf);
+
+    for seq in 0..3 {
+        let frame = codec.decode(&mut src)?.ok_or("missing frame")?;
+        assert_eq!(frame.seq, seq);
+        assert_eq!(&frame.record[..], [seq as u8; 10]);
+    }
+    assert!(codec.decode(&mut src)?.is_none());
+
+    Ok(())
+}
+
+#[test]
+pub fn oversized_frame() {
+    let mut codec = FrameCodec::default();
+
+    let mut src = BytesMut::new();
+    src.put_u32(MAX_FRAME_SIZE as u32 + 1);
+    src.put_u64(0);
+
+    // Rejected from the header alone, without reserving room for the body.
+    assert!(matches!(
+        codec.decode(&mut src),
+        Err(TunnelError::PayloadTooLarge)
+    ));
+    assert!(src.capacity() < MAX_FRAME_SIZE);
+
+    let record = BytesMut::zeroed(8);
+    let mut codec = codec.with_max_frame_size(4);
+    assert!(matches!(
+        codec.encode(Frame { seq: 0, record }, &mut BytesMut::new()),
+        Err(TunnelError::PayloadTooLarge)
+    ));
+}
+
+#[test]
+pub fn handshake_codec() -> Result<(), HandshakeAlert> {
+    let mut codec = HandshakeCodec::default();
+    let mut buf = BytesMut::new();
+
+    codec.encode(
+        (HandshakeContentType::ClientHello, &[1, 2, 3][..]),
+        &mut buf,
+    )?;
+    codec.encode((HandshakeContentType::Finished, &[][..]), &mut buf)?;
+    assert_eq!(buf.len(), 2 * HANDSHAKE_HEADER_LEN + 3);
+
+    let (content_type, payload) = codec
+        .decode(&mut buf)?
+        .ok_or(HandshakeAlert::InvalidPayload)?;
+    assert_eq!(content_type, HandshakeContentType::ClientHello);
+    assert_eq!(&payload[..], [1, 2, 3]);
+
+    let (content_type, payload) = codec
+        .decode(&mut buf)?
+        .ok_or(HandshakeAlert::InvalidPayload)?;
+    assert_eq!(content_type, HandshakeContentType::Finished);
+    assert!(payload.is_empty());
+    assert!(codec.decode(&mut buf)?.is_none());
+
+    Ok(())
+}
+
+#[test]
+pub fn invalid_handshake() {
+    let mut codec = HandshakeCodec::new(PayloadLimits {
+        client_hello: 4,
+        ..PayloadLimits::MAX
+    });
+
+    // Rejected from the header alone, without reserving room for the body.
+    let mut src = BytesMut::from(&[0xff, 0xff, HandshakeContentType::ClientHello as u8][..]);
+    assert!(matches!(
+        codec.decode(&mut src),
+        Err(HandshakeAlert::PayloadTooLarge {
+            content_type: HandshakeContentType::ClientHello,
+            max_size: 4
+        })
+    ));
+    assert!(src.capacity() < u16::MAX as usize);
+
+    // Other content types keep their own limit.
+    let mut src = BytesMut::from(&[0, 5, HandshakeContentType::Finished as u8][..]);
+    assert!(matches!(codec.decode(&mut src), Ok(None)));
+
+    let mut src = BytesMut::from(&[0, 1, 0xff, 0][..]);
+    assert!(matches!(
+        codec.decode(&mut src),
+        Err(HandshakeAlert::UnknownContentType)
+    ));
+
+    assert!(matches!(
+        codec.encode(
+            (HandshakeContentType::ClientHello, &[0; 5][..]),
+            &mut BytesMut::new()
+        ),
+        Err(HandshakeAlert::PayloadTooLarge { .. })
+    ));
+}
+
+#[test]
+pub fn record_codec() -> DynResult<()> {
+    let mut codec = RecordCodec::new(MockTls);
+    let mut buf = BytesMut::new();
+
+    codec.encode((RecordType::Data, &[1, 2, 3][..]), &mut buf)?;
+    codec.encode((RecordType::KeyUpdate, &[][..]), &mut buf)?;
+    codec.encode((RecordType::Data, &[4, 5, 6][..]), &mut buf)?;
+
+    // Key updates are applied and never yielded.
+    assert_eq!(
+        &codec.decode(&mut buf)?.ok_or("missing record")?[..],
+        [1, 2, 3]
+    );
+    assert_eq!(
+        &codec.decode(&mut buf)?.ok_or("missing record")?[..],
+        [4, 5, 6]
+    );
+    assert!(codec.decode(&mut buf)?.is_none());
+
+    let (encoder, decoder) = codec.split();
+    assert_eq!(encoder.encrypter().epoch(), 1);
+    assert_eq!(decoder.decrypter().epoch(), 1);
+
+    let payload = vec![0; MAX_PAYLOAD_SIZE + 1];
+    let (mut encoder, _) = RecordCodec::new(MockTls).split();
+    assert!(matches!(
+        encoder.encode((RecordType::Data, &payload[..]), &mut buf),
+        Err(TunnelError::PayloadTooLarge)
+    ));
+    assert!(buf.is_empty());
+
+    Ok(())
+}
+
+/// Encrypter that fails once, then leaves records as they are.
+#[derive(Default)]
+struct FailOnce(AtomicBool);
+
+impl Encrypter for FailOnce {
+    type Error = ();
+
+    fn encrypt(&self, _seq: u64, _buf: &mut BytesMut, _offset: usize) -> Result<(), ()> {
+        if self.0.swap(true, Ordering::SeqCst) {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    fn rekey(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+#[test]
+pub fn failed_record_keeps_sequence() -> DynResult<()> {
+    let mut encoder = RecordEncoder::new(FailOnce::default());
+    let mut decoder = RecordDecoder::new(MockDirection::default());
+    let mut buf = BytesMut::new();
+
+    assert!(matches!(
+        encoder.encode((RecordType::Data, &[1][..]), &mut buf),
+        Err(TunnelError::Crypto)
+    ));
+    assert!(buf.is_empty());
+
+    // The failed record did not use up a sequence number.
+    encoder.encode((RecordType::Data, &[2][..]), &mut buf)?;
+    assert_eq!(&decoder.decode(&mut buf)?.ok_or("missing record")?[..], [2]);
+
+    Ok(())
+}
+
+#[test]
+pub fn replayed_record() -> DynResult<()> {
+    let mut codec = RecordCodec::new(MockTls);
+    let mut buf = BytesMut::new();
+
+    codec.encode((RecordType::Data, &[1][..]), &mut buf)?;
+    let replay = buf.clone();
+
+    codec.decode(&mut buf)?.ok_or("missing record")?;
+    buf.extend_from_slice(&replay);
+    assert!(matches!(
+        codec.decode(&mut buf),
+        Err(TunnelError::Replayed {
+            expected: 1,
+            got: 0
+        })
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,236 @@
+use std::sync::Mutex;
+use tokio::sync::Semaphore;
+
+/// Number of bytes a peer may send on a stream before it receives a window
+/// update. This also bounds the bytes buffered for a single stream.
+pub const INITIAL_WINDOW_SIZE: u32 = 256 * 1024;
+
+/// Maximum payload size of a single data message.
+pub const MAX_DATA_CHUNK: usize = 16 * 1024;
+
+/// Flow control error types.
+#[derive(Debug, PartialEq, Eq)]
+pub enum FlowControlError {
+    /// The peer sent more data than the advertised window allows.
+    WindowExceeded,
+    /// A window update grew the window beyond [`u32::MAX`].
+    WindowOverflow,
+    /// The stream has been closed.
+    Closed,
+}
+
+impl std::fmt::Display for FlowControlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WindowExceeded => write!(f, "window exceeded"),
+            Self::WindowOverflow => write!(f, "window overflow"),
+            Self::Closed => write!(f, "stream closed"),
+        }
+    }
+}
+
+impl std::error::Error for FlowControlError {}
+
+/// Sending side of a stream window.
+///
+/// Holds the credit granted by the peer. Senders wait for credit, so a slow
+/// receiver only stalls its own stream.
+pub struct SendWindow {
+    credit: Semaphore,
+}
+
+impl SendWindow {
+    /// Creates a new [`SendWindow`] with `initial` bytes of credit.
+    pub fn new(initial: u32) -> SendWindow {
+        SendWindow {
+            credit: Semaphore::new(initial as usize),
+        }
+    }
+
+    /// Waits until `len` bytes of credit are available and consumes them.
+    ///
+    /// `len` must not exceed the peer's window, see [`MAX_DATA_CHUNK`].
+    pub async fn acquire(&self, len: usize) -> Result<(), FlowControlError> {
+        let len = u32::try_from(len).map_err(|_| FlowControlError::WindowOverflow)?;
+
+        self.credit
+            .acquire_many(len)
+            .await
+            .map_err(|_| FlowControlError::Closed)?
+            .forget();
+
+        Ok(())
+    }
+
+    /// Adds credit announced by a window update of the peer.
+    pub fn grant(&self, increment: u32) -> Result<(), FlowControlError> {
+        if self.credit.available_permits() + increment as usize > u32::MAX as usize {
+            return Err(FlowControlError::WindowOverflow);
+        }
+
+        self.credit.add_permits(increment as usize);
+        Ok(())
+    }
+
+    /// Closes the window, failing pending and future acquisitions.
+    pub fn close(&self) {
+        self.credit.close();
+    }
+
+    /// Remaining credit in bytes.
+    pub fn available(&self) -> usize {
+        self.credit.available_permits()
+    }
+}
+
+/// Receiving side of a stream window.
+///
+/// Tracks the bytes buffered for a stream and decides when consumed bytes
+/// should be announced to the peer with a window update.
+pub struct RecvWindow {
+    window: u32,
+    state: Mutex<RecvState>,
+}
+
+struct RecvState {
+    /// Bytes the peer may still send.
+    available: u64,
+    /// Bytes received but not yet consumed.
+    buffered: u64,
+    /// Bytes consumed but not yet announced to the peer.
+    released: u64,
+}
+
+impl RecvWindow {
+    /// Creates a new [`RecvWindow`] advertising `window` bytes.
+    pub fn new(window: u32) -> RecvWindow {
+        RecvWindow {
+            window,
+            state: Mutex::new(RecvState {
+                available: window as u64,
+                buffered: 0,
+                released: 0,
+            }),
+        }
+    }
+
+    /// Accounts `len` received bytes. Fails if the peer exceeded the window.
+    pub fn receive(&self, len: usize) -> Result<(), FlowControlError> {
+        let mut state = self.state.lock().unwrap();
+
+        if len as u64 > state.available {
+            return Err(FlowControlError::WindowExceeded);
+        }
+
+        state.available -= len as u64;
+        state.buffered += len as u64;
+
+        Ok(())
+    }
+
+    /// Releases `len` consumed bytes.
+    ///
+    /// Returns the increment of a window update to be sent once at least half
+    /// of the window has been consumed.
+    pub fn release(&self, len: usize) -> Option<u32> {
+        let mut state = self.state.lock().unwrap();
+
+        let len = (len as u64).min(state.buffered);
+        state.buffered -= len;
+        state.released += len;
+
+        if state.released >= self.window as u64 / 2 {
+            let increment = state.released;
+            state.available += increment;
+            state.released = 0;
+
+            Some(increment as u32)
+        } else {
+            None
+        }
+    }
+
+    /// Bytes received but not yet consumed.
+    pub fn buffered(&self) -> u64 {
+        self.state.lock().unwrap().buffered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FlowControlError, RecvWindow, SendWindow};
+    use std::{sync::Arc, time::Duration};
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn send_window() {
+        let window = Arc::new(SendWindow::new(8));
+
+        window.acquire(6).await.unwrap();
+        assert_eq!(window.available(), 2);
+
+        let pending = tokio::spawn({
+            let window = Arc::clone(&window);
+            async move { window.acquire(4).await }
+        });
+
+        tokio::task::yield_now().await;
+        assert!(!pending.is_finished());
+
+        window.grant(2).unwrap();
+        pending.await.unwrap().unwrap();
+        assert_eq!(window.available(), 0);
+
+        assert_eq!(window.grant(u32::MAX), Ok(()));
+        assert_eq!(window.grant(1), Err(FlowControlError::WindowOverflow));
+    }
+
+    #[tokio::test]
+    async fn close_send_window() {
+        let window = Arc::new(SendWindow::new(0));
+
+        let pending = tokio::spawn({
+            let window = Arc::clone(&window);
+            async move { window.acquire(1).await }
+        });
+
+        window.close();
+        assert_eq!(pending.await.unwrap(), Err(FlowControlError::Closed));
+    }
+
+    #[test]
+    fn recv_window() {
+        let window = RecvWindow::new(16);
+
+        window.receive(10).unwrap();
+        assert_eq!(window.receive(7), Err(FlowControlError::WindowExceeded));
+        assert_eq!(window.buffered(), 10);
+
+        assert_eq!(window.release(4), None);
+        assert_eq!(window.release(4), Some(8));
+        assert_eq!(window.buffered(), 2);
+
+        // The announced bytes can be sent again.
+        window.receive(14).unwrap();
+        assert_eq!(window.receive(1), Err(FlowControlError::WindowExceeded));
+    }
+
+    #[tokio::test]
+    async fn independent_windows() {
+        let stalled = SendWindow::new(4);
+        let active = SendWindow::new(4);
+
+        stalled.acquire(4).await.unwrap();
+
+        // A stream without credit does not hold back other streams.
+        assert!(
+            timeout(Duration::from_millis(10), stalled.acquire(1))
+                .await
+                .is_err()
+        );
+        for _ in 0..4 {
+            active.acquire(1).await.unwrap();
+            active.grant(1).unwrap();
+        }
+    }
+}
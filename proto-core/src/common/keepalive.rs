@@ -0,0 +1,185 @@
+//! Dead-peer detection and round-trip time measurement.
+
+use crate::{sub_protocol::keepalive::Keepalive, tunnel::TunnelError};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::time::{Interval, MissedTickBehavior, interval_at};
+
+/// Interval of keepalive pings and number of missed pongs after which the
+/// peer is considered dead.
+///
+/// A half-open connection, e.g. behind a NAT that silently dropped its state,
+/// is detected after roughly `interval * max_missed_pongs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepalivePolicy {
+    /// Time between two pings. `None` disables keepalives, pings from the
+    /// peer are still answered.
+    pub interval: Option<Duration>,
+    /// Number of consecutive pings left unanswered after which the peer is
+    /// considered dead.
+    pub max_missed_pongs: u32,
+}
+
+impl KeepalivePolicy {
+    /// Policy that never sends pings.
+    pub const DISABLED: KeepalivePolicy = KeepalivePolicy {
+        interval: None,
+        max_missed_pongs: 0,
+    };
+}
+
+impl Default for KeepalivePolicy {
+    /// A ping every 15 seconds, with the peer considered dead after 3 missed
+    /// pongs.
+    fn default() -> Self {
+        KeepalivePolicy {
+            interval: Some(Duration::from_secs(15)),
+            max_missed_pongs: 3,
+        }
+    }
+}
+
+/// Snapshot of the keepalive state of a connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeepaliveStatus {
+    /// Round-trip time measured by the last answered ping.
+    pub rtt: Option<Duration>,
+    /// Number of consecutive pings left unanswered.
+    pub missed_pongs: u32,
+}
+
+/// Issues keepalive pings and tracks the pongs of the peer.
+#[derive(Debug)]
+pub struct KeepaliveMonitor {
+    policy: KeepalivePolicy,
+    state: Mutex<MonitorState>,
+}
+
+#[derive(Debug, Default)]
+struct MonitorState {
+    next_id: u64,
+    /// ID and send time of the last ping, until it is answered.
+    outstanding: Option<(u64, Instant)>,
+    status: KeepaliveStatus,
+}
+
+impl KeepaliveMonitor {
+    pub fn new(policy: KeepalivePolicy) -> KeepaliveMonitor {
+        KeepaliveMonitor {
+            policy,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Returns the ticker of the pings, or `None` if keepalives are disabled.
+    /// The first tick completes after one interval.
+    pub fn interval(&self) -> Option<Interval> {
+        let period = self.policy.interval?;
+        let mut interval = interval_at(tokio::time::Instant::now() + period, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        Some(interval)
+    }
+
+    /// Creates the next ping, counting the previous one as missed if it is
+    /// still unanswered.
+    ///
+    /// Fails with [`TunnelError::KeepaliveTimeout`] once the peer has missed
+    /// [`KeepalivePolicy::max_missed_pongs`] pongs.
+    pub fn ping(&self) -> Result<Keepalive, TunnelError> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.outstanding.is_some() {
+            state.status.missed_pongs += 1;
+            if state.status.missed_pongs >= self.policy.max_missed_pongs {
+                return Err(TunnelError::KeepaliveTimeout {
+                    missed: state.status.missed_pongs,
+                });
+            }
+        }
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.outstanding = Some((id, Instant::now()));
+
+        Ok(Keepalive::Ping { id })
+    }
+
+    /// Records a pong of the peer. Any pong proves the peer alive, but only
+    /// the pong of the last ping measures the round-trip time.
+    pub fn pong(&self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.status.missed_pongs = 0;
+
+        if let Some((outstanding, sent)) = state.outstanding
+            && outstanding == id
+        {
+            state.status.rtt = Some(sent.elapsed());
+            state.outstanding = None;
+        }
+    }
+
+    /// Forgets the outstanding ping and the measurements, e.g. after a
+    /// reconnection.
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.outstanding = None;
+        state.status = KeepaliveStatus::default();
+    }
+
+    /// Returns a snapshot of the keepalive state.
+    pub fn status(&self) -> KeepaliveStatus {
+        self.state.lock().unwrap().status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeepaliveMonitor, KeepalivePolicy};
+    use crate::{sub_protocol::keepalive::Keepalive, tunnel::TunnelError};
+    use std::time::Duration;
+
+    #[test]
+    fn missed_pongs() {
+        let monitor = KeepaliveMonitor::new(KeepalivePolicy {
+            interval: Some(Duration::from_secs(1)),
+            max_missed_pongs: 2,
+        });
+
+        assert_eq!(monitor.ping().unwrap(), Keepalive::Ping { id: 0 });
+        assert_eq!(monitor.ping().unwrap(), Keepalive::Ping { id: 1 });
+        assert_eq!(monitor.status().missed_pongs, 1);
+
+        // A late pong proves the peer alive without measuring the RTT.
+        monitor.pong(0);
+        assert_eq!(monitor.status().missed_pongs, 0);
+        assert_eq!(monitor.status().rtt, None);
+
+        monitor.ping().unwrap();
+        assert!(matches!(
+            monitor.ping(),
+            Err(TunnelError::KeepaliveTimeout { missed: 2 })
+        ));
+    }
+
+    #[test]
+    fn rtt() {
+        let monitor = KeepaliveMonitor::new(KeepalivePolicy::default());
+
+        let Keepalive::Ping { id } = monitor.ping().unwrap() else {
+            panic!("Expected Keepalive::Ping");
+        };
+        std::thread::sleep(Duration::from_millis(5));
+        monitor.pong(id);
+
+        let status = monitor.status();
+        assert!(status.rtt.unwrap() >= Duration::from_millis(5));
+        assert_eq!(status.missed_pongs, 0);
+
+        // The next ping is not counted as missed.
+        monitor.ping().unwrap();
+        assert_eq!(monitor.status().missed_pongs, 0);
+    }
+}
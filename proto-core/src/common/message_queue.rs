@@ -1,46 +1,502 @@
+use super::{
+    priority::Priority,
+    scheduler::{Scheduler, SchedulerState},
+};
 use crate::{
     tls_provider::TlsProvider,
     tunnel::{Tunnel, TunnelError},
 };
-use std::{collections::VecDeque, sync::Arc};
-use tokio::{
-    io::AsyncWrite,
-    sync::{Mutex, Notify},
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
 };
+use tokio::{io::AsyncWrite, sync::Notify};
+use tokio_util::sync::CancellationToken;
 
 /// Holds encoded payloads until they are ready to be sent over the TLS tunnel.
-pub struct MessageQueue<R, W, T> {
+pub struct MessageQueue<R, W, T: TlsProvider> {
     tunnel: Arc<Tunnel<R, W, T>>,
-    /// Per-priority message queues for each connection.
+    sender: MessageSender,
+}
+
+/// Cloneable handle that pushes messages into a [`MessageQueue`].
+///
+/// Unlike the queue itself, the handle does not depend on the tunnel types,
+/// so it can be shared with tasks that only produce messages.
+#[derive(Clone)]
+pub struct MessageSender {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<QueueState>,
+    /// Notifies the message service about new messages.
+    notify: Notify,
+    /// Notifies producers waiting for free capacity.
+    space: Notify,
+}
+
+struct QueueState {
+    /// Message queues indexed by [`Priority::index`].
     ///
     /// Each inner [`VecDeque`] represents a queue of messages at a given
-    /// priority level. Messages in lower-indexed queues are considered higher
-    /// priority and are sent first.
-    queue: Arc<Vec<Mutex<VecDeque<Vec<u8>>>>>,
-    notify: Arc<Notify>,
-}
-
-impl<R, W, T> MessageQueue<R, W, T> {
-    /// Creates a new [`MessageQueue`].
-    pub fn new(tunnel: Arc<Tunnel<R, W, T>>, queue_count: usize) -> MessageQueue<R, W, T> {
-        let mut queue = Vec::with_capacity(queue_count);
-        for _ in 0..queue_count {
-            queue.push(Mutex::new(VecDeque::new()));
+    /// priority level. Lower-indexed queues are considered higher priority,
+    /// the [`Scheduler`] decides how they share the tunnel.
+    queue: Vec<VecDeque<Entry>>,
+    /// Bytes held by each queue.
+    bytes: Vec<usize>,
+    /// Credited messages held by each queue, which the limits do not count.
+    credited: Vec<usize>,
+    limits: QueueLimits,
+    scheduler: SchedulerState,
+    dropped: u64,
+    rejected: u64,
+    throttled: u64,
+}
+
+/// A queued message.
+#[derive(Clone)]
+struct Entry {
+    payload: Vec<u8>,
+    /// Whether [`OverflowPolicy::DropOldest`] may drop the message.
+    droppable: bool,
+    /// Whether the message was pushed with
+    /// [`MessageSender::push_credited`].
+    credited: bool,
+}
+
+impl AsRef<[u8]> for Entry {
+    fn as_ref(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+/// Action taken when a message is pushed into a full queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait until the message service frees capacity.
+    Wait,
+    /// Reject the message with [`QueueError::Full`].
+    Reject,
+    /// Drop the oldest droppable message of the same priority level, or of
+    /// the lowest priority level holding one.
+    ///
+    /// Only messages pushed with [`MessageSender::push_droppable`], such as
+    /// keepalives and datagrams, are ever dropped. Other messages, such as
+    /// stream data, wait for capacity when there is nothing to drop, whereas
+    /// a droppable message is dropped itself.
+    DropOldest,
+}
+
+/// Capacity limits of a [`MessageQueue`], in messages.
+///
+/// Messages pushed with [`MessageSender::push_credited`] are bounded by flow
+/// control instead, and do not count towards the limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueLimits {
+    /// Maximum number of messages held by a single priority level.
+    pub per_queue: usize,
+    /// Maximum number of messages held by all priority levels together.
+    pub total: usize,
+    /// Action taken when either limit is reached.
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for QueueLimits {
+    /// 1024 messages per priority level, 4096 in total, waiting for capacity
+    /// on overflow.
+    fn default() -> Self {
+        QueueLimits {
+            per_queue: 1024,
+            total: 4096,
+            overflow: OverflowPolicy::Wait,
+        }
+    }
+}
+
+/// Outcome of a successful push.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushStatus {
+    /// The message was queued without delay.
+    Queued,
+    /// The queue was full, and the caller waited for capacity.
+    Throttled,
+    /// The queue was full, and an older message was dropped to make room.
+    DroppedOldest,
+    /// The queue was full of messages that cannot be dropped, so the pushed
+    /// droppable message was dropped instead.
+    Dropped,
+}
+
+/// Errors returned when a message cannot be queued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueError {
+    /// The queue is full and the [`OverflowPolicy`] rejects new messages.
+    Full,
+}
+
+impl std::fmt::Display for QueueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Full => write!(f, "message queue is full"),
         }
+    }
+}
+
+impl std::error::Error for QueueError {}
+
+/// Snapshot of the queue depth and overflow counters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueMetrics {
+    /// Number of messages held by each priority level, indexed by
+    /// [`Priority::index`].
+    pub depth: Vec<usize>,
+    /// Number of bytes held by each priority level.
+    pub bytes: Vec<usize>,
+    /// Messages dropped by [`OverflowPolicy::DropOldest`], including pushed
+    /// ones that were dropped for lack of older ones.
+    pub dropped: u64,
+    /// Messages rejected by [`OverflowPolicy::Reject`], or by
+    /// [`MessageSender::try_push_message`] on a full queue.
+    pub rejected: u64,
+    /// Pushes that waited for capacity under [`OverflowPolicy::Wait`].
+    pub throttled: u64,
+}
+
+impl QueueMetrics {
+    /// Total number of queued messages.
+    pub fn total_depth(&self) -> usize {
+        self.depth.iter().sum()
+    }
+}
+
+impl<R, W, T: TlsProvider> MessageQueue<R, W, T> {
+    /// Creates a new [`MessageQueue`] with one level per [`Priority`], the
+    /// default [`QueueLimits`] and strict priority scheduling.
+    pub fn new(tunnel: Arc<Tunnel<R, W, T>>) -> MessageQueue<R, W, T> {
+        let queue_count = Priority::COUNT;
 
         MessageQueue {
             tunnel,
-            queue: Arc::new(queue),
-            notify: Arc::new(Notify::new()),
+            sender: MessageSender {
+                shared: Arc::new(Shared {
+                    state: Mutex::new(QueueState {
+                        queue: vec![VecDeque::new(); queue_count],
+                        bytes: vec![0; queue_count],
+                        credited: vec![0; queue_count],
+                        limits: QueueLimits::default(),
+                        scheduler: SchedulerState::new(Scheduler::Strict, queue_count),
+                        dropped: 0,
+                        rejected: 0,
+                        throttled: 0,
+                    }),
+                    notify: Notify::new(),
+                    space: Notify::new(),
+                }),
+            },
+        }
+    }
+
+    /// Sets the capacity limits of the queue.
+    pub fn with_limits(self, limits: QueueLimits) -> MessageQueue<R, W, T> {
+        self.sender.shared.state.lock().unwrap().limits = limits;
+        self
+    }
+
+    /// Sets the order in which the priority levels are served.
+    pub fn with_scheduler(self, scheduler: Scheduler) -> MessageQueue<R, W, T> {
+        let mut state = self.sender.shared.state.lock().unwrap();
+        let queue_count = state.queue.len();
+        state.scheduler = SchedulerState::new(scheduler, queue_count);
+        drop(state);
+
+        self
+    }
+
+    /// Returns a handle for pushing messages into the queue.
+    pub fn sender(&self) -> MessageSender {
+        self.sender.clone()
+    }
+
+    /// Pushes a message into the queue, see [`MessageSender::push_message`].
+    pub async fn push_message(
+        &self,
+        payload: Vec<u8>,
+        priority: Priority,
+    ) -> Result<PushStatus, QueueError> {
+        self.sender.push_message(payload, priority).await
+    }
+
+    /// Returns a snapshot of the queue metrics.
+    pub fn metrics(&self) -> QueueMetrics {
+        self.sender.metrics()
+    }
+}
+
+impl QueueState {
+    /// Number of messages of the given level that count towards the limits.
+    fn limited_len(&self, level: usize) -> usize {
+        self.queue[level].len() - self.credited[level]
+    }
+
+    fn is_full(&self, level: usize) -> bool {
+        self.limited_len(level) >= self.limits.per_queue
+            || (0..self.queue.len())
+                .map(|level| self.limited_len(level))
+                .sum::<usize>()
+                >= self.limits.total
+    }
+
+    fn push(&mut self, entry: Entry, level: usize) {
+        self.bytes[level] += entry.payload.len();
+        self.credited[level] += usize::from(entry.credited);
+        self.queue[level].push_back(entry);
+    }
+
+    fn pop(&mut self, level: usize) -> Option<Vec<u8>> {
+        let entry = self.queue[level].pop_front()?;
+        self.bytes[level] -= entry.payload.len();
+        self.credited[level] -= usize::from(entry.credited);
+
+        Some(entry.payload)
+    }
+
+    /// Drops the oldest droppable message of the given priority level, or of
+    /// the lowest one holding such a message if that frees room for the
+    /// level. Returns `false` if there is no such message.
+    fn drop_oldest(&mut self, level: usize) -> bool {
+        let droppable = |queue: &VecDeque<Entry>| queue.iter().position(|entry| entry.droppable);
+
+        let victim = match droppable(&self.queue[level]) {
+            Some(index) => Some((level, index)),
+            // Other levels only make room below the per-queue limit.
+            None if self.limited_len(level) < self.limits.per_queue => (0..self.queue.len())
+                .rev()
+                .find_map(|i| Some((i, droppable(&self.queue[i])?))),
+            None => None,
+        };
+
+        let Some((victim, index)) = victim else {
+            return false;
+        };
+
+        if let Some(entry) = self.queue[victim].remove(index) {
+            self.bytes[victim] -= entry.payload.len();
+            self.dropped += 1;
         }
+        true
     }
 
+    /// Drops the oldest droppable messages until the given priority level has
+    /// room. Returns `false` if there are not enough of them.
+    fn make_room(&mut self, level: usize) -> bool {
+        while self.is_full(level) {
+            if !self.drop_oldest(level) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl MessageSender {
     /// Pushes a message into the queue.
-    pub async fn push_message(&self, payload: Vec<u8>, importance: usize) {
-        let mut queue = self.queue[importance].lock().await;
-        queue.push_back(payload);
-        drop(queue);
-        self.notify.notify_one();
+    ///
+    /// If the queue is full, the [`OverflowPolicy`] decides whether the call
+    /// waits, fails or drops an older message. The returned [`PushStatus`]
+    /// tells callers whether they were throttled.
+    pub async fn push_message(
+        &self,
+        payload: Vec<u8>,
+        priority: Priority,
+    ) -> Result<PushStatus, QueueError> {
+        self.push(payload, priority, false).await
+    }
+
+    /// Pushes a message that [`OverflowPolicy::DropOldest`] may drop, see
+    /// [`push_message`](Self::push_message).
+    pub async fn push_droppable(
+        &self,
+        payload: Vec<u8>,
+        priority: Priority,
+    ) -> Result<PushStatus, QueueError> {
+        self.push(payload, priority, true).await
+    }
+
+    /// Pushes a message into the queue without waiting for capacity.
+    ///
+    /// Behaves like [`push_message`](Self::push_message), except that a full
+    /// queue rejects the message instead of waiting, so producers serving
+    /// several queues are never stalled by one of them.
+    pub fn try_push_message(
+        &self,
+        payload: Vec<u8>,
+        priority: Priority,
+    ) -> Result<PushStatus, QueueError> {
+        self.try_push(payload, priority, false)
+    }
+
+    /// Pushes a message that [`OverflowPolicy::DropOldest`] may drop without
+    /// waiting for capacity, see [`try_push_message`](Self::try_push_message).
+    pub fn try_push_droppable(
+        &self,
+        payload: Vec<u8>,
+        priority: Priority,
+    ) -> Result<PushStatus, QueueError> {
+        self.try_push(payload, priority, true)
+    }
+
+    /// Pushes a message whose size the caller bounds by flow control, such
+    /// as stream data within the window granted by the receiver.
+    ///
+    /// The message is queued right away and does not count towards the
+    /// [`QueueLimits`], so it never fails, waits or displaces other
+    /// messages. Other messages still find room up to the limits.
+    pub fn push_credited(&self, payload: Vec<u8>, priority: Priority) {
+        let entry = Entry {
+            payload,
+            droppable: false,
+            credited: true,
+        };
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .push(entry, priority.index());
+        self.shared.notify.notify_one();
+    }
+
+    async fn push(
+        &self,
+        payload: Vec<u8>,
+        priority: Priority,
+        droppable: bool,
+    ) -> Result<PushStatus, QueueError> {
+        let level = priority.index();
+        let mut status = PushStatus::Queued;
+
+        loop {
+            let space = self.shared.space.notified();
+            tokio::pin!(space);
+
+            {
+                let mut state = self.shared.state.lock().unwrap();
+
+                if state.is_full(level) {
+                    let overflow = state.limits.overflow;
+                    let wait = match overflow {
+                        OverflowPolicy::Wait => true,
+                        OverflowPolicy::Reject => {
+                            state.rejected += 1;
+                            return Err(QueueError::Full);
+                        }
+                        OverflowPolicy::DropOldest if state.make_room(level) => {
+                            status = PushStatus::DroppedOldest;
+                            false
+                        }
+                        OverflowPolicy::DropOldest if droppable => {
+                            state.dropped += 1;
+                            return Ok(PushStatus::Dropped);
+                        }
+                        OverflowPolicy::DropOldest => true,
+                    };
+
+                    if wait {
+                        if status == PushStatus::Queued {
+                            state.throttled += 1;
+                            status = PushStatus::Throttled;
+                        }
+                        // Registers the waiter before releasing the lock, so
+                        // capacity freed in between is not missed.
+                        space.as_mut().enable();
+                    }
+                }
+
+                if !state.is_full(level) {
+                    state.push(
+                        Entry {
+                            payload,
+                            droppable,
+                            credited: false,
+                        },
+                        level,
+                    );
+                    drop(state);
+                    self.shared.notify.notify_one();
+
+                    return Ok(status);
+                }
+            }
+
+            space.await;
+        }
+    }
+
+    fn try_push(
+        &self,
+        payload: Vec<u8>,
+        priority: Priority,
+        droppable: bool,
+    ) -> Result<PushStatus, QueueError> {
+        let level = priority.index();
+        let mut status = PushStatus::Queued;
+        let mut state = self.shared.state.lock().unwrap();
+
+        if state.is_full(level) {
+            let overflow = state.limits.overflow;
+            match overflow {
+                OverflowPolicy::DropOldest if state.make_room(level) => {
+                    status = PushStatus::DroppedOldest;
+                }
+                OverflowPolicy::DropOldest if droppable => {
+                    state.dropped += 1;
+                    return Ok(PushStatus::Dropped);
+                }
+                _ => {
+                    state.rejected += 1;
+                    return Err(QueueError::Full);
+                }
+            }
+        }
+
+        state.push(
+            Entry {
+                payload,
+                droppable,
+                credited: false,
+            },
+            level,
+        );
+        drop(state);
+        self.shared.notify.notify_one();
+
+        Ok(status)
+    }
+
+    /// Returns a snapshot of the queue metrics.
+    pub fn metrics(&self) -> QueueMetrics {
+        let state = self.shared.state.lock().unwrap();
+
+        QueueMetrics {
+            depth: state.queue.iter().map(VecDeque::len).collect(),
+            bytes: state.bytes.clone(),
+            dropped: state.dropped,
+            rejected: state.rejected,
+            throttled: state.throttled,
+        }
+    }
+
+    /// Pops the next message chosen by the [`Scheduler`].
+    fn pop(&self) -> Option<Vec<u8>> {
+        let mut guard = self.shared.state.lock().unwrap();
+        let state = &mut *guard;
+        let level = state.scheduler.next(&state.queue)?;
+        let payload = state.pop(level);
+        drop(guard);
+
+        self.shared.space.notify_waiters();
+        payload
     }
 }
 
@@ -49,23 +505,23 @@ where
     W: Unpin + AsyncWrite,
     T: TlsProvider,
 {
-    /// Awaits and sends messages in importance order.
-    pub async fn message_service(&self) -> Result<(), TunnelError> {
+    /// Awaits and sends messages in the order chosen by the [`Scheduler`].
+    ///
+    /// Once `shutdown` is cancelled, the messages still queued are flushed and
+    /// the service returns.
+    pub async fn message_service(&self, shutdown: &CancellationToken) -> Result<(), TunnelError> {
         loop {
-            let mut message = None;
+            let notified = self.sender.shared.notify.notified();
 
-            for i in 0..self.queue.len() {
-                let mut queue = self.queue[i].lock().await;
-                if queue.len() > 0 {
-                    message = queue.pop_front();
-                    break;
-                }
-            }
-
-            if let Some(message) = message {
+            if let Some(message) = self.sender.pop() {
                 self.tunnel.send(&message).await?;
+            } else if shutdown.is_cancelled() {
+                return Ok(());
             } else {
-                self.notify.notified().await;
+                tokio::select! {
+                    _ = notified => {}
+                    _ = shutdown.cancelled() => {}
+                }
             }
         }
     }
@@ -73,35 +529,324 @@ where
 
 #[cfg(test)]
 mod test {
-    use crate::{tls_provider::MockTls, tunnel::Tunnel};
-    use super::MessageQueue;
-    use std::sync::Arc;
+    use super::{MessageQueue, OverflowPolicy, PushStatus, QueueError, QueueLimits};
+    use crate::{
+        common::{DRR_QUANTUM, Priority, Scheduler},
+        tls_provider::MockTls,
+        tunnel::Tunnel,
+    };
+    use std::{sync::Arc, time::Duration};
     use testutil::DynResult;
-    use tokio::{io::simplex, sync::Mutex};
+    use tokio::{
+        io::{ReadHalf, SimplexStream, WriteHalf, simplex},
+        time::timeout,
+    };
+    use tokio_util::sync::CancellationToken;
+
+    type TestTunnel = Tunnel<ReadHalf<SimplexStream>, WriteHalf<SimplexStream>, MockTls>;
 
     #[tokio::test]
     async fn message_queue() -> DynResult<()> {
         let (r, w) = simplex(usize::MAX);
 
-        let tunnel = Arc::new(Tunnel {
-            r: Mutex::new(r),
-            w: Mutex::new(w),
-            tls: MockTls {},
-        });
+        let tunnel = Arc::new(Tunnel::new(r, w, MockTls));
 
-        let message_queue = MessageQueue::new(Arc::clone(&tunnel), 3);
+        let message_queue = MessageQueue::new(Arc::clone(&tunnel));
 
-        message_queue.push_message(vec![1, 2, 3], 0).await;
-        message_queue.push_message(vec![3, 4, 5], 2).await;
-        message_queue.push_message(vec![2, 3, 4], 1).await;
+        message_queue
+            .push_message(vec![1, 2, 3], Priority::Control)
+            .await?;
+        message_queue
+            .push_message(vec![3, 4, 5], Priority::Bulk)
+            .await?;
+        message_queue
+            .push_message(vec![2, 3, 4], Priority::Interactive)
+            .await?;
 
         tokio::spawn(async move {
-            message_queue.message_service().await.unwrap();
+            message_queue
+                .message_service(&CancellationToken::new())
+                .await
+                .unwrap();
+        });
+
+        assert_eq!(&tunnel.recv().await?[..], [1, 2, 3]);
+        assert_eq!(&tunnel.recv().await?[..], [2, 3, 4]);
+        assert_eq!(&tunnel.recv().await?[..], [3, 4, 5]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown_flushes() -> DynResult<()> {
+        let (r, w) = simplex(usize::MAX);
+        let tunnel = Arc::new(Tunnel::new(r, w, MockTls));
+        let queue = Arc::new(MessageQueue::new(Arc::clone(&tunnel)));
+        let shutdown = CancellationToken::new();
+
+        let service = tokio::spawn({
+            let queue = Arc::clone(&queue);
+            let shutdown = shutdown.clone();
+            async move { queue.message_service(&shutdown).await }
+        });
+
+        // The service keeps running until cancelled.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!service.is_finished());
+
+        queue.push_message(vec![1], Priority::Bulk).await?;
+        queue.push_message(vec![2], Priority::Bulk).await?;
+        shutdown.cancel();
+
+        // Messages queued before the cancellation are flushed.
+        timeout(Duration::from_secs(1), service).await???;
+        assert_eq!(&tunnel.recv().await?[..], [1]);
+        assert_eq!(&tunnel.recv().await?[..], [2]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fair_service() -> DynResult<()> {
+        let (r, w) = simplex(usize::MAX);
+        let tunnel = Arc::new(Tunnel::new(r, w, MockTls));
+        let queue =
+            MessageQueue::new(Arc::clone(&tunnel)).with_scheduler(Scheduler::DeficitRoundRobin {
+                weights: vec![3, 1],
+            });
+
+        // A transfer on the control level is queued before a single
+        // interactive message.
+        for _ in 0..64 {
+            queue
+                .push_message(vec![0; DRR_QUANTUM], Priority::Control)
+                .await?;
+        }
+        queue.push_message(vec![1], Priority::Interactive).await?;
+
+        tokio::spawn(async move { queue.message_service(&CancellationToken::new()).await });
+
+        let mut position = 0;
+        while tunnel.recv().await?[..] != [1] {
+            position += 1;
+        }
+        assert_eq!(position, 3);
+
+        Ok(())
+    }
+
+    fn limited_queue(
+        overflow: OverflowPolicy,
+    ) -> (
+        Arc<TestTunnel>,
+        MessageQueue<ReadHalf<SimplexStream>, WriteHalf<SimplexStream>, MockTls>,
+    ) {
+        let (r, w) = simplex(usize::MAX);
+        let tunnel = Arc::new(Tunnel::new(r, w, MockTls));
+        let queue = MessageQueue::new(Arc::clone(&tunnel)).with_limits(QueueLimits {
+            per_queue: 2,
+            total: 3,
+            overflow,
+        });
+
+        (tunnel, queue)
+    }
+
+    #[tokio::test]
+    async fn reject_overflow() -> DynResult<()> {
+        let (_, queue) = limited_queue(OverflowPolicy::Reject);
+
+        assert_eq!(
+            queue.push_message(vec![0], Priority::Control).await?,
+            PushStatus::Queued
+        );
+        assert_eq!(
+            queue.push_message(vec![1], Priority::Control).await?,
+            PushStatus::Queued
+        );
+        assert_eq!(
+            queue.push_message(vec![2], Priority::Control).await,
+            Err(QueueError::Full)
+        );
+        assert_eq!(
+            queue
+                .push_message(vec![3, 4], Priority::Interactive)
+                .await?,
+            PushStatus::Queued
+        );
+        assert_eq!(
+            queue.push_message(vec![5], Priority::Interactive).await,
+            Err(QueueError::Full)
+        );
+
+        let metrics = queue.metrics();
+        assert_eq!(metrics.depth, [2, 1, 0]);
+        assert_eq!(metrics.bytes, [2, 2, 0]);
+        assert_eq!(metrics.total_depth(), 3);
+        assert_eq!(metrics.rejected, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_overflow() -> DynResult<()> {
+        let (tunnel, queue) = limited_queue(OverflowPolicy::DropOldest);
+
+        queue
+            .sender()
+            .push_droppable(vec![0], Priority::Control)
+            .await?;
+        queue
+            .sender()
+            .push_droppable(vec![1], Priority::Control)
+            .await?;
+        assert_eq!(
+            queue
+                .sender()
+                .push_droppable(vec![2], Priority::Control)
+                .await?,
+            PushStatus::DroppedOldest
+        );
+        queue
+            .sender()
+            .push_droppable(vec![3], Priority::Bulk)
+            .await?;
+        // The total limit is reached while the interactive level is empty, so
+        // the oldest message of the lowest priority level is dropped.
+        assert_eq!(
+            queue
+                .sender()
+                .push_droppable(vec![4], Priority::Interactive)
+                .await?,
+            PushStatus::DroppedOldest
+        );
+        assert_eq!(queue.metrics().dropped, 2);
+
+        tokio::spawn(async move { queue.message_service(&CancellationToken::new()).await });
+
+        assert_eq!(&tunnel.recv().await?[..], [1]);
+        assert_eq!(&tunnel.recv().await?[..], [2]);
+        assert_eq!(&tunnel.recv().await?[..], [4]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_undroppable() -> DynResult<()> {
+        let (tunnel, queue) = limited_queue(OverflowPolicy::DropOldest);
+        let sender = queue.sender();
+
+        // E.g. stream data around a datagram.
+        sender.push_message(vec![0], Priority::Bulk).await?;
+        sender.push_droppable(vec![1], Priority::Bulk).await?;
+        assert_eq!(
+            sender.push_message(vec![2], Priority::Bulk).await?,
+            PushStatus::DroppedOldest
+        );
+        assert_eq!(
+            sender.push_droppable(vec![3], Priority::Bulk).await?,
+            PushStatus::Dropped
+        );
+        assert_eq!(
+            sender.try_push_message(vec![4], Priority::Bulk),
+            Err(QueueError::Full)
+        );
+
+        // Without droppable messages left, the push waits for capacity.
+        let blocked = tokio::spawn({
+            let sender = sender.clone();
+            async move { sender.push_message(vec![5], Priority::Bulk).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!blocked.is_finished());
+        assert_eq!(sender.metrics().dropped, 2);
+
+        tokio::spawn(async move { queue.message_service(&CancellationToken::new()).await });
+
+        assert_eq!(blocked.await??, PushStatus::Throttled);
+        for i in [0, 2, 5] {
+            assert_eq!(&tunnel.recv().await?[..], [i]);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wait_overflow() -> DynResult<()> {
+        let (tunnel, queue) = limited_queue(OverflowPolicy::Wait);
+        let sender = queue.sender();
+
+        sender.push_message(vec![0], Priority::Control).await?;
+        sender.push_message(vec![1], Priority::Control).await?;
+
+        let blocked = tokio::spawn({
+            let sender = sender.clone();
+            async move { sender.push_message(vec![2], Priority::Control).await }
         });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!blocked.is_finished());
+        assert_eq!(sender.metrics().throttled, 1);
+
+        tokio::spawn(async move { queue.message_service(&CancellationToken::new()).await });
+
+        assert_eq!(blocked.await??, PushStatus::Throttled);
+        for i in 0..3 {
+            assert_eq!(&tunnel.recv().await?[..], [i]);
+        }
+        assert_eq!(sender.metrics().total_depth(), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn try_push_never_waits() -> DynResult<()> {
+        let (_, queue) = limited_queue(OverflowPolicy::Wait);
+        let sender = queue.sender();
 
-        assert_eq!(tunnel.recv().await?, [1, 2, 3]);
-        assert_eq!(tunnel.recv().await?, [2, 3, 4]);
-        assert_eq!(tunnel.recv().await?, [3, 4, 5]);
+        sender.try_push_message(vec![0], Priority::Control)?;
+        sender.try_push_message(vec![1], Priority::Control)?;
+        assert_eq!(
+            sender.try_push_message(vec![2], Priority::Control),
+            Err(QueueError::Full)
+        );
+        assert_eq!(
+            sender.try_push_message(vec![3], Priority::Bulk)?,
+            PushStatus::Queued
+        );
+
+        let metrics = sender.metrics();
+        assert_eq!(metrics.depth, [2, 0, 1]);
+        assert_eq!((metrics.rejected, metrics.throttled), (1, 0));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn credited_messages_bypass_limits() -> DynResult<()> {
+        let (tunnel, queue) = limited_queue(OverflowPolicy::Reject);
+        let sender = queue.sender();
+
+        for i in 0..8 {
+            sender.push_credited(vec![i], Priority::Control);
+        }
+        // The limits still leave room for other messages.
+        sender.try_push_message(vec![8], Priority::Control)?;
+        sender.try_push_message(vec![9], Priority::Control)?;
+        assert_eq!(
+            sender.try_push_message(vec![10], Priority::Control),
+            Err(QueueError::Full)
+        );
+        assert_eq!(queue.metrics().depth, [10, 0, 0]);
+
+        tokio::spawn(async move {
+            queue
+                .message_service(&CancellationToken::new())
+                .await
+                .unwrap();
+        });
+        for i in 0..10 {
+            assert_eq!(&tunnel.recv().await?[..], [i]);
+        }
 
         Ok(())
     }
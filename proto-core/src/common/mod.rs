@@ -1,3 +1,15 @@
+mod flow_control;
+mod keepalive;
 mod message_queue;
+mod priority;
+mod scheduler;
 
-pub use message_queue::MessageQueue;
+pub use flow_control::{
+    FlowControlError, INITIAL_WINDOW_SIZE, MAX_DATA_CHUNK, RecvWindow, SendWindow,
+};
+pub use keepalive::{KeepaliveMonitor, KeepalivePolicy, KeepaliveStatus};
+pub use message_queue::{
+    MessageQueue, MessageSender, OverflowPolicy, PushStatus, QueueError, QueueLimits, QueueMetrics,
+};
+pub use priority::{Priority, PriorityHints};
+pub use scheduler::{DRR_QUANTUM, Scheduler};
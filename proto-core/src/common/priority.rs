@@ -0,0 +1,88 @@
+use crate::sub_protocol::ContentType;
+use std::collections::HashMap;
+
+/// Priority class of a queued message. Each class is a level of the
+/// [`MessageQueue`](super::MessageQueue), served in declaration order by
+/// [`Scheduler::Strict`](super::Scheduler::Strict).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// Commands, responses, alerts, events and keepalives.
+    Control = 0,
+    /// Latency-sensitive application data, e.g. interactive shells.
+    Interactive = 1,
+    /// Throughput-oriented application data, e.g. file transfers.
+    Bulk = 2,
+}
+
+impl Priority {
+    /// Number of priority classes.
+    pub const COUNT: usize = 3;
+
+    /// All priority classes in declaration order.
+    pub const ALL: [Priority; Priority::COUNT] =
+        [Priority::Control, Priority::Interactive, Priority::Bulk];
+
+    /// Index of the class in the queue levels, metrics and scheduler weights.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl From<ContentType> for Priority {
+    /// Default class of a content type. Application data is interactive unless
+    /// a [`PriorityHints`] entry says otherwise.
+    fn from(content_type: ContentType) -> Self {
+        match content_type {
+            ContentType::Alert
+            | ContentType::Cmd
+            | ContentType::CmdResponse
+            | ContentType::Event
+            | ContentType::Keepalive => Priority::Control,
+            ContentType::ApplicationData => Priority::Interactive,
+        }
+    }
+}
+
+/// Per-port priority classes of application data.
+///
+/// All payloads of a relayed connection, including its setup, window updates
+/// and termination, share one class, so they are never reordered.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PriorityHints {
+    /// Class of the connections to each shared port. Ports without an entry
+    /// are [`Priority::Interactive`].
+    pub ports: HashMap<u16, Priority>,
+}
+
+impl PriorityHints {
+    /// Class of the application data of connections to `port`.
+    pub fn for_port(&self, port: u16) -> Priority {
+        self.ports
+            .get(&port)
+            .copied()
+            .unwrap_or(Priority::from(ContentType::ApplicationData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Priority, PriorityHints};
+    use crate::sub_protocol::ContentType;
+
+    #[test]
+    fn default_mapping() {
+        assert_eq!(Priority::from(ContentType::Cmd), Priority::Control);
+        assert_eq!(Priority::from(ContentType::Alert), Priority::Control);
+        assert_eq!(Priority::from(ContentType::Event), Priority::Control);
+        assert_eq!(
+            Priority::from(ContentType::ApplicationData),
+            Priority::Interactive
+        );
+
+        let hints = PriorityHints {
+            ports: [(873, Priority::Bulk)].into(),
+        };
+        assert_eq!(hints.for_port(873), Priority::Bulk);
+        assert_eq!(hints.for_port(22), Priority::Interactive);
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+
+/// Bytes credited to a priority level of weight one per round of
+/// [`Scheduler::DeficitRoundRobin`].
+pub const DRR_QUANTUM: usize = 16 * 1024;
+
+/// Order in which a [`MessageQueue`](super::MessageQueue) sends the messages
+/// of its priority levels.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Scheduler {
+    /// Always sends from the lowest-indexed non-empty level first. A busy
+    /// level starves all levels behind it.
+    #[default]
+    Strict,
+    /// Deficit round robin. Each round, a level may send up to its weight
+    /// times [`DRR_QUANTUM`] bytes, so every non-empty level receives at
+    /// least a share of `weight / sum(weights)` of the tunnel bandwidth.
+    ///
+    /// Weights are indexed by [`Priority::index`](super::Priority::index).
+    /// Levels without a weight, or with a weight of zero, have a weight of
+    /// one.
+    DeficitRoundRobin { weights: Vec<u32> },
+}
+
+/// Scheduling state of a message queue.
+pub(crate) struct SchedulerState {
+    scheduler: Scheduler,
+    /// Bytes each level may still send in the current round.
+    deficit: Vec<usize>,
+    /// Level currently served by the round robin.
+    cursor: usize,
+    /// Whether the current level was credited its quantum in this round.
+    credited: bool,
+}
+
+impl SchedulerState {
+    pub(crate) fn new(scheduler: Scheduler, queue_count: usize) -> SchedulerState {
+        SchedulerState {
+            scheduler,
+            deficit: vec![0; queue_count],
+            cursor: 0,
+            credited: false,
+        }
+    }
+
+    fn quantum(&self, level: usize) -> usize {
+        let weight = match &self.scheduler {
+            Scheduler::DeficitRoundRobin { weights } => weights.get(level).copied().unwrap_or(1),
+            Scheduler::Strict => 1,
+        };
+
+        weight.max(1) as usize * DRR_QUANTUM
+    }
+
+    /// Returns the level whose front message is sent next.
+    pub(crate) fn next<T: AsRef<[u8]>>(&mut self, queue: &[VecDeque<T>]) -> Option<usize> {
+        if let Scheduler::Strict = self.scheduler {
+            return (0..queue.len()).find(|&i| !queue[i].is_empty());
+        }
+
+        if queue.iter().all(VecDeque::is_empty) {
+            return None;
+        }
+
+        loop {
+            let level = self.cursor;
+
+            match queue[level].front() {
+                Some(front) if self.deficit[level] >= front.as_ref().len() => {
+                    self.deficit[level] -= front.as_ref().len();
+
+                    // Empty levels do not save credit for later rounds.
+                    if queue[level].len() == 1 {
+                        self.deficit[level] = 0;
+                        self.advance(queue.len());
+                    }
+
+                    return Some(level);
+                }
+                Some(_) if !self.credited => {
+                    self.deficit[level] += self.quantum(level);
+                    self.credited = true;
+                }
+                Some(_) => self.advance(queue.len()),
+                None => {
+                    self.deficit[level] = 0;
+                    self.advance(queue.len());
+                }
+            }
+        }
+    }
+
+    fn advance(&mut self, queue_count: usize) {
+        self.cursor = (self.cursor + 1) % queue_count;
+        self.credited = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DRR_QUANTUM, Scheduler, SchedulerState};
+    use std::collections::VecDeque;
+
+    /// Fills every level with messages of the given size and returns the
+    /// number of messages sent per level within the first `count` picks.
+    fn serve(scheduler: Scheduler, sizes: &[usize], count: usize) -> Vec<usize> {
+        let mut queue: Vec<VecDeque<Vec<u8>>> = sizes
+            .iter()
+            .map(|&size| (0..1000).map(|_| vec![0; size]).collect())
+            .collect();
+        let mut state = SchedulerState::new(scheduler, queue.len());
+
+        let mut sent = vec![0; queue.len()];
+        for _ in 0..count {
+            let level = state.next(&queue).unwrap();
+            queue[level].pop_front();
+            sent[level] += 1;
+        }
+
+        sent
+    }
+
+    #[test]
+    fn strict_starves() {
+        assert_eq!(
+            serve(Scheduler::Strict, &[1024, 1024, 1024], 300),
+            [300, 0, 0]
+        );
+    }
+
+    #[test]
+    fn deficit_round_robin_shares() {
+        let sent = serve(
+            Scheduler::DeficitRoundRobin {
+                weights: vec![4, 2, 1],
+            },
+            &[1024; 3],
+            7 * 16 * 10,
+        );
+
+        assert_eq!(sent, [640, 320, 160]);
+    }
+
+    #[test]
+    fn no_class_starves() {
+        // Large messages on the first level do not starve the small ones on
+        // the other levels, and levels without weight get a weight of one.
+        let sent = serve(
+            Scheduler::DeficitRoundRobin {
+                weights: vec![8, 0],
+            },
+            &[DRR_QUANTUM * 2, 512, 64],
+            200,
+        );
+
+        assert!(sent.iter().all(|&count| count > 0), "{sent:?}");
+    }
+
+    #[test]
+    fn empty_levels() {
+        let mut state = SchedulerState::new(Scheduler::DeficitRoundRobin { weights: vec![] }, 3);
+        let mut queue = vec![VecDeque::new(), VecDeque::new(), VecDeque::new()];
+
+        assert_eq!(state.next(&queue), None);
+
+        queue[2].push_back(vec![0; DRR_QUANTUM * 3]);
+        assert_eq!(state.next(&queue), Some(2));
+    }
+}
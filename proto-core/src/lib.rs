@@ -5,6 +5,7 @@
 //!
 //! Modules:
 //! - [`token`]: Authentication token structures and scopes.
+//! - [`codec`]: Framing codecs of the handshake and the tunnel.
 //! - [`common`]: Basic connection utilities.
 //! - [`sub_protocol`]: Definitions for handshake, command, and data payloads.
 //! - [`algorithms`]: Supported encryption and signature algorithms.
@@ -16,6 +17,7 @@
 mod macros;
 
 pub mod algorithms;
+pub mod codec;
 pub mod common;
 pub mod sub_protocol;
 pub mod tls_provider;
@@ -16,4 +16,7 @@ pub enum Alert {
     AlreadyConnected,
     /// The same token was used from a different device.
     LoggedInFromAnotherComputer,
+    /// The server is shutting down. Relayed connections of the client are
+    /// terminated.
+    ServerShutdown,
 }
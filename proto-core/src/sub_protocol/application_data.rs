@@ -3,9 +3,24 @@
 //! Represents tunneled data flowing through established connections, including
 //! initial connection requests, raw application data, and connection
 //! termination messages.
+//!
+//! Besides stream connections, clients relay datagram flows. A flow is
+//! identified by its connection ID like a connection, but carries
+//! [`ApplicationDataEnum::Datagram`] payloads, which preserve message
+//! boundaries and are neither acknowledged nor flow-controlled.
 
 use serde::{Deserialize, Serialize};
 
+/// Bit set in connection IDs assigned by the server.
+///
+/// Port-requesting clients choose IDs with this bit cleared, so IDs of
+/// requested and shared connections never collide within a client.
+pub const SERVER_ASSIGNED_ID: u64 = 1 << 63;
+
+/// Maximum size of a [`ApplicationDataEnum::Datagram`] payload, the largest
+/// UDP payload over IPv4.
+pub const MAX_DATAGRAM_SIZE: usize = 65_507;
+
 /// Definitions for transmitting application data at the protocol layer.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ApplicationDataEnum {
@@ -26,11 +41,100 @@ pub enum ApplicationDataEnum {
     Connection { accept: bool },
 
     /// Application-layer data sent over a tunneled connection.
+    ///
+    /// The sender may only send as many bytes as the receiver's window allows,
+    /// starting from [`INITIAL_WINDOW_SIZE`](crate::common::INITIAL_WINDOW_SIZE).
     Data { payload: Vec<u8> },
 
-    /// Payload indicating that the connection has been terminated.
-    /// Can be sent by either the port-requesting or port-sharing client.
-    TerminateConnection { reason: Option<String> },
+    /// Grants the peer `increment` more bytes of
+    /// [`ApplicationDataEnum::Data`] after the receiver consumed them.
+    WindowUpdate { increment: u32 },
+
+    /// Payload indicating that the connection has been terminated in both
+    /// directions. Can be sent by either the port-requesting or port-sharing
+    /// client, or by the server.
+    ///
+    /// A port-sharing client may also send it instead of
+    /// [`ApplicationDataEnum::Connection`] to refuse a connection with a
+    /// reason.
+    TerminateConnection {
+        reason: TerminateReason,
+        /// Human-readable context, e.g. the error of the local socket.
+        detail: Option<String>,
+    },
+
+    /// The sender will send no more [`ApplicationDataEnum::Data`], while it
+    /// keeps receiving data from the peer, like a TCP half-close.
+    ///
+    /// The connection is closed once both clients sent this payload.
+    Eof,
+
+    /// Message sent from the client to open a datagram flow to a specific
+    /// port on a remote host. The connection ID identifies the flow.
+    ///
+    /// Unlike connections, flows are not accepted: datagrams may follow the
+    /// request right away, and the port-sharing client refuses the flow with
+    /// [`ApplicationDataEnum::TerminateConnection`].
+    RequestFlow { token_id: u64, port: u16 },
+
+    /// Message sent by the server to the port-sharing client to open a
+    /// datagram flow, like [`ApplicationDataEnum::NewConnection`].
+    NewFlow { port: u16 },
+
+    /// A datagram of a flow, at most [`MAX_DATAGRAM_SIZE`] bytes.
+    ///
+    /// Flows are terminated with [`ApplicationDataEnum::TerminateConnection`],
+    /// e.g. with [`TerminateReason::IdleTimeout`] once no datagram was
+    /// exchanged for a while.
+    Datagram { payload: Vec<u8> },
+}
+
+/// Cause of an [`ApplicationDataEnum::TerminateConnection`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TerminateReason {
+    /// The connection was closed without an error, e.g. the stream was
+    /// dropped before its end.
+    Closed,
+    /// The port-sharing client could not connect to the shared port.
+    RemoteRefused,
+    /// The port is not shared by the port-sharing client.
+    PortNotShared,
+    /// The token of the port-requesting client may not request the port.
+    PermissionDenied,
+    /// The other client is not connected, or disconnected.
+    PeerDisconnected,
+    /// The connection or flow was idle for too long.
+    IdleTimeout,
+    /// A client sent more data than the window of its peer allows.
+    FlowControlViolation,
+    /// A client sent a payload that is invalid for the connection.
+    ProtocolViolation,
+    /// The local socket of a client failed, or its connection to the server
+    /// was lost.
+    LocalError,
+    /// The client or the server is shutting down.
+    Shutdown,
+    /// One of the clients already relays as many connections as the server
+    /// allows.
+    TooManyConnections,
+}
+
+impl std::fmt::Display for TerminateReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Closed => write!(f, "closed"),
+            Self::RemoteRefused => write!(f, "remote refused"),
+            Self::PortNotShared => write!(f, "port not shared"),
+            Self::PermissionDenied => write!(f, "permission denied"),
+            Self::PeerDisconnected => write!(f, "peer disconnected"),
+            Self::IdleTimeout => write!(f, "idle timeout"),
+            Self::FlowControlViolation => write!(f, "flow control violation"),
+            Self::ProtocolViolation => write!(f, "protocol violation"),
+            Self::LocalError => write!(f, "local error"),
+            Self::Shutdown => write!(f, "shutdown"),
+            Self::TooManyConnections => write!(f, "too many connections"),
+        }
+    }
 }
 
 /// Payload structure wrapping a application data message with an identifier.
@@ -10,7 +10,7 @@
 use serde::{Deserialize, Serialize};
 
 /// Represents a connected client.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Client {
     pub name: String,
     pub tags: Vec<String>,
@@ -4,25 +4,36 @@
 //! symmetric encryption mechanism. Nodes and servers are assumed to already
 //! share knowledge of the symmetric encryption key (e.g., AES).
 //!
-//! All handshake payloads follow the structure:
+//! All handshake payloads follow the structure, see
+//! [`HandshakeCodec`]:
 //! ```text
 //! bytes
 //!  0, 1   content_length
 //!     2   handshake_content_type
 //!   3..   payload data
 //! ```
+//!
+//! Handshakes are unauthenticated, so both sides guard them with
+//! [`HandshakeLimits`]: payloads larger than their content type allows are
+//! rejected from the header, and handshakes exceeding the timeout are
+//! aborted.
 
+use bytes::BytesMut;
 use serde::{Deserialize, Serialize};
-use std::ops::RangeInclusive;
+use std::{ops::RangeInclusive, time::Duration};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Decoder, Encoder};
 
-use crate::algorithms::{EncryptionAlgorithm, SignatureAlgorithm};
+use crate::{
+    algorithms::{EncryptionAlgorithm, SignatureAlgorithm},
+    codec::{HANDSHAKE_HEADER_LEN, HandshakeCodec},
+};
 
 /// Content type of the handshake payloads.
 ///
 /// Currently, these handshake layers are implemented for symmetric encryption.
 /// The protocol is subject to change with future asymmetric encryption support.
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum HandshakeContentType {
     /// Alert, warning, or error message.
     HandshakeAlert = 0,
@@ -87,6 +98,90 @@ pub enum HandshakeAlert {
     ///
     /// All encoding and decoding errors are converted into this type.
     InvalidPayload,
+
+    /// The payload exceeds the maximum size of its content type.
+    PayloadTooLarge {
+        content_type: HandshakeContentType,
+        max_size: u16,
+    },
+    /// The handshake did not complete within the timeout.
+    Timeout,
+    /// Too many handshakes from the same source address are in progress.
+    TooManyHandshakes,
+}
+
+/// Maximum payload size of each [`HandshakeContentType`], in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadLimits {
+    pub handshake_alert: u16,
+    pub client_hello: u16,
+    pub server_hello: u16,
+    pub finished: u16,
+}
+
+impl PayloadLimits {
+    /// No limit besides the [`u16`] content length.
+    pub const MAX: PayloadLimits = PayloadLimits {
+        handshake_alert: u16::MAX,
+        client_hello: u16::MAX,
+        server_hello: u16::MAX,
+        finished: u16::MAX,
+    };
+
+    /// Maximum payload size of `content_type`.
+    pub fn max_size(&self, content_type: HandshakeContentType) -> u16 {
+        match content_type {
+            HandshakeContentType::HandshakeAlert => self.handshake_alert,
+            HandshakeContentType::ClientHello => self.client_hello,
+            HandshakeContentType::ServerHello => self.server_hello,
+            HandshakeContentType::Finished => self.finished,
+        }
+    }
+}
+
+impl Default for PayloadLimits {
+    /// 1 KiB for alerts, 256 bytes for the other content types, well above
+    /// the size of their encoded payloads.
+    fn default() -> Self {
+        PayloadLimits {
+            handshake_alert: 1024,
+            client_hello: 256,
+            server_hello: 256,
+            finished: 256,
+        }
+    }
+}
+
+/// Limits guarding a handshake against oversized and slow peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandshakeLimits {
+    /// Time the whole handshake may take. Servers allow the same time for
+    /// the authentication following it.
+    pub timeout: Duration,
+    /// Maximum payload size of each content type.
+    pub payload: PayloadLimits,
+    /// Maximum number of handshakes in progress from a single source IP,
+    /// including connections that are not yet authenticated. Only enforced
+    /// by servers.
+    pub max_pending_per_ip: usize,
+    /// Maximum size of the frames received through the tunnel until the
+    /// authentication succeeded, after which it is raised to
+    /// [`MAX_FRAME_SIZE`](crate::tunnel::MAX_FRAME_SIZE).
+    pub max_pre_auth_frame_size: usize,
+}
+
+impl Default for HandshakeLimits {
+    /// A 10 second timeout, the default [`PayloadLimits`], 8 pending
+    /// handshakes per source IP and 64 KiB frames before the authentication,
+    /// well above the size of a signed token.
+    fn default() -> Self {
+        HandshakeLimits {
+            timeout: Duration::from_secs(10),
+            payload: PayloadLimits::default(),
+            max_pending_per_ip: 8,
+            max_pre_auth_frame_size: 64 * 1024,
+        }
+    }
 }
 
 /// Initial payload sent by the client.
@@ -112,18 +207,28 @@ pub struct Finished {
 }
 
 /// Reads a handshake payload from the TCP stream.
+///
+/// Exactly one frame is read, so the bytes following the handshake stay in
+/// the stream for the tunnel. The header is validated against `limits` before
+/// the payload is allocated.
 pub async fn read_handshake_payload<R: Unpin + AsyncRead>(
     r: &mut R,
-) -> Result<(HandshakeContentType, Vec<u8>), HandshakeAlert> {
-    let content_length = r.read_u16().await?;
-
-    let handshake_content_type = r.read_u8().await?;
-    let handshake_content_type = HandshakeContentType::try_from(handshake_content_type)?;
-
-    let mut payload = vec![0; content_length as usize];
-    r.read_exact(&mut payload).await?;
-
-    Ok((handshake_content_type, payload))
+    limits: &PayloadLimits,
+) -> Result<(HandshakeContentType, BytesMut), HandshakeAlert> {
+    let mut codec = HandshakeCodec::new(*limits);
+
+    let mut buf = BytesMut::zeroed(HANDSHAKE_HEADER_LEN);
+    r.read_exact(&mut buf).await?;
+
+    let frame_len = codec
+        .frame_len(&buf)?
+        .ok_or(HandshakeAlert::InvalidPayload)?;
+    buf.resize(frame_len, 0);
+    r.read_exact(&mut buf[HANDSHAKE_HEADER_LEN..]).await?;
+
+    codec
+        .decode(&mut buf)?
+        .ok_or(HandshakeAlert::InvalidPayload)
 }
 
 /// Writes the handshake payload to the TCP stream.
@@ -132,9 +237,9 @@ pub async fn write_handshake_payload<W: Unpin + AsyncWrite>(
     handshake_content_type: HandshakeContentType,
     payload: &[u8],
 ) -> Result<(), HandshakeAlert> {
-    w.write_u16(payload.len() as u16).await?;
-    w.write_u8(handshake_content_type as u8).await?;
-    w.write_all(payload).await?;
+    let mut buf = BytesMut::new();
+    HandshakeCodec::new(PayloadLimits::MAX).encode((handshake_content_type, payload), &mut buf)?;
+    w.write_all(&buf).await?;
 
     Ok(())
 }
@@ -0,0 +1,17 @@
+//! Keepalive messages exchanged by both peers.
+//!
+//! Each peer periodically sends a [`Keepalive::Ping`], which the other peer
+//! answers with a [`Keepalive::Pong`] carrying the same ID. Unanswered pings
+//! reveal half-open connections, and answered ones measure the round-trip
+//! time.
+
+use serde::{Deserialize, Serialize};
+
+/// Ping or pong message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Keepalive {
+    /// Requests a [`Keepalive::Pong`] with the same ID.
+    Ping { id: u64 },
+    /// Answers the [`Keepalive::Ping`] with the same ID.
+    Pong { id: u64 },
+}
@@ -1,7 +1,16 @@
 //! Message type definitions used across the protocol layers.
 //!
 //! High-level message types exchanged between peers, including handshake,
-//! commands, application data, and alerts are defined in this module.
+//! commands, application data, alerts and keepalives are defined in this
+//! module.
+//!
+//! Every message sent over the tunnel after a successful handshake follows
+//! the structure:
+//! ```text
+//! bytes
+//!     0   content_type
+//!   1..   bincode encoded payload
+//! ```
 
 pub mod alert;
 pub mod application_data;
@@ -9,13 +18,174 @@ pub mod cmd;
 pub mod cmd_response;
 pub mod event;
 pub mod handshake;
+pub mod keepalive;
+
+use bincode::error::{DecodeError, EncodeError};
+use serde::Serialize;
 
 /// Identifies the type of protocol message contained in a payload.
 /// Used to route and deserialize messages correctly based on their category.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ContentType {
     Alert = 0,
     ApplicationData = 1,
     Cmd = 2,
     CmdResponse = 3,
     Event = 4,
+    Keepalive = 5,
+}
+
+impl std::convert::TryFrom<u8> for ContentType {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Alert),
+            1 => Ok(Self::ApplicationData),
+            2 => Ok(Self::Cmd),
+            3 => Ok(Self::CmdResponse),
+            4 => Ok(Self::Event),
+            5 => Ok(Self::Keepalive),
+            _ => Err(DecodeError::Other("unknown content type")),
+        }
+    }
+}
+
+/// A message exchanged over the tunnel, tagged by its [`ContentType`].
+#[derive(Debug)]
+pub enum Message {
+    Alert(alert::Alert),
+    ApplicationData(application_data::ApplicationData),
+    Cmd(cmd::Cmd),
+    CmdResponse(cmd_response::CmdResponse),
+    Event(event::Event),
+    Keepalive(keepalive::Keepalive),
+}
+
+impl Message {
+    /// Content type of the message.
+    pub fn content_type(&self) -> ContentType {
+        match self {
+            Self::Alert(_) => ContentType::Alert,
+            Self::ApplicationData(_) => ContentType::ApplicationData,
+            Self::Cmd(_) => ContentType::Cmd,
+            Self::CmdResponse(_) => ContentType::CmdResponse,
+            Self::Event(_) => ContentType::Event,
+            Self::Keepalive(_) => ContentType::Keepalive,
+        }
+    }
+
+    /// Whether the message may be dropped from a congested queue without
+    /// breaking the protocol, as keepalives and datagrams may.
+    pub fn is_droppable(&self) -> bool {
+        matches!(
+            self,
+            Self::Keepalive(_)
+                | Self::ApplicationData(application_data::ApplicationData {
+                    payload: application_data::ApplicationDataEnum::Datagram { .. },
+                    ..
+                })
+        )
+    }
+
+    /// Encodes the message into its wire format.
+    pub fn encode(&self) -> Result<Vec<u8>, EncodeError> {
+        fn encode_into<T: Serialize>(buf: &mut Vec<u8>, payload: &T) -> Result<(), EncodeError> {
+            buf.extend(bincode::serde::encode_to_vec(
+                payload,
+                bincode::config::standard(),
+            )?);
+            Ok(())
+        }
+
+        let mut buf = vec![self.content_type() as u8];
+
+        match self {
+            Self::Alert(payload) => encode_into(&mut buf, payload)?,
+            Self::ApplicationData(payload) => encode_into(&mut buf, payload)?,
+            Self::Cmd(payload) => encode_into(&mut buf, payload)?,
+            Self::CmdResponse(payload) => encode_into(&mut buf, payload)?,
+            Self::Event(payload) => encode_into(&mut buf, payload)?,
+            Self::Keepalive(payload) => encode_into(&mut buf, payload)?,
+        }
+
+        Ok(buf)
+    }
+
+    /// Decodes a message from its wire format.
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let (&content_type, payload) = buf
+            .split_first()
+            .ok_or(DecodeError::Other("empty message"))?;
+
+        macro_rules! decode {
+            ($variant:ident) => {
+                Self::$variant(
+                    bincode::serde::decode_from_slice(payload, bincode::config::standard())?.0,
+                )
+            };
+        }
+
+        Ok(match ContentType::try_from(content_type)? {
+            ContentType::Alert => decode!(Alert),
+            ContentType::ApplicationData => decode!(ApplicationData),
+            ContentType::Cmd => decode!(Cmd),
+            ContentType::CmdResponse => decode!(CmdResponse),
+            ContentType::Event => decode!(Event),
+            ContentType::Keepalive => decode!(Keepalive),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ContentType, Message,
+        application_data::{ApplicationData, ApplicationDataEnum},
+        event::Event,
+        keepalive::Keepalive,
+    };
+
+    #[test]
+    fn message_round_trip() {
+        let message = Message::ApplicationData(ApplicationData {
+            connection_id: 7,
+            payload: ApplicationDataEnum::Data {
+                payload: vec![1, 2, 3],
+            },
+        });
+
+        let encoded = message.encode().unwrap();
+        assert_eq!(encoded[0], ContentType::ApplicationData as u8);
+
+        let Message::ApplicationData(ApplicationData {
+            connection_id: 7,
+            payload: ApplicationDataEnum::Data { payload },
+        }) = Message::decode(&encoded).unwrap()
+        else {
+            panic!("Expected Message::ApplicationData");
+        };
+        assert_eq!(payload, [1, 2, 3]);
+
+        let encoded = Message::Event(Event::ListClients(vec![])).encode().unwrap();
+        assert!(matches!(
+            Message::decode(&encoded).unwrap(),
+            Message::Event(Event::ListClients(_))
+        ));
+
+        let encoded = Message::Keepalive(Keepalive::Ping { id: 3 })
+            .encode()
+            .unwrap();
+        assert_eq!(encoded[0], ContentType::Keepalive as u8);
+        assert!(matches!(
+            Message::decode(&encoded).unwrap(),
+            Message::Keepalive(Keepalive::Ping { id: 3 })
+        ));
+    }
+
+    #[test]
+    fn unknown_content_type() {
+        assert!(Message::decode(&[255, 0]).is_err());
+        assert!(Message::decode(&[]).is_err());
+    }
 }
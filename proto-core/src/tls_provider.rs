@@ -4,35 +4,128 @@
 //! A successful handshake returns an implementation of [`TlsProvider`].
 //! This is used by application and VPN layers to secure traffic.
 
-/// Provides encryption and decryption methods used after a successful handshake.
+use bytes::BytesMut;
+
+/// Provides encryption and decryption used after a successful handshake.
 /// This trait abstracts over the secure channel implementation
 /// (e.g., symmetric or asymmetric encryption).
 ///
-/// The [`TlsProvider`] implementation may include internal mutability, but it
-/// must be thread-safe, typically achieved through synchronization primitives
-/// such as [`std::sync::Mutex`] or [`std::sync::RwLock`].
+/// The provider is split into its sending and receiving directions, so each
+/// half of a [`Tunnel`](crate::tunnel::Tunnel) owns its own cipher state.
+/// Records are numbered by the record layer owning the direction, and only
+/// rekeying mutates a direction. Both happen under the same borrow, so a key
+/// update cannot interleave with a record.
 pub trait TlsProvider {
+    /// Sending direction.
+    type Encrypter: Encrypter;
+    /// Receiving direction.
+    type Decrypter: Decrypter;
+
+    /// Splits the provider into its independent directions.
+    fn split(self) -> (Self::Encrypter, Self::Decrypter);
+}
+
+/// Sending direction of a [`TlsProvider`].
+pub trait Encrypter {
+    type Error;
+
+    /// Encrypts `buf[offset..]` in place to be safely transmitted over the
+    /// network. The bytes before `offset`, e.g. a frame header, are left
+    /// untouched, and the buffer grows by the encryption overhead.
+    ///
+    /// `seq` is the explicit sequence number of the record. It selects the
+    /// nonce of the record and is authenticated along with the data, so it
+    /// must not repeat for the same traffic keys.
+    fn encrypt(&self, seq: u64, buf: &mut BytesMut, offset: usize) -> Result<(), Self::Error>;
+
+    /// Derives fresh traffic keys from the current ones.
+    ///
+    /// Called by the tunnel right after a key update record is sent.
+    fn rekey(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Receiving direction of a [`TlsProvider`].
+pub trait Decrypter {
     type Error;
 
-    /// Encrypts raw data to be safely transmitted over the network.
-    fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, Self::Error>;
+    /// Decrypts `buf` in place back to its original form.
+    ///
+    /// Fails if `seq` differs from the one passed to [`Encrypter::encrypt`].
+    /// A failure leaves the direction untouched, so later records still
+    /// decrypt.
+    fn decrypt(&self, seq: u64, buf: &mut BytesMut) -> Result<(), Self::Error>;
 
-    /// Decrypts received encrypted data back to its original form.
-    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, Self::Error>;
+    /// Derives fresh traffic keys from the current ones.
+    ///
+    /// Called by the tunnel right after a key update record is received.
+    fn rekey(&mut self) -> Result<(), Self::Error>;
 }
 
+/// Identity "encryption" that XORs data with its key epoch, so rekeying
+/// mismatches are still detected in tests.
 #[cfg(test)]
-pub struct MockTls {}
+#[derive(Default)]
+pub struct MockTls;
+
+/// Either direction of [`MockTls`].
+#[cfg(test)]
+#[derive(Default)]
+pub struct MockDirection {
+    epoch: u8,
+}
+
+#[cfg(test)]
+impl MockDirection {
+    /// Returns the key epoch.
+    pub fn epoch(&self) -> u8 {
+        self.epoch
+    }
+
+    fn xor(&self, buf: &mut [u8]) {
+        buf.iter_mut().for_each(|byte| *byte ^= self.epoch);
+    }
+
+    fn next_epoch(&mut self) {
+        self.epoch = self.epoch.wrapping_add(1);
+    }
+}
 
 #[cfg(test)]
 impl TlsProvider for MockTls {
+    type Encrypter = MockDirection;
+    type Decrypter = MockDirection;
+
+    fn split(self) -> (MockDirection, MockDirection) {
+        Default::default()
+    }
+}
+
+#[cfg(test)]
+impl Encrypter for MockDirection {
+    type Error = ();
+
+    fn encrypt(&self, _seq: u64, buf: &mut BytesMut, offset: usize) -> Result<(), Self::Error> {
+        self.xor(&mut buf[offset..]);
+        Ok(())
+    }
+
+    fn rekey(&mut self) -> Result<(), Self::Error> {
+        self.next_epoch();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Decrypter for MockDirection {
     type Error = ();
 
-    fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, Self::Error> {
-        Ok(Vec::from(data))
+    fn decrypt(&self, _seq: u64, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        self.xor(buf);
+        Ok(())
     }
 
-    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, Self::Error> {
-        Ok(Vec::from(ciphertext))
+    fn rekey(&mut self) -> Result<(), Self::Error> {
+        self.next_epoch();
+        Ok(())
     }
 }
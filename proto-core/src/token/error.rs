@@ -1,19 +1,28 @@
-use bincode::error::EncodeError;
+use bincode::error::{DecodeError, EncodeError};
+use regex::Error as RegexError;
 
 /// Token operations error types.
 #[derive(Debug)]
 pub enum TokenError {
     Encode(EncodeError),
+    Decode(DecodeError),
+    /// The encoded token is followed by further bytes.
+    TrailingBytes,
+    /// The pattern of a [`TagRegex`](super::TagRegex) is invalid.
+    Regex(RegexError),
 }
 
 impl std::fmt::Display for TokenError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Encode(encode_error) => write!(f, "encode: {encode_error}"),
+            Self::Decode(decode_error) => write!(f, "decode: {decode_error}"),
+            Self::TrailingBytes => write!(f, "trailing bytes"),
+            Self::Regex(regex_error) => write!(f, "invalid tag regex: {regex_error}"),
         }
     }
 }
 
 impl std::error::Error for TokenError {}
 
-crate::error_impl_from!(TokenError; Encode);
+crate::error_impl_from!(TokenError; Encode, Decode, Regex);
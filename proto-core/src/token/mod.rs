@@ -17,11 +17,12 @@ mod error;
 pub use error::TokenError;
 
 use crate::algorithms::SignatureAlgorithm;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::ops::RangeInclusive;
 
 /// Node ID Token.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Token {
     /// Subject: Token ID, used for token revocation.
     pub sub: u64,
@@ -45,15 +46,27 @@ pub struct Token {
 }
 
 /// Signed [`Token`] variant.
-#[derive(Debug, Serialize, Deserialize)]
+///
+/// The token is kept encoded, so its signature is checked over the signed
+/// bytes before anything of it is decoded, e.g. the regular expressions of
+/// its tags.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SignedToken {
-    pub token: Token,
+    /// The [`Token`] as encoded by [`Token::encode`].
+    pub token: Vec<u8>,
     pub signature: Vec<u8>,
     pub signature_algorithm: SignatureAlgorithm,
 }
 
+impl SignedToken {
+    /// Decodes the token. The signature should be verified first.
+    pub fn decode(&self) -> Result<Token, TokenError> {
+        Token::decode(&self.token)
+    }
+}
+
 /// Token permissions.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TokenScope {
     /// The lowest permission level. A salvage node can only forward ports.
     ForwardPort,
@@ -71,15 +84,95 @@ pub enum TokenScope {
 }
 
 /// Token tag enum.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TokenTag {
     /// A tag defined by a literal string.
     StringLiteral(String),
     /// A tag defined by a regular expression.
-    Regex(String),
+    Regex(TagRegex),
+}
+
+impl TokenTag {
+    /// Whether the tag matches the node tag. Regular expressions must match
+    /// the whole tag.
+    pub fn matches(&self, tag: &str) -> bool {
+        match self {
+            Self::StringLiteral(literal) => literal == tag,
+            Self::Regex(regex) => regex.regex.is_match(tag),
+        }
+    }
+}
+
+/// Regular expression of a [`TokenTag::Regex`], compiled once when it is
+/// created or deserialized.
+///
+/// It is encoded as its pattern, and tokens with an invalid pattern fail to
+/// decode.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TagRegex {
+    pattern: String,
+    /// The pattern anchored to match whole tags.
+    regex: Regex,
+}
+
+impl TagRegex {
+    pub fn new(pattern: &str) -> Result<TagRegex, TokenError> {
+        Ok(TagRegex {
+            pattern: String::from(pattern),
+            regex: Regex::new(&format!("^(?:{pattern})$"))?,
+        })
+    }
+
+    /// Returns the pattern the expression was created from.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+}
+
+impl TryFrom<String> for TagRegex {
+    type Error = TokenError;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        TagRegex::new(&pattern)
+    }
+}
+
+impl From<TagRegex> for String {
+    fn from(regex: TagRegex) -> Self {
+        regex.pattern
+    }
 }
 
 impl Token {
+    /// Whether the holder of this token may request `port` from the node
+    /// holding `target`.
+    ///
+    /// The target must be allowed to forward ports and must not have a higher
+    /// permission level. A [`TokenScope::RequestPort`] scope must cover the
+    /// port and match one of the target's tags.
+    pub fn can_request_port(&self, target: &Token, port: u16) -> bool {
+        if self.level < target.level
+            || !target
+                .scope
+                .iter()
+                .any(|scope| matches!(scope, TokenScope::ForwardPort))
+        {
+            return false;
+        }
+
+        self.scope.iter().any(|scope| match scope {
+            TokenScope::RequestPort { tags, ports } => {
+                ports.iter().any(|range| range.contains(&port))
+                    && tags
+                        .iter()
+                        .any(|tag| target.tags.iter().any(|target_tag| tag.matches(target_tag)))
+            }
+            TokenScope::Super => true,
+            TokenScope::ForwardPort => false,
+        })
+    }
+
     /// Encode token into binary format. Used in signature verification.
     pub fn encode(&self) -> Result<Vec<u8>, TokenError> {
         Ok(bincode::serde::encode_to_vec(
@@ -87,4 +180,115 @@ impl Token {
             bincode::config::standard(),
         )?)
     }
+
+    /// Decodes a token encoded by [`Token::encode`]. Fails on trailing bytes
+    /// and invalid tag regular expressions.
+    pub fn decode(encoded: &[u8]) -> Result<Token, TokenError> {
+        let (token, len) = bincode::serde::decode_from_slice(encoded, bincode::config::standard())?;
+
+        if len == encoded.len() {
+            Ok(token)
+        } else {
+            Err(TokenError::TrailingBytes)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SignedToken, TagRegex, Token, TokenScope, TokenTag};
+    use crate::algorithms::SignatureAlgorithm;
+
+    fn token(level: u64, tags: &[&str], scope: Vec<TokenScope>) -> Token {
+        Token {
+            sub: 0,
+            iat: 0,
+            exp: u64::MAX,
+            name: String::new(),
+            tags: tags.iter().map(|tag| String::from(*tag)).collect(),
+            scope,
+            level,
+        }
+    }
+
+    #[test]
+    fn tag_matches() {
+        assert!(TokenTag::StringLiteral(String::from("rdp")).matches("rdp"));
+        assert!(!TokenTag::StringLiteral(String::from("rdp")).matches("rdp-1"));
+        let regex = |pattern| TokenTag::Regex(TagRegex::new(pattern).unwrap());
+        assert!(regex("license-.*").matches("license-srv"));
+        assert!(!regex("license").matches("license-srv"));
+        assert!(TagRegex::new("*").is_err());
+    }
+
+    #[test]
+    fn invalid_regex_fails_to_decode() {
+        let config = bincode::config::standard();
+        let encode = |pattern: &str| {
+            bincode::serde::encode_to_vec(TokenTag::StringLiteral(String::from(pattern)), config)
+                .unwrap()
+        };
+        let decode = |mut encoded: Vec<u8>| {
+            // Same layout, with the variant index of `TokenTag::Regex`.
+            encoded[0] = 1;
+            bincode::serde::decode_from_slice::<TokenTag, _>(&encoded, config)
+        };
+
+        let (tag, _) = decode(encode("license-.*")).unwrap();
+        assert!(tag.matches("license-srv"));
+        assert!(decode(encode("*")).is_err());
+    }
+
+    #[test]
+    fn signed_token_is_decoded_on_demand() {
+        let config = bincode::config::standard();
+        let scope = vec![TokenScope::RequestPort {
+            tags: vec![TokenTag::Regex(TagRegex::new("license").unwrap())],
+            ports: vec![27000..=27009],
+        }];
+        let mut encoded = token(1, &[], scope).encode().unwrap();
+        assert!(Token::decode(&encoded).is_ok());
+
+        // Swap in an invalid pattern of the same length.
+        let pattern = encoded
+            .windows(7)
+            .position(|window| window == b"license")
+            .unwrap();
+        encoded[pattern..pattern + 7].copy_from_slice(b"licen(e");
+
+        let signed_token = SignedToken {
+            token: encoded,
+            signature: vec![],
+            signature_algorithm: SignatureAlgorithm::HmacSha256,
+        };
+
+        // Decoding the signed token leaves the pattern alone until the token
+        // itself is decoded.
+        let message = bincode::serde::encode_to_vec(&signed_token, config).unwrap();
+        let (signed_token, _): (SignedToken, _) =
+            bincode::serde::decode_from_slice(&message, config).unwrap();
+        assert!(signed_token.decode().is_err());
+    }
+
+    #[test]
+    fn request_port() {
+        let requester = token(
+            1,
+            &[],
+            vec![TokenScope::RequestPort {
+                tags: vec![TokenTag::StringLiteral(String::from("license"))],
+                ports: vec![27000..=27009],
+            }],
+        );
+        let license = token(1, &["license"], vec![TokenScope::ForwardPort]);
+        let rdp = token(1, &["rdp"], vec![TokenScope::ForwardPort]);
+        let higher = token(2, &["license"], vec![TokenScope::ForwardPort]);
+        let not_forwarding = token(1, &["license"], vec![]);
+
+        assert!(requester.can_request_port(&license, 27000));
+        assert!(!requester.can_request_port(&license, 3389));
+        assert!(!requester.can_request_port(&rdp, 27000));
+        assert!(!requester.can_request_port(&higher, 27000));
+        assert!(!requester.can_request_port(&not_forwarding, 27000));
+    }
 }
@@ -8,6 +8,24 @@ pub enum TunnelError {
     Crypto,
     Disconnected,
     PayloadTooLarge,
+    /// The decrypted record is empty or has an unknown type.
+    InvalidRecord,
+    /// A record with an already received sequence number arrived.
+    Replayed {
+        expected: u64,
+        got: u64,
+    },
+    /// A record arrived ahead of its sequence number, so earlier records were
+    /// dropped or reordered.
+    OutOfOrder {
+        expected: u64,
+        got: u64,
+    },
+    /// The peer missed too many keepalive pongs in a row and is considered
+    /// dead.
+    KeepaliveTimeout {
+        missed: u32,
+    },
 }
 
 impl std::fmt::Display for TunnelError {
@@ -17,6 +35,16 @@ impl std::fmt::Display for TunnelError {
             Self::Crypto => write!(f, "a crypto error is occured"),
             Self::Disconnected => write!(f, "disconnected"),
             Self::PayloadTooLarge => write!(f, "payload is too large"),
+            Self::InvalidRecord => write!(f, "invalid record"),
+            Self::Replayed { expected, got } => {
+                write!(f, "replayed record {got}, expected {expected}")
+            }
+            Self::OutOfOrder { expected, got } => {
+                write!(f, "out of order record {got}, expected {expected}")
+            }
+            Self::KeepaliveTimeout { missed } => {
+                write!(f, "peer missed {missed} keepalive pongs")
+            }
         }
     }
 }
@@ -1,25 +1,144 @@
+//! Encrypted record layer established after the handshake.
+//!
+//! Every frame sent over the tunnel follows the structure:
+//! ```text
+//! bytes
+//!  0..4   content_length
+//!  4..12  sequence_number
+//!   12..  encrypted record
+//! ```
+//!
+//! Sequence numbers start at zero and increase by one for every record in a
+//! direction. They are bound to the record as associated data of the
+//! [`TlsProvider`], so replayed, dropped or reordered records are detected
+//! before decryption.
+//!
+//! The first byte of a decrypted record is its [`RecordType`]. Key update
+//! records switch the traffic keys of their direction, see [`RekeyPolicy`].
+//!
+//! Frames are encoded and decoded by the [`codec`](crate::codec) module, and
+//! encrypted and decrypted in place in buffers owned by the tunnel, which are
+//! reused from one frame to the next.
+
 mod error;
+mod reader;
+mod rekey;
+mod writer;
 
 pub use error::TunnelError;
+pub use reader::TunnelReader;
+pub use rekey::RekeyPolicy;
+pub use writer::TunnelWriter;
 
 use crate::tls_provider::TlsProvider;
+use bytes::{Bytes, BytesMut};
 use tokio::{
-    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    io::{AsyncRead, AsyncWrite},
+    net::{
+        TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
     sync::Mutex,
 };
 
+/// Maximum size of a payload sent through the tunnel.
 pub const MAX_PAYLOAD_SIZE: usize = 2usize.pow(24) - 4;
 
+/// Maximum number of bytes the record header and encryption may add to a
+/// payload.
+pub const MAX_RECORD_OVERHEAD: usize = 256;
+
+/// Maximum size of an encrypted frame.
+pub const MAX_FRAME_SIZE: usize = MAX_PAYLOAD_SIZE + MAX_RECORD_OVERHEAD;
+
+/// Capacity a frame buffer keeps between frames. Buffers grown past it by an
+/// unusually large frame are released once a regular frame follows.
+const RETAINED_CAPACITY: usize = 64 * 1024;
+
+/// Type of a decrypted record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+    /// Application payload.
+    Data = 0,
+    /// The sender switched to fresh traffic keys. Records following this one
+    /// are encrypted with the new keys.
+    KeyUpdate = 1,
+}
+
+impl std::convert::TryFrom<u8> for RecordType {
+    type Error = TunnelError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Data),
+            1 => Ok(Self::KeyUpdate),
+            _ => Err(TunnelError::InvalidRecord),
+        }
+    }
+}
+
 /// VPN tunnel that sends and receives payloads over an encrypted channel in
 /// ordered manner.
 ///
 /// The tunnel handles encrypted communication using the provided
 /// [`TlsProvider`], ensuring data confidentiality and integrity between
-/// endpoints.
-pub struct Tunnel<R, W, T> {
-    pub r: Mutex<R>,
-    pub w: Mutex<W>,
-    pub tls: T,
+/// endpoints. Its [`TunnelWriter`] and [`TunnelReader`] are locked
+/// separately, so sending never waits for a receive in progress and vice
+/// versa. Use [`Tunnel::split`] to drive the halves from separate tasks
+/// without locking at all.
+pub struct Tunnel<R, W, T: TlsProvider> {
+    writer: Mutex<TunnelWriter<W, T::Encrypter>>,
+    reader: Mutex<TunnelReader<R, T::Decrypter>>,
+}
+
+impl<R, W, T: TlsProvider> Tunnel<R, W, T> {
+    /// Creates a new [`Tunnel`] with the default [`RekeyPolicy`].
+    pub fn new(r: R, w: W, tls: T) -> Tunnel<R, W, T> {
+        let (encrypter, decrypter) = tls.split();
+
+        Tunnel {
+            writer: Mutex::new(TunnelWriter::new(w, encrypter)),
+            reader: Mutex::new(TunnelReader::new(r, decrypter)),
+        }
+    }
+
+    /// Sets the policy for rekeying the sending direction.
+    pub fn with_rekey_policy(mut self, policy: RekeyPolicy) -> Tunnel<R, W, T> {
+        self.writer.get_mut().rekey.policy = policy;
+        self
+    }
+
+    /// Sets the maximum size of the frames accepted from the peer. Defaults
+    /// to [`MAX_FRAME_SIZE`].
+    ///
+    /// Larger frames are refused from their header alone, so a small limit
+    /// keeps unauthenticated peers from making the tunnel buffer large
+    /// frames. It is raised with [`Tunnel::set_max_frame_size`] once the
+    /// peer is trusted.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Tunnel<R, W, T> {
+        self.reader.get_mut().set_max_frame_size(max_frame_size);
+        self
+    }
+
+    /// Changes the maximum size of the frames accepted from the peer, see
+    /// [`Tunnel::with_max_frame_size`]. Waits for a receive in progress.
+    pub async fn set_max_frame_size(&self, max_frame_size: usize) {
+        self.reader.lock().await.set_max_frame_size(max_frame_size);
+    }
+
+    /// Splits the tunnel into halves that can be moved into separate tasks.
+    pub fn split(self) -> (TunnelReader<R, T::Decrypter>, TunnelWriter<W, T::Encrypter>) {
+        (self.reader.into_inner(), self.writer.into_inner())
+    }
+}
+
+impl<T: TlsProvider> Tunnel<OwnedReadHalf, OwnedWriteHalf, T> {
+    /// Creates a new [`Tunnel`] over a TCP stream, e.g. one that completed
+    /// the handshake.
+    pub fn from_tcp(tcp_stream: TcpStream, tls: T) -> Tunnel<OwnedReadHalf, OwnedWriteHalf, T> {
+        let (r, w) = tcp_stream.into_split();
+        Tunnel::new(r, w, tls)
+    }
 }
 
 impl<R, W, T> Tunnel<R, W, T>
@@ -27,19 +146,23 @@ where
     W: Unpin + AsyncWrite,
     T: TlsProvider,
 {
+    /// Sends a payload, rekeying beforehand if the [`RekeyPolicy`] demands.
+    ///
+    /// Records are encrypted while holding the writer, so they reach the peer
+    /// in the order their keys were used.
     pub async fn send(&self, payload: &[u8]) -> Result<(), TunnelError> {
-        let encrypted = self.tls.encrypt(payload).map_err(|_| TunnelError::Crypto)?;
-
-        if encrypted.len() > MAX_PAYLOAD_SIZE {
-            return Err(TunnelError::PayloadTooLarge);
-        }
-
-        let mut w = self.w.lock().await;
+        self.writer.lock().await.send(payload).await
+    }
 
-        w.write_u32(encrypted.len() as u32).await?;
-        w.write_all(&encrypted).await?;
+    /// Switches the sending direction to fresh traffic keys.
+    pub async fn rekey(&self) -> Result<(), TunnelError> {
+        self.writer.lock().await.rekey().await
+    }
 
-        Ok(())
+    /// Shuts down the writing half, signaling the peer that the tunnel is
+    /// closed.
+    pub async fn shutdown(&self) -> Result<(), TunnelError> {
+        self.writer.lock().await.shutdown().await
     }
 }
 
@@ -48,47 +171,282 @@ where
     R: Unpin + AsyncRead,
     T: TlsProvider,
 {
-    pub async fn recv(&self) -> Result<Vec<u8>, TunnelError> {
-        let mut r = self.r.lock().await;
-
-        let content_lenght = r.read_u32().await? as usize;
-        if content_lenght > MAX_PAYLOAD_SIZE {
-            return Err(TunnelError::PayloadTooLarge);
-        }
-
-        let mut payload = vec![0; content_lenght];
-
-        r.read_exact(&mut payload).await?;
+    /// Receives the next payload. Key update records are applied
+    /// transparently, see [`TunnelReader::recv`].
+    pub async fn recv(&self) -> Result<Bytes, TunnelError> {
+        self.reader.lock().await.recv().await
+    }
+}
 
-        Ok(payload)
+/// Clears a reused frame buffer and reserves `len` bytes, releasing capacity
+/// left over from an unusually large frame.
+fn reuse(buf: &mut BytesMut, len: usize) {
+    if buf.capacity() > RETAINED_CAPACITY && len <= RETAINED_CAPACITY {
+        *buf = BytesMut::with_capacity(RETAINED_CAPACITY);
     }
+
+    buf.clear();
+    buf.reserve(len);
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{MAX_PAYLOAD_SIZE, Tunnel};
+    use super::{
+        MAX_FRAME_SIZE, MAX_PAYLOAD_SIZE, RETAINED_CAPACITY, RekeyPolicy, Tunnel, TunnelError,
+    };
     use crate::{random_bytes, tls_provider::MockTls};
     use testutil::DynResult;
-    use tokio::{io::simplex, sync::Mutex};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt, ReadHalf, SimplexStream, WriteHalf, simplex},
+        net::{TcpListener, TcpStream},
+    };
+
+    /// Sends the payloads through a tunnel and returns the raw frames.
+    async fn capture_frames(payloads: &[&[u8]]) -> DynResult<Vec<Vec<u8>>> {
+        let (mut r, w) = simplex(usize::MAX);
+        let (source, _) = simplex(1);
+        let tunnel = Tunnel::new(source, w, MockTls);
+
+        let mut frames = Vec::new();
+        for payload in payloads {
+            tunnel.send(payload).await?;
+
+            let len = r.read_u32().await? as usize;
+            let mut frame = Vec::from((len as u32).to_be_bytes());
+            frame.resize(4 + 8 + len, 0);
+            r.read_exact(&mut frame[4..]).await?;
+            frames.push(frame);
+        }
+
+        Ok(frames)
+    }
+
+    /// Creates a tunnel that receives the given frames in order.
+    async fn replay_frames(
+        frames: &[&Vec<u8>],
+    ) -> DynResult<Tunnel<ReadHalf<SimplexStream>, WriteHalf<SimplexStream>, MockTls>> {
+        let (r, mut w) = simplex(usize::MAX);
+        let (_, sink) = simplex(1);
+
+        for frame in frames {
+            w.write_all(frame).await?;
+        }
+
+        Ok(Tunnel::new(r, sink, MockTls))
+    }
+
+    #[tokio::test]
+    pub async fn replayed_record() -> DynResult<()> {
+        let frames = capture_frames(&[b"first", b"second"]).await?;
+        let tunnel = replay_frames(&[&frames[0], &frames[1], &frames[1]]).await?;
+
+        assert_eq!(&tunnel.recv().await?[..], b"first");
+        assert_eq!(&tunnel.recv().await?[..], b"second");
+        assert!(matches!(
+            tunnel.recv().await,
+            Err(TunnelError::Replayed {
+                expected: 2,
+                got: 1
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn dropped_record() -> DynResult<()> {
+        let frames = capture_frames(&[b"first", b"second", b"third"]).await?;
+        let tunnel = replay_frames(&[&frames[0], &frames[2], &frames[1]]).await?;
+
+        assert_eq!(&tunnel.recv().await?[..], b"first");
+        assert!(matches!(
+            tunnel.recv().await,
+            Err(TunnelError::OutOfOrder {
+                expected: 1,
+                got: 2
+            })
+        ));
+
+        Ok(())
+    }
 
     #[tokio::test]
     pub async fn tunnel() -> DynResult<()> {
         let (r, w) = simplex(usize::MAX);
 
-        let tunnel = Tunnel {
-            r: Mutex::new(r),
-            w: Mutex::new(w),
-            tls: MockTls {},
-        };
+        let tunnel = Tunnel::new(r, w, MockTls);
 
         let random = random_bytes!(u16::MAX as usize);
         let zero = vec![0; MAX_PAYLOAD_SIZE];
 
         tunnel.send(&random).await?;
         tunnel.send(&zero).await?;
-        assert_eq!(tunnel.recv().await?, random);
+        assert_eq!(&tunnel.recv().await?[..], random);
         assert_eq!(tunnel.recv().await?, zero);
 
+        assert!(matches!(
+            tunnel.send(&vec![0; MAX_PAYLOAD_SIZE + 1]).await,
+            Err(TunnelError::PayloadTooLarge)
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn max_frame_size() -> DynResult<()> {
+        let (r, w) = simplex(usize::MAX);
+        let tunnel = Tunnel::new(r, w, MockTls).with_max_frame_size(256);
+
+        tunnel.send(b"small").await?;
+        tunnel.send(&[0; 1024]).await?;
+        assert_eq!(&tunnel.recv().await?[..], b"small");
+        assert!(matches!(
+            tunnel.recv().await,
+            Err(TunnelError::PayloadTooLarge)
+        ));
+
+        // Raised limits take effect for the next frame.
+        let (r, w) = simplex(usize::MAX);
+        let tunnel = Tunnel::new(r, w, MockTls).with_max_frame_size(256);
+        tunnel.send(b"small").await?;
+        tunnel.send(&[0; 1024]).await?;
+        assert_eq!(&tunnel.recv().await?[..], b"small");
+        tunnel.set_max_frame_size(MAX_FRAME_SIZE).await;
+        assert_eq!(&tunnel.recv().await?[..], [0; 1024]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn reused_buffers() -> DynResult<()> {
+        let (r, w) = simplex(usize::MAX);
+
+        let tunnel = Tunnel::new(r, w, MockTls);
+
+        let large = random_bytes!(4 * RETAINED_CAPACITY);
+        tunnel.send(&large).await?;
+        tunnel.send(b"small").await?;
+        tunnel.send(b"again").await?;
+
+        // Payloads stay valid while later frames are received.
+        let received = tunnel.recv().await?;
+        assert_eq!(&tunnel.recv().await?[..], b"small");
+        assert_eq!(&received[..], large);
+
+        // Capacity grown by the large frame is released afterwards.
+        assert_eq!(&tunnel.recv().await?[..], b"again");
+        let (reader, writer) = tunnel.split();
+        assert!(writer.buf.capacity() <= RETAINED_CAPACITY);
+        assert!(reader.buf.capacity() <= RETAINED_CAPACITY);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn oversized_payload_keeps_keys() -> DynResult<()> {
+        let (r, w) = simplex(usize::MAX);
+
+        let tunnel = Tunnel::new(r, w, MockTls).with_rekey_policy(RekeyPolicy {
+            max_records: Some(1),
+            ..RekeyPolicy::NEVER
+        });
+
+        tunnel.send(b"first").await?;
+        // A key update is due, but the payload is rejected beforehand.
+        assert!(matches!(
+            tunnel.send(&vec![0; MAX_PAYLOAD_SIZE + 1]).await,
+            Err(TunnelError::PayloadTooLarge)
+        ));
+        tunnel.send(b"second").await?;
+
+        assert_eq!(&tunnel.recv().await?[..], b"first");
+        assert_eq!(&tunnel.recv().await?[..], b"second");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn rekey_policy() -> DynResult<()> {
+        let (r, w) = simplex(usize::MAX);
+
+        let tunnel = Tunnel::new(r, w, MockTls).with_rekey_policy(RekeyPolicy {
+            max_records: Some(3),
+            max_bytes: Some(64),
+            max_age: None,
+        });
+
+        let mut payloads = Vec::new();
+        for i in 0..16 {
+            payloads.push(vec![i; i as usize * 4]);
+        }
+
+        for payload in &payloads {
+            tunnel.send(payload).await?;
+        }
+        tunnel.rekey().await?;
+        tunnel.send(&[1, 2, 3]).await?;
+
+        for payload in &payloads {
+            assert_eq!(&tunnel.recv().await?, payload);
+        }
+        assert_eq!(&tunnel.recv().await?[..], [1, 2, 3]);
+
+        let (reader, writer) = tunnel.split();
+        assert_eq!(
+            writer.encoder.encrypter().epoch(),
+            reader.decoder.decrypter().epoch()
+        );
+        assert!(writer.encoder.encrypter().epoch() > 5);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn split_tunnel() -> DynResult<()> {
+        let (r, w) = simplex(64);
+
+        let (mut reader, writer) = Tunnel::new(r, w, MockTls).split();
+        let mut writer = writer.with_rekey_policy(RekeyPolicy {
+            max_records: Some(7),
+            max_bytes: None,
+            max_age: None,
+        });
+
+        // The pipe only buffers 64 bytes, so both halves must make progress
+        // concurrently.
+        let sender = tokio::spawn(async move {
+            for i in 0..128u8 {
+                writer.send(&[i; 100]).await?;
+            }
+            writer.shutdown().await
+        });
+
+        for i in 0..128u8 {
+            assert_eq!(&reader.recv().await?[..], [i; 100]);
+        }
+        sender.await??;
+        assert!(matches!(
+            reader.recv().await,
+            Err(TunnelError::Disconnected)
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn from_tcp() -> DynResult<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        let client = Tunnel::from_tcp(client?, MockTls);
+        let server = Tunnel::from_tcp(server?.0, MockTls);
+
+        client.send(b"ping").await?;
+        assert_eq!(&server.recv().await?[..], b"ping");
+        server.send(b"pong").await?;
+        assert_eq!(&client.recv().await?[..], b"pong");
+
         Ok(())
     }
 }
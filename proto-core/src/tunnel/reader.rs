@@ -0,0 +1,71 @@
+use super::{RETAINED_CAPACITY, TunnelError};
+use crate::{codec::RecordDecoder, tls_provider::Decrypter};
+use bytes::{Bytes, BytesMut};
+use std::io::ErrorKind;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_util::codec::Decoder;
+
+/// Minimum room reserved in the receive buffer before each read, so several
+/// small frames can arrive with a single read.
+const READ_CAPACITY: usize = 16 * 1024;
+
+/// Receiving half of a [`Tunnel`](super::Tunnel).
+///
+/// Owns the reader and the cipher state of the receiving direction, so it can
+/// be moved into its own task.
+pub struct TunnelReader<R, D> {
+    r: R,
+    pub(super) decoder: RecordDecoder<D>,
+    /// Received bytes not decoded yet.
+    pub(super) buf: BytesMut,
+}
+
+impl<R, D> TunnelReader<R, D> {
+    pub(super) fn new(r: R, tls: D) -> TunnelReader<R, D> {
+        TunnelReader {
+            r,
+            decoder: RecordDecoder::new(tls),
+            buf: BytesMut::new(),
+        }
+    }
+
+    /// Sets the maximum size of the frames accepted from the peer, see
+    /// [`Tunnel::with_max_frame_size`](super::Tunnel::with_max_frame_size).
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.decoder.set_max_frame_size(max_frame_size);
+    }
+}
+
+impl<R, D> TunnelReader<R, D>
+where
+    R: Unpin + AsyncRead,
+    D: Decrypter,
+{
+    /// Receives the next payload. Key update records are applied
+    /// transparently.
+    ///
+    /// The payload shares its allocation with the receive buffer, which is
+    /// reclaimed for later frames once the payload is dropped. The method is
+    /// cancel safe: bytes read before cancellation are kept for the next call.
+    pub async fn recv(&mut self) -> Result<Bytes, TunnelError> {
+        loop {
+            if let Some(payload) = self.decoder.decode(&mut self.buf)? {
+                // Release capacity left over from an unusually large frame.
+                if self.buf.is_empty() && self.buf.capacity() > RETAINED_CAPACITY {
+                    self.buf = BytesMut::new();
+                }
+
+                return Ok(payload);
+            }
+
+            self.buf.reserve(READ_CAPACITY);
+            if self.r.read_buf(&mut self.buf).await? == 0 {
+                return Err(if self.buf.is_empty() {
+                    TunnelError::Disconnected
+                } else {
+                    TunnelError::Io(ErrorKind::UnexpectedEof.into())
+                });
+            }
+        }
+    }
+}
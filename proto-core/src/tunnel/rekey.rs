@@ -0,0 +1,78 @@
+use std::time::{Duration, Instant};
+
+/// Limits after which the sending side of a [`Tunnel`](super::Tunnel)
+/// derives fresh traffic keys.
+///
+/// Each direction is rekeyed independently. Limits are checked before a
+/// record is sent, so an idle tunnel is rekeyed with its next record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RekeyPolicy {
+    /// Maximum number of records encrypted with the same keys.
+    pub max_records: Option<u64>,
+    /// Maximum number of payload bytes encrypted with the same keys.
+    pub max_bytes: Option<u64>,
+    /// Maximum lifetime of the keys.
+    pub max_age: Option<Duration>,
+}
+
+impl RekeyPolicy {
+    /// Policy that never rekeys automatically.
+    pub const NEVER: RekeyPolicy = RekeyPolicy {
+        max_records: None,
+        max_bytes: None,
+        max_age: None,
+    };
+}
+
+impl Default for RekeyPolicy {
+    fn default() -> Self {
+        RekeyPolicy {
+            max_records: Some(1 << 24),
+            max_bytes: Some(1 << 32),
+            max_age: Some(Duration::from_secs(60 * 60)),
+        }
+    }
+}
+
+/// Usage of the current sending keys.
+#[derive(Debug)]
+pub(crate) struct RekeyState {
+    pub(crate) policy: RekeyPolicy,
+    records: u64,
+    bytes: u64,
+    since: Instant,
+}
+
+impl RekeyState {
+    pub(crate) fn new(policy: RekeyPolicy) -> Self {
+        RekeyState {
+            policy,
+            records: 0,
+            bytes: 0,
+            since: Instant::now(),
+        }
+    }
+
+    /// Whether any of the policy limits has been reached.
+    pub(crate) fn is_due(&self) -> bool {
+        self.policy
+            .max_records
+            .is_some_and(|max| self.records >= max)
+            || self.policy.max_bytes.is_some_and(|max| self.bytes >= max)
+            || self
+                .policy
+                .max_age
+                .is_some_and(|max| self.since.elapsed() >= max)
+    }
+
+    /// Accounts a record sent with the current keys.
+    pub(crate) fn record(&mut self, len: usize) {
+        self.records += 1;
+        self.bytes += len as u64;
+    }
+
+    /// Resets the usage after a key update.
+    pub(crate) fn reset(&mut self) {
+        *self = RekeyState::new(self.policy);
+    }
+}
@@ -0,0 +1,105 @@
+use super::{
+    MAX_PAYLOAD_SIZE, MAX_RECORD_OVERHEAD, RecordType, RekeyPolicy, TunnelError, rekey::RekeyState,
+    reuse,
+};
+use crate::{
+    codec::{FRAME_HEADER_LEN, RecordEncoder},
+    tls_provider::Encrypter,
+};
+use bytes::BytesMut;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::Encoder;
+
+/// Sending half of a [`Tunnel`](super::Tunnel).
+///
+/// Owns the writer and the cipher state of the sending direction, so it can
+/// be moved into its own task.
+pub struct TunnelWriter<W, E> {
+    w: W,
+    pub(super) encoder: RecordEncoder<E>,
+    /// Reused buffer of outgoing frames.
+    pub(super) buf: BytesMut,
+    pub(super) rekey: RekeyState,
+}
+
+impl<W, E> TunnelWriter<W, E> {
+    /// Creates the sending half with the default [`RekeyPolicy`].
+    pub(super) fn new(w: W, tls: E) -> TunnelWriter<W, E> {
+        TunnelWriter {
+            w,
+            encoder: RecordEncoder::new(tls),
+            buf: BytesMut::new(),
+            rekey: RekeyState::new(RekeyPolicy::default()),
+        }
+    }
+
+    /// Sets the policy for rekeying the sending direction.
+    pub fn with_rekey_policy(mut self, policy: RekeyPolicy) -> TunnelWriter<W, E> {
+        self.rekey.policy = policy;
+        self
+    }
+}
+
+impl<W, E> TunnelWriter<W, E>
+where
+    W: Unpin + AsyncWrite,
+    E: Encrypter,
+{
+    /// Sends a payload, rekeying beforehand if the [`RekeyPolicy`] demands.
+    pub async fn send(&mut self, payload: &[u8]) -> Result<(), TunnelError> {
+        // Checked before a due key update, which cannot be undone.
+        if payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(TunnelError::PayloadTooLarge);
+        }
+
+        reuse(
+            &mut self.buf,
+            2 * (FRAME_HEADER_LEN + 1 + MAX_RECORD_OVERHEAD) + payload.len(),
+        );
+
+        // A due key update is written along with the payload.
+        let rekeyed = self.rekey.is_due();
+        if rekeyed {
+            self.encoder
+                .encode((RecordType::KeyUpdate, &[]), &mut self.buf)?;
+        }
+        let encoded = self
+            .encoder
+            .encode((RecordType::Data, payload), &mut self.buf);
+
+        if let Err(tunnel_error) = encoded {
+            // The encrypter already switched keys, so the peer must too.
+            if rekeyed {
+                self.w.write_all(&self.buf).await?;
+                self.rekey.reset();
+            }
+            return Err(tunnel_error);
+        }
+
+        self.w.write_all(&self.buf).await?;
+
+        if rekeyed {
+            self.rekey.reset();
+        }
+        self.rekey.record(payload.len());
+
+        Ok(())
+    }
+
+    /// Switches the sending direction to fresh traffic keys.
+    pub async fn rekey(&mut self) -> Result<(), TunnelError> {
+        reuse(&mut self.buf, FRAME_HEADER_LEN + 1 + MAX_RECORD_OVERHEAD);
+        self.encoder
+            .encode((RecordType::KeyUpdate, &[]), &mut self.buf)?;
+
+        self.w.write_all(&self.buf).await?;
+        self.rekey.reset();
+
+        Ok(())
+    }
+
+    /// Shuts down the writer, signaling the peer that the tunnel is closed.
+    pub async fn shutdown(&mut self) -> Result<(), TunnelError> {
+        Ok(self.w.shutdown().await?)
+    }
+}
@@ -1,12 +1,16 @@
+use crypto::{
+    key::KeyMaterial,
+    sign::{Hs256, TokenVerifier},
+};
 use server::ServerBuilder;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let server = ServerBuilder {
-        addr: "0.0.0.0:3781".parse().unwrap(),
-        encryption_key: vec![0; 16],
-        signing_key: vec![0; 32],
-    }
+    let server = ServerBuilder::new(
+        "0.0.0.0:3781".parse().unwrap(),
+        KeyMaterial::from_bytes(vec![0; 16]),
+        TokenVerifier::from(Hs256::try_new(&[0; 32])?),
+    )
     .try_build()
     .await?;
 
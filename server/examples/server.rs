@@ -1,3 +1,7 @@
+use crypto::{
+    key::KeyMaterial,
+    sign::{Hs256, TokenVerifier},
+};
 use server::ServerBuilder;
 
 #[tokio::main]
@@ -6,11 +10,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with_max_level(tracing::Level::TRACE)
         .init();
 
-    let server = ServerBuilder {
-        addr: "0.0.0.0:3781".parse()?,
-        encryption_key: vec![0; 16],
-        signing_key: vec![0; 32],
-    }
+    let server = ServerBuilder::new(
+        "0.0.0.0:3781".parse()?,
+        KeyMaterial::from_bytes(vec![0; 16]),
+        TokenVerifier::from(Hs256::try_new(&[0; 32])?),
+    )
     .try_build()
     .await?;
 
@@ -0,0 +1,103 @@
+use super::{Connection, ConnectionError};
+use crate::registry::ConnectedClient;
+use proto_core::{
+    sub_protocol::{
+        Message,
+        cmd::{Cmd, CmdEnum},
+        cmd_response::{Authenticate, CmdResponse, CmdResponsePayload},
+    },
+    tls_provider::TlsProvider,
+    token::Token,
+    tunnel::MAX_FRAME_SIZE,
+};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{instrument, trace};
+
+impl<T: TlsProvider> Connection<T> {
+    /// Authenticates the client with its signed token.
+    ///
+    /// The first message of the client is expected to be an
+    /// [`CmdEnum::Authenticate`] command. The signature of the token is
+    /// checked against the server's verifier, which only requires a public
+    /// key for asymmetric algorithms.
+    ///
+    /// Authenticated clients are added to the registry of the server. Only
+    /// one client may be connected per token ID. Their frames may take up to
+    /// [`MAX_FRAME_SIZE`] bytes from then on, instead of the
+    /// pre-authentication limit.
+    #[instrument(skip(self))]
+    pub async fn authenticate(&self) -> Result<Arc<Token>, ConnectionError> {
+        let Message::Cmd(Cmd {
+            response_id,
+            payload: CmdEnum::Authenticate(authenticate),
+        }) = self.recv().await?
+        else {
+            return Err(ConnectionError::UnexpectedMessage);
+        };
+
+        let signed_token = authenticate.token;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+
+        // Tokens with unsupported signature algorithms are treated as invalid.
+        // The token is only decoded once its signature is verified.
+        let token = self
+            .state
+            .token_verifier
+            .verify_token(&signed_token)
+            .unwrap_or(false)
+            .then(|| signed_token.decode().ok())
+            .flatten()
+            .filter(|token| token.exp >= now)
+            .map(Arc::new);
+        trace!(?token, "Got authenticate");
+
+        let response = match &token {
+            None => Authenticate::InvalidToken,
+            Some(token) => {
+                if self.state.registry.register(ConnectedClient {
+                    token: Arc::clone(token),
+                    sender: self.queue.sender(),
+                    congested: self.congested.clone(),
+                    timestamp: now,
+                }) {
+                    Authenticate::Success
+                } else {
+                    Authenticate::AlreadyConnected
+                }
+            }
+        };
+        let authenticated = matches!(response, Authenticate::Success);
+
+        let sent = self
+            .send(&Message::CmdResponse(CmdResponse {
+                response_id,
+                payload: CmdResponsePayload::Authenticate(response),
+            }))
+            .await;
+
+        match token {
+            Some(token) if authenticated => match sent {
+                Ok(()) => {
+                    self.tunnel.set_max_frame_size(MAX_FRAME_SIZE).await;
+                    Ok(token)
+                }
+                Err(connection_error) => {
+                    self.state.registry.unregister(token.sub);
+                    Err(connection_error)
+                }
+            },
+            _ => {
+                sent?;
+                Err(ConnectionError::Authentication(if token.is_some() {
+                    Authenticate::AlreadyConnected
+                } else {
+                    Authenticate::InvalidToken
+                }))
+            }
+        }
+    }
+}
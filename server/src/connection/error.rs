@@ -0,0 +1,41 @@
+use bincode::error::{DecodeError, EncodeError};
+use crypto::CryptoError;
+use proto_core::{
+    common::QueueError, sub_protocol::cmd_response::Authenticate, tunnel::TunnelError,
+};
+
+/// Errors that terminate a client connection.
+#[derive(Debug)]
+pub enum ConnectionError {
+    Tunnel(TunnelError),
+    Crypto(CryptoError),
+    Encode(EncodeError),
+    Decode(DecodeError),
+    Queue(QueueError),
+    /// The client sent a message that is not expected at this stage.
+    UnexpectedMessage,
+    /// The client could not be authenticated.
+    Authentication(Authenticate),
+    /// The client did not read its messages fast enough to keep its queue
+    /// from filling up.
+    Congested,
+}
+
+impl std::fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tunnel(tunnel_error) => write!(f, "tunnel: {tunnel_error}"),
+            Self::Crypto(crypto_error) => write!(f, "crypto: {crypto_error}"),
+            Self::Encode(encode_error) => write!(f, "encode: {encode_error}"),
+            Self::Decode(decode_error) => write!(f, "decode: {decode_error}"),
+            Self::Queue(queue_error) => write!(f, "queue: {queue_error}"),
+            Self::UnexpectedMessage => write!(f, "unexpected message"),
+            Self::Authentication(response) => write!(f, "authentication: {response:?}"),
+            Self::Congested => write!(f, "message queue congested"),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {}
+
+proto_core::error_impl_from!(ConnectionError; Tunnel, Crypto, Encode, Decode, Queue);
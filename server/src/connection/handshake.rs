@@ -1,22 +1,58 @@
 use proto_core::{
     random_bytes,
     sub_protocol::handshake::{
-        self, HandshakeAlert, HandshakeContentType, read_handshake_payload, write_handshake_payload,
+        self, HandshakeAlert, HandshakeContentType, HandshakeLimits, PayloadLimits,
+        read_handshake_payload, write_handshake_payload,
     },
 };
-use tokio::io::{AsyncRead, AsyncWrite};
+use std::{collections::HashMap, net::IpAddr, sync::Mutex, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time::timeout,
+};
 use tracing::{info, instrument, trace};
 
+/// Time given to a client to receive a handshake alert before disconnecting.
+const ALERT_TIMEOUT: Duration = Duration::from_secs(1);
+
 /// Server-side implementation of the handshake protocol (version 0.1).
 ///
-/// Returns a [`HandshakeAlert`] if an error occurs, which should then be sent
-/// back to the client and terminate the connection.
+/// Returns a [`HandshakeAlert`] if an error occurs or the handshake exceeds
+/// its [`HandshakeLimits`], which should then be sent back to the client with
+/// [`send_alert`] and terminate the connection.
 #[instrument(skip(r, w))]
 pub async fn do_handshake<R: Unpin + AsyncRead, W: Unpin + AsyncWrite>(
     r: &mut R,
     w: &mut W,
+    limits: &HandshakeLimits,
 ) -> Result<([u8; 32], [u8; 32]), HandshakeAlert> {
-    let (content_type, payload) = read_handshake_payload(r).await?;
+    timeout(limits.timeout, exchange_hellos(r, w, &limits.payload))
+        .await
+        .map_err(|_| HandshakeAlert::Timeout)?
+}
+
+/// Sends `alert` to the client, giving up if the client does not read it
+/// within a second.
+///
+/// I/O errors are not sent, as the stream is already unusable.
+pub async fn send_alert<W: Unpin + AsyncWrite>(w: &mut W, alert: &HandshakeAlert) {
+    if matches!(alert, HandshakeAlert::IoError) {
+        return;
+    }
+
+    let Ok(payload) = bincode::serde::encode_to_vec(alert, bincode::config::standard()) else {
+        return;
+    };
+    let send = write_handshake_payload(w, HandshakeContentType::HandshakeAlert, &payload);
+    let _ = timeout(ALERT_TIMEOUT, send).await;
+}
+
+async fn exchange_hellos<R: Unpin + AsyncRead, W: Unpin + AsyncWrite>(
+    r: &mut R,
+    w: &mut W,
+    limits: &PayloadLimits,
+) -> Result<([u8; 32], [u8; 32]), HandshakeAlert> {
+    let (content_type, payload) = read_handshake_payload(r, limits).await?;
 
     // The server expects the client's first payload to be a `ClientHello`.
     if content_type != HandshakeContentType::ClientHello {
@@ -46,7 +82,7 @@ pub async fn do_handshake<R: Unpin + AsyncRead, W: Unpin + AsyncWrite>(
     trace!("Send server hello: {server_hello:?}");
 
     // TODO: decrypt
-    let (content_type, payload) = read_handshake_payload(r).await?;
+    let (content_type, payload) = read_handshake_payload(r, limits).await?;
 
     // A `Finished` payload is expected.
     if content_type != HandshakeContentType::Finished {
@@ -67,13 +103,53 @@ pub async fn do_handshake<R: Unpin + AsyncRead, W: Unpin + AsyncWrite>(
     Ok((server_random, finished.random))
 }
 
+/// Number of handshakes in progress from each source IP.
+#[derive(Debug, Default)]
+pub(crate) struct PendingHandshakes {
+    per_ip: Mutex<HashMap<IpAddr, usize>>,
+}
+
+/// A handshake counted by [`PendingHandshakes`] until dropped.
+#[derive(Debug)]
+pub(crate) struct PendingHandshake<'a> {
+    pending: &'a PendingHandshakes,
+    ip: IpAddr,
+}
+
+impl PendingHandshakes {
+    /// Registers a handshake from `ip`, unless `max` handshakes from it are
+    /// already in progress.
+    pub(crate) fn try_start(&self, ip: IpAddr, max: usize) -> Option<PendingHandshake<'_>> {
+        let mut per_ip = self.per_ip.lock().unwrap();
+        if per_ip.get(&ip).is_some_and(|count| *count >= max) || max == 0 {
+            return None;
+        }
+        *per_ip.entry(ip).or_default() += 1;
+
+        Some(PendingHandshake { pending: self, ip })
+    }
+}
+
+impl Drop for PendingHandshake<'_> {
+    fn drop(&mut self) {
+        let mut per_ip = self.pending.per_ip.lock().unwrap();
+        if let Some(count) = per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::do_handshake;
     use proto_core::{
         algorithms, random_bytes,
         sub_protocol::handshake::{
-            self, HandshakeAlert, HandshakeContentType, read_handshake_payload,
+            self, HandshakeAlert, HandshakeContentType, HandshakeLimits, PayloadLimits,
+            read_handshake_payload,
         },
     };
     use testutil::{DynResult, send_handshake_payload};
@@ -92,7 +168,8 @@ mod tests {
             }
         );
 
-        if let Err(HandshakeAlert::UnexpectedPayload { .. }) = do_handshake(&mut sr, &mut sw).await
+        if let Err(HandshakeAlert::UnexpectedPayload { .. }) =
+            do_handshake(&mut sr, &mut sw, &HandshakeLimits::default()).await
         {
             Ok(())
         } else {
@@ -115,9 +192,15 @@ mod tests {
             }
         );
 
-        tokio::spawn(async move { do_handshake(&mut sr, &mut sw).await.unwrap() });
+        tokio::spawn(async move {
+            do_handshake(&mut sr, &mut sw, &HandshakeLimits::default())
+                .await
+                .unwrap()
+        });
 
-        let (content_type, _) = read_handshake_payload(&mut cr).await.unwrap();
+        let (content_type, _) = read_handshake_payload(&mut cr, &PayloadLimits::default())
+            .await
+            .unwrap();
 
         assert_eq!(content_type, handshake::HandshakeContentType::ServerHello);
 
@@ -147,8 +230,14 @@ mod tests {
             }
         );
 
-        let task = tokio::spawn(async move { do_handshake(&mut sr, &mut sw).await.unwrap() });
-        let (content_type, _) = read_handshake_payload(&mut cr).await.unwrap();
+        let task = tokio::spawn(async move {
+            do_handshake(&mut sr, &mut sw, &HandshakeLimits::default())
+                .await
+                .unwrap()
+        });
+        let (content_type, _) = read_handshake_payload(&mut cr, &PayloadLimits::default())
+            .await
+            .unwrap();
 
         assert_eq!(content_type, handshake::HandshakeContentType::ServerHello);
 
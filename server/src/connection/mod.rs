@@ -1,19 +1,83 @@
 //! Utilities for managing client connections and handling sub-protocol layers.
 
+mod authenticate;
+mod error;
 mod handshake;
+mod serve;
 
-pub use handshake::do_handshake;
-use proto_core::tls_provider::TlsProvider;
+pub use error::ConnectionError;
+pub use handshake::{do_handshake, send_alert};
+
+pub(crate) use handshake::PendingHandshakes;
 
 use crate::server::SharedState;
+use proto_core::{
+    common::{KeepaliveMonitor, MessageQueue, Priority},
+    sub_protocol::Message,
+    tls_provider::TlsProvider,
+    tunnel::Tunnel,
+};
 use std::sync::Arc;
-use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio_util::sync::CancellationToken;
 
 /// Represents a client connection to the server.
 ///
-/// Wraps the underlying TCP stream, remote address, and shared server state.
+/// Wraps the encrypted tunnel established by the handshake and shared server
+/// state.
 pub struct Connection<T: TlsProvider> {
-    pub(crate) _tcp_stream: TcpStream,
-    pub(crate) _state: Arc<SharedState>,
-    pub(crate) _tls: T,
+    pub(crate) tunnel: Arc<Tunnel<OwnedReadHalf, OwnedWriteHalf, T>>,
+    pub(crate) queue: MessageQueue<OwnedReadHalf, OwnedWriteHalf, T>,
+    pub(crate) keepalive: KeepaliveMonitor,
+    /// Cancelled by the registry once the client no longer keeps up with its
+    /// messages.
+    pub(crate) congested: CancellationToken,
+    pub(crate) state: Arc<SharedState>,
+}
+
+impl<T: TlsProvider> Connection<T> {
+    /// Creates a connection over an established tunnel.
+    pub(crate) fn new(
+        tunnel: Tunnel<OwnedReadHalf, OwnedWriteHalf, T>,
+        state: Arc<SharedState>,
+    ) -> Connection<T> {
+        let tunnel = Arc::new(tunnel);
+
+        Connection {
+            queue: MessageQueue::new(Arc::clone(&tunnel))
+                .with_limits(state.queue_limits)
+                .with_scheduler(state.scheduler.clone()),
+            keepalive: KeepaliveMonitor::new(state.keepalive_policy),
+            congested: CancellationToken::new(),
+            tunnel,
+            state,
+        }
+    }
+
+    /// Encodes and sends a message through the tunnel, bypassing the message
+    /// queue.
+    pub async fn send(&self, message: &Message) -> Result<(), ConnectionError> {
+        Ok(self.tunnel.send(&message.encode()?).await?)
+    }
+
+    /// Encodes and queues a message in the default class of its content type.
+    /// Keepalives may be dropped by the overflow policy.
+    pub(crate) async fn push(&self, message: &Message) -> Result<(), ConnectionError> {
+        let payload = message.encode()?;
+        let priority = Priority::from(message.content_type());
+        let sender = self.queue.sender();
+
+        if message.is_droppable() {
+            sender.push_droppable(payload, priority).await?;
+        } else {
+            sender.push_message(payload, priority).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Receives and decodes a message from the tunnel.
+    pub async fn recv(&self) -> Result<Message, ConnectionError> {
+        Ok(Message::decode(&self.tunnel.recv().await?)?)
+    }
 }
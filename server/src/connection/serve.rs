@@ -0,0 +1,110 @@
+use super::{Connection, ConnectionError};
+use proto_core::{
+    sub_protocol::{Message, alert::Alert, event::Event, keepalive::Keepalive},
+    tls_provider::TlsProvider,
+    token::Token,
+};
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument};
+
+impl<T: TlsProvider> Connection<T> {
+    /// Serves an authenticated client until it disconnects.
+    ///
+    /// The client is informed about the other connected clients, and its
+    /// application data is relayed to the clients sharing the requested ports.
+    /// The client is pinged according to the keepalive policy, and is
+    /// disconnected once it misses too many pongs. Once the client
+    /// disconnects, it is removed from the registry and its relayed
+    /// connections are terminated.
+    ///
+    /// Messages relayed to the client never wait for room in its queue. A
+    /// client whose queue is full, because it stopped reading, is
+    /// disconnected instead of stalling the clients talking to it.
+    ///
+    /// When `shutdown` is cancelled, the queued messages are flushed and the
+    /// client receives an [`Alert::ServerShutdown`] before the method returns.
+    #[instrument(skip_all, fields(token.sub))]
+    pub async fn serve(
+        &self,
+        token: Arc<Token>,
+        shutdown: &CancellationToken,
+    ) -> Result<(), ConnectionError> {
+        let state = &self.state;
+
+        let clients = state
+            .registry
+            .others(token.sub)
+            .iter()
+            .map(|client| client.to_event())
+            .collect();
+        self.push(&Message::Event(Event::ListClients(clients)))
+            .await?;
+
+        if let Some(client) = state.registry.get(token.sub) {
+            state
+                .registry
+                .broadcast(token.sub, Event::ClientConnected(client.to_event()));
+        }
+
+        let result = tokio::select! {
+            result = self.queue.message_service(shutdown) => result.map_err(ConnectionError::from),
+            result = self.relay_messages(&token) => result,
+            result = self.ping() => result,
+            _ = self.congested.cancelled() => Err(ConnectionError::Congested),
+        };
+
+        state.registry.unregister(token.sub);
+        state.relay.disconnect(&state.registry, token.sub);
+        state.registry.broadcast(
+            token.sub,
+            Event::ClientDisconnected {
+                token_id: token.sub,
+                timestamp: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |duration| duration.as_secs()),
+            },
+        );
+
+        if result.is_ok() && shutdown.is_cancelled() {
+            self.push(&Message::Alert(Alert::ServerShutdown)).await?;
+            self.queue.message_service(shutdown).await?;
+        }
+        info!("Client disconnected");
+
+        result
+    }
+
+    async fn relay_messages(&self, token: &Token) -> Result<(), ConnectionError> {
+        loop {
+            match self.recv().await? {
+                Message::ApplicationData(data) => {
+                    self.state.relay.relay(&self.state.registry, token, data);
+                }
+                Message::Keepalive(Keepalive::Ping { id }) => {
+                    self.push(&Message::Keepalive(Keepalive::Pong { id }))
+                        .await?;
+                }
+                Message::Keepalive(Keepalive::Pong { id }) => self.keepalive.pong(id),
+                _ => return Err(ConnectionError::UnexpectedMessage),
+            }
+        }
+    }
+
+    /// Pings the client at each keepalive interval until it misses too many
+    /// pongs.
+    async fn ping(&self) -> Result<(), ConnectionError> {
+        let Some(mut interval) = self.keepalive.interval() else {
+            return std::future::pending().await;
+        };
+
+        loop {
+            interval.tick().await;
+            let ping = self.keepalive.ping()?;
+            self.push(&Message::Keepalive(ping)).await?;
+        }
+    }
+}
@@ -10,12 +10,22 @@
 
 pub mod connection;
 mod error;
+mod registry;
+mod relay;
 mod server;
 
 pub use error::Error;
-pub use server::Server;
+pub use server::{
+    DEFAULT_FLOW_IDLE_TIMEOUT, DEFAULT_MAX_RELAYED_CONNECTIONS, DEFAULT_SHUTDOWN_TIMEOUT, Server,
+};
 
-use std::net::SocketAddr;
+use crypto::{key::KeyMaterial, sign::TokenVerifier};
+use proto_core::{
+    common::{KeepalivePolicy, PriorityHints, QueueLimits, Scheduler},
+    sub_protocol::handshake::HandshakeLimits,
+    tunnel::RekeyPolicy,
+};
+use std::{net::SocketAddr, time::Duration};
 
 pub use proto_core;
 
@@ -29,7 +39,63 @@ pub struct ServerBuilder {
     pub addr: SocketAddr,
 
     /// Symmetric encryption key used for encryption.
-    pub encryption_key: Vec<u8>,
-    /// Signing key used for token authentication and message integrity.
-    pub signing_key: Vec<u8>,
+    pub encryption_key: KeyMaterial,
+    /// Verifier used for token authentication. Asymmetric verifiers only hold
+    /// the public key.
+    pub token_verifier: TokenVerifier,
+
+    /// Timeout, payload sizes and per-IP concurrency of handshakes.
+    pub handshake_limits: HandshakeLimits,
+
+    /// Limits after which the traffic keys of each connection are rekeyed.
+    pub rekey_policy: RekeyPolicy,
+    /// Interval of the keepalive pings sent to each client and number of
+    /// missed pongs after which it is disconnected.
+    pub keepalive_policy: KeepalivePolicy,
+
+    /// Capacity limits of the outgoing message queue of each connection.
+    /// Clients whose queue is full when a message is relayed to them are
+    /// disconnected, unless [`OverflowPolicy::DropOldest`] makes room. Relayed
+    /// stream data is bounded by the stream windows instead, and does not
+    /// count towards the limits.
+    ///
+    /// [`OverflowPolicy::DropOldest`]: proto_core::common::OverflowPolicy::DropOldest
+    pub queue_limits: QueueLimits,
+    /// Order in which the message queue of each connection serves its
+    /// priority levels.
+    pub scheduler: Scheduler,
+    /// Priority classes of relayed connections by port.
+    pub priority_hints: PriorityHints,
+    /// Time without datagrams in either direction after which a relayed
+    /// datagram flow is terminated on both clients.
+    pub flow_idle_timeout: Duration,
+    /// Number of connections and flows relayed for each client, which bounds
+    /// the stream data queued for it.
+    pub max_relayed_connections: usize,
+}
+
+impl ServerBuilder {
+    /// Creates a builder for a server bound to `addr`.
+    ///
+    /// Limits and policies take their defaults. Other settings are given with
+    /// the struct update syntax.
+    pub fn new(
+        addr: SocketAddr,
+        encryption_key: KeyMaterial,
+        token_verifier: TokenVerifier,
+    ) -> ServerBuilder {
+        ServerBuilder {
+            addr,
+            encryption_key,
+            token_verifier,
+            handshake_limits: HandshakeLimits::default(),
+            rekey_policy: RekeyPolicy::default(),
+            keepalive_policy: KeepalivePolicy::default(),
+            queue_limits: QueueLimits::default(),
+            scheduler: Scheduler::default(),
+            priority_hints: PriorityHints::default(),
+            flow_idle_timeout: DEFAULT_FLOW_IDLE_TIMEOUT,
+            max_relayed_connections: DEFAULT_MAX_RELAYED_CONNECTIONS,
+        }
+    }
 }
@@ -1 +1,81 @@
-fn main() {}
+//! VPN server binary.
+//!
+//! ```text
+//! server <addr> <encryption-key> <token-algorithm> <token-key>
+//! ```
+//!
+//! Keys are given as key sources, e.g. `hex:/etc/dehset/key` or
+//! `base64-env:DEHSET_KEY`. The token algorithm is one of `hs256`, `ed25519`
+//! or `es256`. SIGTERM and Ctrl-C shut the server down gracefully.
+
+use crypto::{
+    key::{KeyMaterial, KeySource},
+    sign::TokenVerifier,
+};
+use proto_core::algorithms::SignatureAlgorithm;
+use server::ServerBuilder;
+use std::env;
+use tracing::info;
+
+const USAGE: &str = "usage: server <addr> <encryption-key> <token-algorithm> <token-key>";
+
+fn signature_algorithm(name: &str) -> Option<SignatureAlgorithm> {
+    match name {
+        "hs256" => Some(SignatureAlgorithm::HmacSha256),
+        "ed25519" => Some(SignatureAlgorithm::Ed25519),
+        "es256" => Some(SignatureAlgorithm::EcdsaP256Sha256),
+        _ => None,
+    }
+}
+
+/// Resolves once the process receives SIGTERM or Ctrl-C.
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut sigterm = signal(SignalKind::terminate())?;
+        tokio::select! {
+            _ = sigterm.recv() => Ok(()),
+            ctrl_c = tokio::signal::ctrl_c() => ctrl_c,
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt().init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [addr, encryption_key, algorithm, token_key] = args.as_slice() else {
+        return Err(USAGE.into());
+    };
+    let algorithm = signature_algorithm(algorithm).ok_or(USAGE)?;
+
+    let token_key = KeyMaterial::load(&token_key.parse::<KeySource>()?)?;
+
+    let server = ServerBuilder::new(
+        addr.parse()?,
+        KeyMaterial::load(&encryption_key.parse::<KeySource>()?)?,
+        TokenVerifier::from_key(algorithm, &token_key)?,
+    )
+    .try_build()
+    .await?;
+
+    let shutdown = server.shutdown_token();
+    tokio::spawn(async move {
+        if let Err(io_error) = shutdown_signal().await {
+            info!("Could not listen for shutdown signals: {io_error}");
+            return;
+        }
+
+        info!("Received shutdown signal");
+        shutdown.cancel();
+    });
+
+    server.serve().await?;
+    Ok(())
+}
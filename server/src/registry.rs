@@ -0,0 +1,163 @@
+//! Registry of authenticated clients.
+
+use proto_core::{
+    common::{MessageSender, Priority, PushStatus},
+    sub_protocol::{
+        Message,
+        event::{self, Event},
+    },
+    token::Token,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{trace, warn};
+
+/// An authenticated client that can receive messages.
+#[derive(Clone)]
+pub(crate) struct ConnectedClient {
+    pub(crate) token: Arc<Token>,
+    pub(crate) sender: MessageSender,
+    /// Cancelled once the client no longer keeps up with its messages, which
+    /// disconnects it.
+    pub(crate) congested: CancellationToken,
+    /// The timestamp when the server accepted the client connection.
+    pub(crate) timestamp: u64,
+}
+
+/// Authenticated clients keyed by their token ID.
+#[derive(Default)]
+pub(crate) struct Registry {
+    clients: Mutex<HashMap<u64, ConnectedClient>>,
+}
+
+impl ConnectedClient {
+    /// Encodes and queues a message for the client.
+    ///
+    /// The message is queued without waiting, so a client that stops reading
+    /// never stalls the connection of another. If the queue of the client is
+    /// full and its overflow policy cannot make room, the client is
+    /// disconnected instead.
+    pub(crate) fn send(&self, message: &Message, priority: Priority) {
+        match message.encode() {
+            Ok(payload) => match self.sender.try_push_message(payload, priority) {
+                Ok(PushStatus::Queued) => {}
+                Ok(status) => trace!(self.token.sub, ?status, "Message queue overflow"),
+                Err(queue_error) => {
+                    warn!(
+                        self.token.sub,
+                        "Disconnecting congested client: {queue_error}"
+                    );
+                    self.congested.cancel();
+                }
+            },
+            Err(encode_error) => warn!("Could not encode message: {encode_error}"),
+        }
+    }
+
+    /// Encodes and queues stream data the client granted a window for.
+    ///
+    /// The data is bounded by the window instead of the queue limits, so a
+    /// client receiving on many streams at once is never taken for a
+    /// congested one.
+    pub(crate) fn send_credited(&self, message: &Message, priority: Priority) {
+        match message.encode() {
+            Ok(payload) => self.sender.push_credited(payload, priority),
+            Err(encode_error) => warn!("Could not encode message: {encode_error}"),
+        }
+    }
+
+    /// Encodes and queues a message the client may miss, such as a datagram.
+    /// The message is dropped if the queue of the client is full.
+    pub(crate) fn try_send(&self, message: &Message, priority: Priority) {
+        match message.encode() {
+            Ok(payload) => match self.sender.try_push_droppable(payload, priority) {
+                Ok(PushStatus::Dropped) => trace!(self.token.sub, "Dropped message"),
+                Ok(_) => {}
+                Err(queue_error) => trace!(self.token.sub, "Dropped message: {queue_error}"),
+            },
+            Err(encode_error) => warn!("Could not encode message: {encode_error}"),
+        }
+    }
+
+    /// Event description of the client.
+    pub(crate) fn to_event(&self) -> event::Client {
+        event::Client {
+            name: self.token.name.clone(),
+            tags: self.token.tags.clone(),
+            token_id: self.token.sub,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+impl Registry {
+    /// Registers a client. Returns `false` if a client with the same token ID
+    /// is already connected.
+    pub(crate) fn register(&self, client: ConnectedClient) -> bool {
+        let mut clients = self.clients.lock().unwrap();
+
+        if clients.contains_key(&client.token.sub) {
+            return false;
+        }
+
+        clients.insert(client.token.sub, client);
+        true
+    }
+
+    /// Removes a client from the registry.
+    pub(crate) fn unregister(&self, token_id: u64) -> Option<ConnectedClient> {
+        self.clients.lock().unwrap().remove(&token_id)
+    }
+
+    /// Looks up a connected client.
+    pub(crate) fn get(&self, token_id: u64) -> Option<ConnectedClient> {
+        self.clients.lock().unwrap().get(&token_id).cloned()
+    }
+
+    /// All connected clients except the one with the given token ID.
+    pub(crate) fn others(&self, token_id: u64) -> Vec<ConnectedClient> {
+        self.clients
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|client| client.token.sub != token_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Sends a message to a connected client. Messages to clients that are no
+    /// longer connected are dropped.
+    pub(crate) fn send(&self, token_id: u64, message: &Message, priority: Priority) {
+        if let Some(client) = self.get(token_id) {
+            client.send(message, priority);
+        }
+    }
+
+    /// Sends stream data within the window of a connected client, see
+    /// [`ConnectedClient::send_credited`].
+    pub(crate) fn send_credited(&self, token_id: u64, message: &Message, priority: Priority) {
+        if let Some(client) = self.get(token_id) {
+            client.send_credited(message, priority);
+        }
+    }
+
+    /// Sends a message that may be missed to a connected client, see
+    /// [`ConnectedClient::try_send`].
+    pub(crate) fn try_send(&self, token_id: u64, message: &Message, priority: Priority) {
+        if let Some(client) = self.get(token_id) {
+            client.try_send(message, priority);
+        }
+    }
+
+    /// Sends an event to all clients except the one with the given token ID.
+    pub(crate) fn broadcast(&self, except: u64, event: Event) {
+        let message = Message::Event(event);
+
+        for client in self.others(except) {
+            client.send(&message, Priority::from(message.content_type()));
+        }
+    }
+}
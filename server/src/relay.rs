@@ -0,0 +1,957 @@
+//! Relaying of application data between port-requesting and port-sharing
+//! clients.
+//!
+//! Each relayed connection has two routes, one per client, keyed by the
+//! client's token ID and its connection ID. The requesting client chooses its
+//! own connection ID, whereas the sharing client sees a server-assigned one
+//! with [`SERVER_ASSIGNED_ID`] set.
+//!
+//! Only the sharing client answers a connection request, and neither client
+//! may send data before it accepted the connection. Payloads that break these
+//! rules terminate the connection.
+//!
+//! Stream data within the window of the receiving client is queued regardless
+//! of the queue limits, as the windows already bound it. Instead, each client
+//! takes part in a limited number of relayed connections, and requests beyond
+//! it are refused with [`TerminateReason::TooManyConnections`].
+//!
+//! Datagram flows are routed the same way, and requested with the same scope
+//! checks, but their datagrams are not flow-controlled: datagrams that find
+//! the peer's queue full are dropped. Flows without datagrams in either
+//! direction for the flow idle timeout are terminated by the server too, so
+//! flows of unresponsive clients do not pile up.
+//!
+//! Routes are only inserted while the target client is registered, and all
+//! routes of a disconnected client are removed under the same lock, so no
+//! route outlives its clients.
+
+use crate::{
+    registry::Registry,
+    server::{DEFAULT_FLOW_IDLE_TIMEOUT, DEFAULT_MAX_RELAYED_CONNECTIONS},
+};
+use proto_core::{
+    common::{INITIAL_WINDOW_SIZE, Priority, PriorityHints},
+    sub_protocol::{
+        ContentType, Message,
+        application_data::{
+            ApplicationData, ApplicationDataEnum, MAX_DATAGRAM_SIZE, SERVER_ASSIGNED_ID,
+            TerminateReason,
+        },
+    },
+    token::Token,
+};
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+use tokio::time::Instant;
+use tracing::{debug, trace};
+
+/// Route key: token ID of the client and its connection ID.
+type RouteKey = (u64, u64);
+
+struct Route {
+    peer: RouteKey,
+    /// Class of all payloads of the connection, so they are never reordered.
+    priority: Priority,
+    /// Bytes the client may still send before the peer updates its window.
+    credit: u64,
+    /// Whether the client sent [`ApplicationDataEnum::Eof`].
+    eof: bool,
+    /// Whether the route carries a datagram flow instead of a stream.
+    flow: bool,
+    /// Whether the route belongs to the sharing client, which answers the
+    /// connection request.
+    sharing: bool,
+    /// Whether the sharing client accepted the connection. Flows need no
+    /// answer, so they are accepted right away.
+    accepted: bool,
+    /// Last time the client sent a datagram on the flow.
+    last_activity: Instant,
+}
+
+/// Routes of all relayed connections, the number of connections of each
+/// client and the next server-assigned ID, kept under one lock.
+#[derive(Default)]
+struct Routes {
+    routes: HashMap<RouteKey, Route>,
+    connections: HashMap<u64, usize>,
+    next_connection_id: u64,
+}
+
+impl Routes {
+    /// Number of connections relayed for the client with the given token ID.
+    fn connections(&self, token_id: u64) -> usize {
+        self.connections.get(&token_id).copied().unwrap_or_default()
+    }
+
+    /// Inserts a route and counts it towards its client.
+    fn insert(&mut self, key: RouteKey, route: Route) {
+        *self.connections.entry(key.0).or_default() += 1;
+        self.routes.insert(key, route);
+    }
+
+    /// Removes both routes of a connection and returns the peer's key.
+    fn remove(&mut self, key: RouteKey) -> Option<(RouteKey, Priority)> {
+        let route = self.routes.remove(&key)?;
+        self.routes.remove(&route.peer);
+
+        for token_id in [key.0, route.peer.0] {
+            if let Some(count) = self.connections.get_mut(&token_id) {
+                *count -= 1;
+                if *count == 0 {
+                    self.connections.remove(&token_id);
+                }
+            }
+        }
+
+        Some((route.peer, route.priority))
+    }
+}
+
+/// Table of relayed connections.
+pub(crate) struct Relay {
+    routes: Mutex<Routes>,
+    priority_hints: PriorityHints,
+    flow_idle_timeout: Duration,
+    max_connections: usize,
+}
+
+impl Default for Relay {
+    fn default() -> Self {
+        Relay::new(
+            PriorityHints::default(),
+            DEFAULT_FLOW_IDLE_TIMEOUT,
+            DEFAULT_MAX_RELAYED_CONNECTIONS,
+        )
+    }
+}
+
+/// Builds an application data message.
+fn application_data(connection_id: u64, payload: ApplicationDataEnum) -> Message {
+    Message::ApplicationData(ApplicationData {
+        connection_id,
+        payload,
+    })
+}
+
+/// Builds a termination message with the given reason.
+fn terminate(connection_id: u64, reason: TerminateReason, detail: Option<&str>) -> Message {
+    application_data(
+        connection_id,
+        ApplicationDataEnum::TerminateConnection {
+            reason,
+            detail: detail.map(String::from),
+        },
+    )
+}
+
+impl Relay {
+    /// Creates an empty relay that prioritizes connections by port, expires
+    /// flows after `flow_idle_timeout` and relays at most `max_connections`
+    /// connections and flows per client.
+    pub(crate) fn new(
+        priority_hints: PriorityHints,
+        flow_idle_timeout: Duration,
+        max_connections: usize,
+    ) -> Relay {
+        Relay {
+            routes: Mutex::default(),
+            priority_hints,
+            flow_idle_timeout,
+            max_connections,
+        }
+    }
+
+    /// Interval at which [`Self::expire_flows`] should run, so flows are
+    /// terminated at most half their idle timeout late.
+    pub(crate) fn expiry_interval(&self) -> Duration {
+        (self.flow_idle_timeout / 2).max(Duration::from_millis(10))
+    }
+
+    /// Handles application data sent by the client holding `from`.
+    pub(crate) fn relay(&self, registry: &Registry, from: &Token, data: ApplicationData) {
+        let key = (from.sub, data.connection_id);
+
+        if matches!(
+            data.payload,
+            ApplicationDataEnum::Data { .. }
+                | ApplicationDataEnum::Datagram { .. }
+                | ApplicationDataEnum::WindowUpdate { .. }
+                | ApplicationDataEnum::Eof
+        ) && let Some((peer, priority)) = self.unaccepted(key)
+        {
+            let reason = TerminateReason::PermissionDenied;
+            self.violation(registry, key, peer, priority, reason, Some("not accepted"));
+            return;
+        }
+
+        match data.payload {
+            ApplicationDataEnum::RequestConnection { token_id, port } => {
+                self.request_connection(registry, from, key, token_id, port, false);
+            }
+            ApplicationDataEnum::RequestFlow { token_id, port } => {
+                self.request_connection(registry, from, key, token_id, port, true);
+            }
+            ApplicationDataEnum::NewConnection { .. } | ApplicationDataEnum::NewFlow { .. } => {
+                debug!(?key, "Client sent a new connection payload");
+                registry.send(
+                    key.0,
+                    &terminate(
+                        key.1,
+                        TerminateReason::ProtocolViolation,
+                        Some("unexpected payload"),
+                    ),
+                    Priority::from(ContentType::ApplicationData),
+                );
+            }
+            ApplicationDataEnum::Connection { accept } => match self.answer(key, accept) {
+                Some(Ok(((token_id, connection_id), priority))) => {
+                    let message =
+                        application_data(connection_id, ApplicationDataEnum::Connection { accept });
+                    registry.send(token_id, &message, priority);
+                }
+                Some(Err((peer, priority))) => {
+                    let reason = TerminateReason::ProtocolViolation;
+                    self.violation(
+                        registry,
+                        key,
+                        peer,
+                        priority,
+                        reason,
+                        Some("unexpected answer"),
+                    );
+                }
+                // The request may have been terminated in the meantime.
+                None => {}
+            },
+            ApplicationDataEnum::Data { payload } => {
+                let peer = {
+                    let mut routes = self.routes.lock().unwrap();
+                    routes.routes.get_mut(&key).map(|route| {
+                        let violation = if route.flow {
+                            Some((TerminateReason::ProtocolViolation, Some("data on a flow")))
+                        } else if route.eof {
+                            Some((TerminateReason::ProtocolViolation, Some("data after eof")))
+                        } else if payload.len() as u64 > route.credit {
+                            Some((TerminateReason::FlowControlViolation, None))
+                        } else {
+                            None
+                        };
+                        route.credit = route.credit.saturating_sub(payload.len() as u64);
+                        (route.peer, route.priority, violation)
+                    })
+                };
+
+                match peer {
+                    Some((peer, priority, None)) => {
+                        // The credit bounds the data queued for the peer.
+                        let message =
+                            application_data(peer.1, ApplicationDataEnum::Data { payload });
+                        registry.send_credited(peer.0, &message, priority);
+                    }
+                    Some((peer, priority, Some((reason, detail)))) => {
+                        self.violation(registry, key, peer, priority, reason, detail);
+                    }
+                    None => Self::unknown_connection(registry, key),
+                }
+            }
+            ApplicationDataEnum::Datagram { payload } => {
+                let peer = self
+                    .routes
+                    .lock()
+                    .unwrap()
+                    .routes
+                    .get_mut(&key)
+                    .map(|route| {
+                        let violation = if !route.flow {
+                            Some("datagram on a stream")
+                        } else if payload.len() > MAX_DATAGRAM_SIZE {
+                            Some("datagram too large")
+                        } else {
+                            route.last_activity = Instant::now();
+                            None
+                        };
+                        (route.peer, route.priority, violation)
+                    });
+
+                match peer {
+                    Some((peer, priority, None)) => {
+                        // Like on a congested network, datagrams are
+                        // dropped when the peer does not keep up.
+                        let message =
+                            application_data(peer.1, ApplicationDataEnum::Datagram { payload });
+                        registry.try_send(peer.0, &message, priority);
+                    }
+                    Some((peer, priority, Some(detail))) => {
+                        let reason = TerminateReason::ProtocolViolation;
+                        self.violation(registry, key, peer, priority, reason, Some(detail));
+                    }
+                    None => Self::unknown_connection(registry, key),
+                }
+            }
+            ApplicationDataEnum::WindowUpdate { increment } => {
+                let peer = {
+                    let routes = &mut self.routes.lock().unwrap().routes;
+                    routes
+                        .get(&key)
+                        .map(|route| (route.peer, route.priority))
+                        .inspect(|(peer, _)| {
+                            if let Some(route) = routes.get_mut(peer) {
+                                route.credit += increment as u64;
+                            }
+                        })
+                };
+
+                match peer {
+                    Some((peer, priority)) => {
+                        let message = application_data(
+                            peer.1,
+                            ApplicationDataEnum::WindowUpdate { increment },
+                        );
+                        registry.send(peer.0, &message, priority);
+                    }
+                    None => Self::unknown_connection(registry, key),
+                }
+            }
+            ApplicationDataEnum::TerminateConnection { reason, detail } => {
+                if let Some((peer, priority)) = self.remove(key) {
+                    trace!(?key, ?detail, "Connection terminated: {reason}");
+                    let message = application_data(
+                        peer.1,
+                        ApplicationDataEnum::TerminateConnection { reason, detail },
+                    );
+                    registry.send(peer.0, &message, priority);
+                }
+            }
+            ApplicationDataEnum::Eof => match self.eof(key) {
+                Some((peer, priority)) => {
+                    let message = application_data(peer.1, ApplicationDataEnum::Eof);
+                    registry.send(peer.0, &message, priority);
+                }
+                None => Self::unknown_connection(registry, key),
+            },
+        }
+    }
+
+    /// Relays a connection, or a datagram flow if `flow` is set, after
+    /// checking the requesting client may request `port` from the target.
+    fn request_connection(
+        &self,
+        registry: &Registry,
+        from: &Token,
+        key: RouteKey,
+        token_id: u64,
+        port: u16,
+        flow: bool,
+    ) {
+        let priority = self.priority_hints.for_port(port);
+
+        if key.1 & SERVER_ASSIGNED_ID != 0 {
+            Self::invalid_connection_id(registry, key, priority);
+            return;
+        }
+
+        // The target is looked up under the routes' lock, so it cannot be
+        // disconnected between the lookup and the insertion of its route.
+        let mut routes = self.routes.lock().unwrap();
+        if routes.routes.contains_key(&key) {
+            drop(routes);
+            Self::invalid_connection_id(registry, key, priority);
+            return;
+        }
+
+        let Some(target) = registry.get(token_id) else {
+            drop(routes);
+            registry.send(
+                key.0,
+                &terminate(
+                    key.1,
+                    TerminateReason::PeerDisconnected,
+                    Some("peer not connected"),
+                ),
+                priority,
+            );
+            return;
+        };
+
+        if !from.can_request_port(&target.token, port) {
+            drop(routes);
+            debug!(?key, token_id, port, "Permission denied");
+            registry.send(
+                key.0,
+                &terminate(key.1, TerminateReason::PermissionDenied, None),
+                priority,
+            );
+            return;
+        }
+
+        // Stream data is only bounded by the windows of the connections, so
+        // their number bounds the queue of each client.
+        if routes.connections(key.0) >= self.max_connections
+            || routes.connections(token_id) >= self.max_connections
+        {
+            drop(routes);
+            debug!(?key, token_id, "Too many connections");
+            registry.send(
+                key.0,
+                &terminate(key.1, TerminateReason::TooManyConnections, None),
+                priority,
+            );
+            return;
+        }
+
+        let peer = (token_id, routes.next_connection_id | SERVER_ASSIGNED_ID);
+        routes.next_connection_id += 1;
+        let last_activity = Instant::now();
+        routes.insert(
+            key,
+            Route {
+                peer,
+                priority,
+                credit: INITIAL_WINDOW_SIZE as u64,
+                eof: false,
+                flow,
+                sharing: false,
+                accepted: flow,
+                last_activity,
+            },
+        );
+        routes.insert(
+            peer,
+            Route {
+                peer: key,
+                priority,
+                credit: INITIAL_WINDOW_SIZE as u64,
+                eof: false,
+                flow,
+                sharing: true,
+                accepted: flow,
+                last_activity,
+            },
+        );
+        drop(routes);
+
+        trace!(?key, ?peer, port, ?priority, flow, "Relaying connection");
+        let payload = if flow {
+            ApplicationDataEnum::NewFlow { port }
+        } else {
+            ApplicationDataEnum::NewConnection { port }
+        };
+        target.send(&application_data(peer.1, payload), priority);
+    }
+
+    /// Terminates a connection whose client sent an invalid payload.
+    fn violation(
+        &self,
+        registry: &Registry,
+        key: RouteKey,
+        peer: RouteKey,
+        priority: Priority,
+        reason: TerminateReason,
+        detail: Option<&str>,
+    ) {
+        debug!(?key, ?detail, "Protocol violation: {reason}");
+        self.remove(key);
+        registry.send(key.0, &terminate(key.1, reason, detail), priority);
+        registry.send(peer.0, &terminate(peer.1, reason, detail), priority);
+    }
+
+    /// Answers connection requests that reuse a connection ID or take a
+    /// server-assigned one.
+    fn invalid_connection_id(registry: &Registry, key: RouteKey, priority: Priority) {
+        registry.send(
+            key.0,
+            &terminate(
+                key.1,
+                TerminateReason::ProtocolViolation,
+                Some("invalid connection id"),
+            ),
+            priority,
+        );
+    }
+
+    /// Answers messages for connections that are not relayed.
+    fn unknown_connection(registry: &Registry, key: RouteKey) {
+        registry.send(
+            key.0,
+            &terminate(
+                key.1,
+                TerminateReason::ProtocolViolation,
+                Some("unknown connection"),
+            ),
+            Priority::from(ContentType::ApplicationData),
+        );
+    }
+
+    /// Returns the peer's key if the connection is not accepted yet.
+    fn unaccepted(&self, key: RouteKey) -> Option<(RouteKey, Priority)> {
+        self.routes
+            .lock()
+            .unwrap()
+            .routes
+            .get(&key)
+            .filter(|route| !route.accepted)
+            .map(|route| (route.peer, route.priority))
+    }
+
+    /// Applies the answer of the sharing client to a connection request and
+    /// returns the peer's key. Answers of the requesting client, repeated
+    /// answers and answers to flows are returned as errors.
+    fn answer(
+        &self,
+        key: RouteKey,
+        accept: bool,
+    ) -> Option<Result<(RouteKey, Priority), (RouteKey, Priority)>> {
+        let mut routes = self.routes.lock().unwrap();
+        let route = routes.routes.get(&key)?;
+        let (peer, priority) = (route.peer, route.priority);
+
+        if !route.sharing || route.accepted {
+            return Some(Err((peer, priority)));
+        }
+
+        if accept {
+            for key in [key, peer] {
+                if let Some(route) = routes.routes.get_mut(&key) {
+                    route.accepted = true;
+                }
+            }
+        } else {
+            routes.remove(key);
+        }
+
+        Some(Ok((peer, priority)))
+    }
+
+    /// Records the end of the data sent by `key` and returns the peer's key.
+    /// Both routes are removed once the peer has ended its data too.
+    fn eof(&self, key: RouteKey) -> Option<(RouteKey, Priority)> {
+        let mut routes = self.routes.lock().unwrap();
+        let route = routes.routes.get_mut(&key)?;
+        route.eof = true;
+        let (peer, priority) = (route.peer, route.priority);
+
+        if routes.routes.get(&peer).is_some_and(|route| route.eof) {
+            routes.remove(key);
+        }
+
+        Some((peer, priority))
+    }
+
+    /// Removes both routes of a connection and returns the peer's key.
+    fn remove(&self, key: RouteKey) -> Option<(RouteKey, Priority)> {
+        self.routes.lock().unwrap().remove(key)
+    }
+
+    /// Terminates all connections of a disconnected client. The client must
+    /// be unregistered first, so no new route to it is inserted.
+    pub(crate) fn disconnect(&self, registry: &Registry, token_id: u64) {
+        let peers: Vec<_> = {
+            let mut routes = self.routes.lock().unwrap();
+            let keys: Vec<_> = routes
+                .routes
+                .keys()
+                .filter(|key| key.0 == token_id)
+                .copied()
+                .collect();
+
+            keys.into_iter()
+                .filter_map(|key| routes.remove(key))
+                .collect()
+        };
+
+        for (peer, priority) in peers {
+            registry.send(
+                peer.0,
+                &terminate(peer.1, TerminateReason::PeerDisconnected, None),
+                priority,
+            );
+        }
+    }
+
+    /// Terminates the flows without datagrams in either direction since
+    /// the flow idle timeout before `now`.
+    pub(crate) fn expire_flows(&self, registry: &Registry, now: Instant) {
+        let expired: Vec<_> = {
+            let mut routes = self.routes.lock().unwrap();
+            let idle =
+                |route: &Route| now.duration_since(route.last_activity) >= self.flow_idle_timeout;
+            let keys: Vec<_> = routes
+                .routes
+                .iter()
+                .filter(|(_, route)| {
+                    route.flow
+                        && route.sharing
+                        && idle(route)
+                        && routes.routes.get(&route.peer).is_none_or(idle)
+                })
+                .map(|(key, _)| *key)
+                .collect();
+
+            keys.into_iter()
+                .filter_map(|key| Some((key, routes.remove(key)?)))
+                .collect()
+        };
+
+        for (key, (peer, priority)) in expired {
+            trace!(?key, ?peer, "Flow expired");
+            for (token_id, connection_id) in [key, peer] {
+                registry.send(
+                    token_id,
+                    &terminate(connection_id, TerminateReason::IdleTimeout, None),
+                    priority,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Relay;
+    use crate::{
+        registry::{ConnectedClient, Registry},
+        server::{DEFAULT_FLOW_IDLE_TIMEOUT, DEFAULT_MAX_RELAYED_CONNECTIONS},
+    };
+    use crypto::{symm::Aes128CbcSha256, tls::SymmTls};
+    use proto_core::{
+        common::{INITIAL_WINDOW_SIZE, MAX_DATA_CHUNK, MessageQueue, OverflowPolicy, QueueLimits},
+        sub_protocol::{
+            application_data::{
+                ApplicationData, ApplicationDataEnum, SERVER_ASSIGNED_ID, TerminateReason,
+            },
+            event::Event,
+        },
+        tunnel::Tunnel,
+    };
+    use std::{sync::Arc, thread, time::Duration};
+    use testutil::generate_token;
+    use tokio::{
+        io::{ReadHalf, SimplexStream, WriteHalf, simplex},
+        time::Instant,
+    };
+    use tokio_util::sync::CancellationToken;
+
+    type TestQueue = MessageQueue<ReadHalf<SimplexStream>, WriteHalf<SimplexStream>, SymmTls>;
+
+    const PORT: u16 = 9;
+
+    /// Registers a client whose queue is never served, as if it stopped
+    /// reading.
+    fn register(registry: &Registry, id: u64, limits: QueueLimits) -> (TestQueue, ConnectedClient) {
+        let (r, w) = simplex(usize::MAX);
+        let encrypter = Arc::new(Aes128CbcSha256::try_new(&[0; 16]).unwrap());
+        let tls = SymmTls::new(([0; 32], [0; 32]), encrypter);
+        let queue = MessageQueue::new(Arc::new(Tunnel::new(r, w, tls))).with_limits(limits);
+        let client = ConnectedClient {
+            token: Arc::new(generate_token(
+                id,
+                id.to_string(),
+                vec![String::from("peer")],
+            )),
+            sender: queue.sender(),
+            congested: CancellationToken::new(),
+            timestamp: 0,
+        };
+        assert!(registry.register(client.clone()));
+
+        (queue, client)
+    }
+
+    fn data(connection_id: u64, payload: ApplicationDataEnum) -> ApplicationData {
+        ApplicationData {
+            connection_id,
+            payload,
+        }
+    }
+
+    #[test]
+    fn congested_peer_is_disconnected() {
+        let registry = Registry::default();
+        let relay = Relay::default();
+        let (_requester_queue, requester) = register(&registry, 1, QueueLimits::default());
+        let (_peer_queue, peer) = register(
+            &registry,
+            2,
+            QueueLimits {
+                per_queue: 2,
+                total: 2,
+                overflow: OverflowPolicy::Wait,
+            },
+        );
+        let (other_queue, other) = register(&registry, 3, QueueLimits::default());
+
+        // The peer's queue holds two new connections, so the third finds it
+        // full.
+        for connection_id in 0..3 {
+            let request = ApplicationDataEnum::RequestConnection {
+                token_id: 2,
+                port: PORT,
+            };
+            relay.relay(&registry, &requester.token, data(connection_id, request));
+        }
+        assert!(peer.congested.is_cancelled());
+        assert!(!requester.congested.is_cancelled());
+
+        // The other clients are still served.
+        registry.broadcast(2, Event::ListClients(vec![]));
+        assert_eq!(other_queue.metrics().depth, [1, 0, 0]);
+        assert!(!other.congested.is_cancelled());
+    }
+
+    #[test]
+    fn data_within_windows_never_congests() {
+        const STREAMS: u64 = 128;
+        const CHUNKS: usize = INITIAL_WINDOW_SIZE as usize / MAX_DATA_CHUNK;
+
+        let registry = Registry::default();
+        let relay = Relay::default();
+        let (_requester_queue, requester) = register(&registry, 1, QueueLimits::default());
+        let (peer_queue, peer) = register(&registry, 2, QueueLimits::default());
+
+        for connection_id in 0..STREAMS {
+            let request = ApplicationDataEnum::RequestConnection {
+                token_id: 2,
+                port: PORT,
+            };
+            relay.relay(&registry, &requester.token, data(connection_id, request));
+            let accept = ApplicationDataEnum::Connection { accept: true };
+            let peer_id = SERVER_ASSIGNED_ID | connection_id;
+            relay.relay(&registry, &peer.token, data(peer_id, accept));
+        }
+
+        // Every stream fills the window of the peer, which far exceeds the
+        // message limits of its queue.
+        for connection_id in 0..STREAMS {
+            for _ in 0..CHUNKS {
+                let chunk = ApplicationDataEnum::Data {
+                    payload: vec![0; MAX_DATA_CHUNK],
+                };
+                relay.relay(&registry, &requester.token, data(connection_id, chunk));
+            }
+        }
+        assert!(!peer.congested.is_cancelled());
+        let depth = STREAMS as usize * (CHUNKS + 1);
+        assert_eq!(peer_queue.metrics().total_depth(), depth);
+        assert_eq!(
+            relay.routes.lock().unwrap().routes.len(),
+            2 * STREAMS as usize
+        );
+
+        // Data beyond the window only terminates its stream.
+        let chunk = ApplicationDataEnum::Data { payload: vec![0] };
+        relay.relay(&registry, &requester.token, data(0, chunk));
+        assert!(!peer.congested.is_cancelled());
+        assert!(!requester.congested.is_cancelled());
+        assert_eq!(peer_queue.metrics().total_depth(), depth + 1);
+        assert_eq!(
+            relay.routes.lock().unwrap().routes.len(),
+            2 * STREAMS as usize - 2
+        );
+    }
+
+    #[test]
+    fn too_many_connections_are_refused() {
+        let registry = Registry::default();
+        let relay = Relay::new(Default::default(), DEFAULT_FLOW_IDLE_TIMEOUT, 2);
+        let (requester_queue, requester) = register(&registry, 1, QueueLimits::default());
+        let (_peer_queue, _peer) = register(&registry, 2, QueueLimits::default());
+
+        for connection_id in 0..3 {
+            let request = ApplicationDataEnum::RequestConnection {
+                token_id: 2,
+                port: PORT,
+            };
+            relay.relay(&registry, &requester.token, data(connection_id, request));
+        }
+        assert_eq!(relay.routes.lock().unwrap().routes.len(), 4);
+        assert_eq!(requester_queue.metrics().total_depth(), 1);
+
+        // Terminating a connection makes room for another.
+        let terminate = ApplicationDataEnum::TerminateConnection {
+            reason: TerminateReason::Closed,
+            detail: None,
+        };
+        relay.relay(&registry, &requester.token, data(0, terminate));
+        let request = ApplicationDataEnum::RequestConnection {
+            token_id: 2,
+            port: PORT,
+        };
+        relay.relay(&registry, &requester.token, data(3, request));
+        assert_eq!(relay.routes.lock().unwrap().routes.len(), 4);
+        assert_eq!(requester_queue.metrics().total_depth(), 1);
+    }
+
+    #[test]
+    fn datagrams_to_congested_peer_are_dropped() {
+        let registry = Registry::default();
+        let relay = Relay::default();
+        let (_requester_queue, requester) = register(&registry, 1, QueueLimits::default());
+        let (peer_queue, peer) = register(
+            &registry,
+            2,
+            QueueLimits {
+                per_queue: 2,
+                total: 2,
+                overflow: OverflowPolicy::Wait,
+            },
+        );
+
+        let request = ApplicationDataEnum::RequestFlow {
+            token_id: 2,
+            port: PORT,
+        };
+        relay.relay(&registry, &requester.token, data(1, request));
+        for _ in 0..3 {
+            let datagram = ApplicationDataEnum::Datagram {
+                payload: vec![0; 8],
+            };
+            relay.relay(&registry, &requester.token, data(1, datagram));
+        }
+
+        // The new flow and one datagram fit, the others are dropped.
+        let metrics = peer_queue.metrics();
+        assert_eq!((metrics.total_depth(), metrics.rejected), (2, 2));
+        assert!(!peer.congested.is_cancelled());
+    }
+
+    #[test]
+    fn only_the_sharing_client_accepts() {
+        let registry = Registry::default();
+        let relay = Relay::default();
+        let (_requester_queue, requester) = register(&registry, 1, QueueLimits::default());
+        let (peer_queue, peer) = register(&registry, 2, QueueLimits::default());
+
+        let request = ApplicationDataEnum::RequestConnection {
+            token_id: 2,
+            port: PORT,
+        };
+        relay.relay(&registry, &requester.token, data(1, request));
+
+        // The requester cannot answer its own request.
+        let accept = ApplicationDataEnum::Connection { accept: true };
+        relay.relay(&registry, &requester.token, data(1, accept));
+        assert!(relay.routes.lock().unwrap().routes.is_empty());
+        // The peer got the new connection and its termination.
+        assert_eq!(peer_queue.metrics().total_depth(), 2);
+
+        let request = ApplicationDataEnum::RequestConnection {
+            token_id: 2,
+            port: PORT,
+        };
+        relay.relay(&registry, &requester.token, data(2, request));
+        let accept = ApplicationDataEnum::Connection { accept: true };
+        relay.relay(&registry, &peer.token, data(SERVER_ASSIGNED_ID | 1, accept));
+
+        let chunk = ApplicationDataEnum::Data {
+            payload: vec![0; 8],
+        };
+        relay.relay(&registry, &requester.token, data(2, chunk));
+        assert_eq!(relay.routes.lock().unwrap().routes.len(), 2);
+        assert_eq!(peer_queue.metrics().total_depth(), 4);
+
+        // A second answer is a protocol violation.
+        let accept = ApplicationDataEnum::Connection { accept: true };
+        relay.relay(&registry, &peer.token, data(SERVER_ASSIGNED_ID | 1, accept));
+        assert!(relay.routes.lock().unwrap().routes.is_empty());
+    }
+
+    #[test]
+    fn data_before_acceptance_is_refused() {
+        let registry = Registry::default();
+        let relay = Relay::default();
+        let (_requester_queue, requester) = register(&registry, 1, QueueLimits::default());
+        let (peer_queue, peer) = register(&registry, 2, QueueLimits::default());
+
+        // Neither the requester nor the sharing client may send first. The
+        // server assigns the sharing client's IDs in order.
+        let senders = [(&requester.token, 1), (&peer.token, SERVER_ASSIGNED_ID | 1)];
+        for (token, connection_id) in senders {
+            let request = ApplicationDataEnum::RequestConnection {
+                token_id: 2,
+                port: PORT,
+            };
+            relay.relay(&registry, &requester.token, data(1, request));
+            let chunk = ApplicationDataEnum::Data {
+                payload: vec![0; 8],
+            };
+            relay.relay(&registry, token, data(connection_id, chunk));
+
+            // The data is not relayed, and the connection is terminated.
+            assert!(relay.routes.lock().unwrap().routes.is_empty());
+        }
+        // Two new connections and their terminations.
+        assert_eq!(peer_queue.metrics().total_depth(), 4);
+    }
+
+    #[test]
+    fn idle_flows_expire() {
+        let registry = Registry::default();
+        let relay = Relay::new(
+            Default::default(),
+            Duration::from_secs(60),
+            DEFAULT_MAX_RELAYED_CONNECTIONS,
+        );
+        let (requester_queue, requester) = register(&registry, 1, QueueLimits::default());
+        let (peer_queue, _peer) = register(&registry, 2, QueueLimits::default());
+
+        for connection_id in [1, 2] {
+            let request = ApplicationDataEnum::RequestFlow {
+                token_id: 2,
+                port: PORT,
+            };
+            relay.relay(&registry, &requester.token, data(connection_id, request));
+        }
+        let requested = Instant::now();
+
+        // A datagram keeps the second flow alive.
+        let datagram = ApplicationDataEnum::Datagram {
+            payload: vec![0; 8],
+        };
+        relay.relay(&registry, &requester.token, data(2, datagram));
+
+        relay.expire_flows(&registry, requested + Duration::from_secs(59));
+        assert_eq!(relay.routes.lock().unwrap().routes.len(), 4);
+
+        relay.expire_flows(&registry, requested + Duration::from_secs(60));
+        let routes = relay.routes.lock().unwrap();
+        assert!(routes.routes.contains_key(&(1, 2)));
+        assert!(!routes.routes.contains_key(&(1, 1)));
+        assert_eq!(routes.routes.len(), 2);
+        drop(routes);
+
+        // Both clients are told about the expiry.
+        assert_eq!(requester_queue.metrics().total_depth(), 1);
+        assert_eq!(peer_queue.metrics().total_depth(), 4);
+    }
+
+    #[test]
+    fn no_routes_outlive_a_disconnect() {
+        let registry = Arc::new(Registry::default());
+        let relay = Arc::new(Relay::default());
+        let (_requester_queue, requester) = register(&registry, 1, QueueLimits::default());
+        let (_peer_queue, _peer) = register(&registry, 2, QueueLimits::default());
+
+        let requests = thread::spawn({
+            let (registry, relay) = (Arc::clone(&registry), Arc::clone(&relay));
+            move || {
+                for connection_id in 0..1000 {
+                    let request = ApplicationDataEnum::RequestFlow {
+                        token_id: 2,
+                        port: PORT,
+                    };
+                    relay.relay(&registry, &requester.token, data(connection_id, request));
+                }
+            }
+        });
+
+        // Disconnects the peer the way a connection ends, while the
+        // requests are relayed.
+        while relay.routes.lock().unwrap().routes.is_empty() {
+            thread::yield_now();
+        }
+        registry.unregister(2);
+        relay.disconnect(&registry, 2);
+
+        requests.join().unwrap();
+        assert!(relay.routes.lock().unwrap().routes.is_empty());
+    }
+}
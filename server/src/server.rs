@@ -1,11 +1,36 @@
 use crate::{
     Error, ServerBuilder,
-    connection::{Connection, do_handshake},
+    connection::{Connection, PendingHandshakes, do_handshake, send_alert},
+    registry::Registry,
+    relay::Relay,
 };
-use crypto::{sign::Hs256, symm::Aes128CbcSha256, tls::SymmTls};
-use std::sync::Arc;
-use tokio::net::TcpListener;
-use tracing::{info, instrument, trace};
+use crypto::{sign::TokenVerifier, symm::Aes128CbcSha256, tls::SymmTls};
+use proto_core::{
+    common::{KeepalivePolicy, QueueLimits, Scheduler},
+    sub_protocol::handshake::{HandshakeAlert, HandshakeLimits},
+    tunnel::{RekeyPolicy, Tunnel},
+};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::JoinSet,
+    time::{MissedTickBehavior, interval, timeout},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument, trace, warn};
+
+/// Default time connections are given to close during a graceful shutdown.
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default time without datagrams in either direction after which the server
+/// terminates a datagram flow. It exceeds the clients' default, so clients
+/// normally expire their flows first.
+pub const DEFAULT_FLOW_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Default number of connections and flows relayed for each client. Each
+/// stream may queue up to its window for the client, so this bounds the
+/// memory held for a client to 64 MiB.
+pub const DEFAULT_MAX_RELAYED_CONNECTIONS: usize = 256;
 
 /// Internal VPN server struct holding shared state.
 #[derive(Debug)]
@@ -14,68 +39,209 @@ pub struct Server {
     pub(crate) shared_state: SharedState,
     pub(crate) tcp_listener: TcpListener,
     pub(crate) encrypter: Aes128CbcSha256,
+    pub(crate) shutdown: CancellationToken,
+    pub(crate) shutdown_timeout: Duration,
 }
 
-#[derive(Debug)]
 pub(crate) struct SharedState {
-    pub(crate) _signer: Hs256,
+    pub(crate) token_verifier: TokenVerifier,
+    pub(crate) registry: Registry,
+    pub(crate) relay: Relay,
+    pub(crate) handshake_limits: HandshakeLimits,
+    pub(crate) pending_handshakes: PendingHandshakes,
+    pub(crate) rekey_policy: RekeyPolicy,
+    pub(crate) keepalive_policy: KeepalivePolicy,
+    pub(crate) queue_limits: QueueLimits,
+    pub(crate) scheduler: Scheduler,
+}
+
+impl std::fmt::Debug for SharedState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedState")
+            .field("token_verifier", &self.token_verifier)
+            .field("handshake_limits", &self.handshake_limits)
+            .field("rekey_policy", &self.rekey_policy)
+            .field("keepalive_policy", &self.keepalive_policy)
+            .field("queue_limits", &self.queue_limits)
+            .field("scheduler", &self.scheduler)
+            .finish_non_exhaustive()
+    }
 }
 
 impl ServerBuilder {
     /// Consumes `self` and builds a [`Server`] instance.
     #[instrument(skip(self), fields(self.addr))]
     pub async fn try_build(self) -> Result<Server, Error> {
-        let signer = Hs256::try_new(&self.signing_key)?;
-        let encrypter = Aes128CbcSha256::new(self.encryption_key);
+        let encrypter = Aes128CbcSha256::try_new(self.encryption_key.as_bytes())?;
         let tcp_listener = TcpListener::bind(self.addr).await?;
         trace!(%self.addr, "Bind socket");
 
         Ok(Server {
-            shared_state: SharedState { _signer: signer },
+            shared_state: SharedState {
+                token_verifier: self.token_verifier,
+                registry: Registry::default(),
+                relay: Relay::new(
+                    self.priority_hints,
+                    self.flow_idle_timeout,
+                    self.max_relayed_connections,
+                ),
+                handshake_limits: self.handshake_limits,
+                pending_handshakes: PendingHandshakes::default(),
+                rekey_policy: self.rekey_policy,
+                keepalive_policy: self.keepalive_policy,
+                queue_limits: self.queue_limits,
+                scheduler: self.scheduler,
+            },
             encrypter,
             tcp_listener,
+            shutdown: CancellationToken::new(),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
         })
     }
 }
 
 impl Server {
-    /// Serves the server forever.
+    /// Returns the local address the server is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.tcp_listener.local_addr()?)
+    }
+
+    /// Returns a token that shuts the server down gracefully when cancelled.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// Sets the time connections are given to flush their messages and close
+    /// during a graceful shutdown. Defaults to [`DEFAULT_SHUTDOWN_TIMEOUT`].
+    pub fn with_shutdown_timeout(mut self, shutdown_timeout: Duration) -> Server {
+        self.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// Serves the server until its [`shutdown_token`](Self::shutdown_token)
+    /// is cancelled.
+    ///
+    /// On shutdown, the server stops accepting connections. Authenticated
+    /// clients receive their queued messages followed by a closure alert.
+    /// Connections that do not close within the shutdown timeout are aborted.
     #[instrument(skip(self))]
     pub async fn serve(self) -> Result<(), Error> {
         let encrypter = Arc::new(self.encrypter);
         let shared_state = Arc::new(self.shared_state);
+        let mut connections = JoinSet::new();
+        let mut flow_expiry = interval(shared_state.relay.expiry_interval());
+        flow_expiry.set_missed_tick_behavior(MissedTickBehavior::Delay);
         trace!("Serving the server");
 
         loop {
-            let (mut tcp_stream, remote_addr) = self.tcp_listener.accept().await?;
-            info!("Got connection from {remote_addr}");
-            tokio::spawn({
-                let encrpter = Arc::clone(&encrypter);
-                let shared_state = Arc::clone(&shared_state);
-                async move {
-                    let state = Arc::clone(&shared_state);
-
-                    let (mut r, mut w) = tcp_stream.split();
-
-                    match do_handshake(&mut r, &mut w).await {
-                        Err(handshake_alert) => {
-                            info!("Could not complete handshake: {handshake_alert:?}");
-                            // TODO: Send handshake alerts to the client.
-                        }
-                        Ok((server_random, client_random)) => {
-                            let tls = SymmTls::new((server_random, client_random), encrpter);
-
-                            let mut _connection = Connection {
-                                _tcp_stream: tcp_stream,
-                                _state: state,
-                                _tls: tls,
-                            };
-                            // TODO: Handle socket.
-                        }
+            tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                accepted = self.tcp_listener.accept() => {
+                    let (tcp_stream, remote_addr) = accepted?;
+                    info!("Got connection from {remote_addr}");
+
+                    connections.spawn(handle_connection(
+                        tcp_stream,
+                        remote_addr,
+                        Arc::clone(&encrypter),
+                        Arc::clone(&shared_state),
+                        self.shutdown.child_token(),
+                    ));
+                }
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+                now = flow_expiry.tick() => {
+                    shared_state.relay.expire_flows(&shared_state.registry, now);
+                }
+            }
+        }
+
+        info!(connections = connections.len(), "Shutting down");
+        drop(self.tcp_listener);
+
+        let drain = async { while connections.join_next().await.is_some() {} };
+        if timeout(self.shutdown_timeout, drain).await.is_err() {
+            warn!(
+                connections = connections.len(),
+                "Aborting connections after shutdown timeout"
+            );
+            connections.shutdown().await;
+        }
+
+        Ok(())
+    }
+}
+
+/// Performs the handshake, authenticates the client and serves it until the
+/// connection is lost or the server shuts down.
+///
+/// The handshake and the authentication each have to complete within the
+/// handshake timeout, during which the connection counts as a pending
+/// handshake of its source IP.
+async fn handle_connection(
+    mut tcp_stream: TcpStream,
+    remote_addr: SocketAddr,
+    encrypter: Arc<Aes128CbcSha256>,
+    state: Arc<SharedState>,
+    shutdown: CancellationToken,
+) {
+    let limits = &state.handshake_limits;
+    let Some(pending) = state
+        .pending_handshakes
+        .try_start(remote_addr.ip(), limits.max_pending_per_ip)
+    else {
+        info!("Too many handshakes in progress from {}", remote_addr.ip());
+        send_alert(&mut tcp_stream, &HandshakeAlert::TooManyHandshakes).await;
+        return;
+    };
+
+    let (mut r, mut w) = tcp_stream.split();
+    let handshake = tokio::select! {
+        handshake = do_handshake(&mut r, &mut w, limits) => handshake,
+        _ = shutdown.cancelled() => return,
+    };
+
+    match handshake {
+        Err(handshake_alert) => {
+            info!("Could not complete handshake: {handshake_alert:?}");
+            send_alert(&mut tcp_stream, &handshake_alert).await;
+        }
+        Ok((server_random, client_random)) => {
+            let tls = SymmTls::new((server_random, client_random), encrypter);
+
+            // Until the client is authenticated, its frames are held to the
+            // pre-authentication limit.
+            let connection = Connection::new(
+                Tunnel::from_tcp(tcp_stream, tls)
+                    .with_rekey_policy(state.rekey_policy)
+                    .with_max_frame_size(limits.max_pre_auth_frame_size),
+                Arc::clone(&state),
+            );
+
+            // Authentication counts towards the handshake, so a client
+            // stalling before it still holds its slot and is timed out.
+            let authenticated = tokio::select! {
+                authenticated = timeout(limits.timeout, connection.authenticate()) => authenticated,
+                _ = shutdown.cancelled() => return,
+            };
+            drop(pending);
+
+            match authenticated {
+                Ok(Ok(token)) => {
+                    info!(token.sub, token.name, "Client authenticated");
+                    if let Err(connection_error) = connection.serve(token, &shutdown).await {
+                        info!("Connection closed: {connection_error}");
                     }
                 }
-            });
-            info!("Lost connection {remote_addr}");
+                Ok(Err(connection_error)) => {
+                    info!("Could not authenticate: {connection_error}");
+                }
+                Err(_) => info!("Authentication timed out"),
+            }
+
+            // Close the tunnel cleanly, e.g. after a replayed record.
+            let _ = connection.tunnel.shutdown().await;
         }
     }
+
+    info!("Lost connection {remote_addr}");
 }
@@ -0,0 +1,88 @@
+//! Throughput of a [`SymmTls`] encrypter when records are encrypted from several threads
+//! at once, compared to the same cipher behind a single mutex.
+//!
+//! Encrypting a record takes the encrypter by shared reference and touches no
+//! lock or counter, so the shared runs only measure the cipher itself. In a
+//! tunnel the records are still encrypted one at a time by the writer, which
+//! numbers them and rekeys under the same lock.
+//!
+//! ```text
+//! cargo bench -p testutil --bench contention
+//! ```
+
+use bytes::BytesMut;
+use crypto::{
+    symm::Aes128CbcSha256,
+    tls::{SymmEncrypter, SymmTls},
+};
+use proto_core::tls_provider::{Encrypter, TlsProvider};
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+const RECORD_SIZE: usize = 1024;
+const RECORDS_PER_THREAD: usize = 20_000;
+
+/// Serializes every call, like the former mutex-guarded IV states.
+struct Locked(Mutex<SymmEncrypter>);
+
+fn encrypter() -> SymmEncrypter {
+    let tls = SymmTls::new(
+        ([1; 32], [2; 32]),
+        Arc::new(Aes128CbcSha256::try_new(&[3; 16]).unwrap()),
+    );
+
+    tls.split().0
+}
+
+/// Encrypts records from `threads` threads and returns the elapsed time.
+fn run(threads: usize, encrypt: &(dyn Fn(&mut BytesMut) + Sync)) -> Duration {
+    let start = Instant::now();
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                let mut buf = BytesMut::new();
+                for _ in 0..RECORDS_PER_THREAD {
+                    buf.clear();
+                    buf.resize(RECORD_SIZE, 0xab);
+                    encrypt(&mut buf);
+                }
+            });
+        }
+    });
+
+    start.elapsed()
+}
+
+fn report(name: &str, threads: usize, elapsed: Duration) {
+    let records = (threads * RECORDS_PER_THREAD) as f64;
+    let mib = records * RECORD_SIZE as f64 / (1024.0 * 1024.0);
+
+    println!(
+        "{name:>8} {threads:>2} threads: {:>10.0} records/s {:>8.1} MiB/s",
+        records / elapsed.as_secs_f64(),
+        mib / elapsed.as_secs_f64(),
+    );
+}
+
+fn main() {
+    let max_threads = thread::available_parallelism().map_or(4, usize::from);
+
+    for threads in [1, 2, 4, 8]
+        .into_iter()
+        .filter(|&t| t <= max_threads.max(2))
+    {
+        let tls = encrypter();
+        let elapsed = run(threads, &|buf| tls.encrypt(0, buf, 0).unwrap());
+        report("shared", threads, elapsed);
+
+        let locked = Locked(Mutex::new(encrypter()));
+        let elapsed = run(threads, &|buf| {
+            locked.0.lock().unwrap().encrypt(0, buf, 0).unwrap()
+        });
+        report("mutex", threads, elapsed);
+    }
+}
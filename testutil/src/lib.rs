@@ -1,6 +1,4 @@
-use std::u64;
-
-use proto_core::token::{Token, TokenScope, TokenTag};
+use proto_core::token::{TagRegex, Token, TokenScope, TokenTag};
 
 pub fn generate_token(id: u64, name: String, tags: Vec<String>) -> Token {
     Token {
@@ -13,7 +11,7 @@ pub fn generate_token(id: u64, name: String, tags: Vec<String>) -> Token {
         scope: vec![
             TokenScope::ForwardPort,
             TokenScope::RequestPort {
-                tags: vec![TokenTag::Regex(String::from("*"))],
+                tags: vec![TokenTag::Regex(TagRegex::new(".*").unwrap())],
                 ports: vec![0..=u16::MAX],
             },
         ],
@@ -0,0 +1,63 @@
+use client::{ClientBuilder, Error};
+use crypto::{
+    key::KeyMaterial,
+    sign::{EcdsaP256Sha256Signer, Ed25519Signer, TokenVerifier, sign_token},
+};
+use proto_core::sub_protocol::cmd_response::Authenticate;
+use server::ServerBuilder;
+use std::net::SocketAddr;
+use testutil::{DynResult, generate_token};
+
+async fn spawn_server(token_verifier: TokenVerifier) -> DynResult<SocketAddr> {
+    let server = ServerBuilder::new(
+        "127.0.0.1:0".parse()?,
+        KeyMaterial::from_bytes(vec![0; 16]),
+        token_verifier,
+    )
+    .try_build()
+    .await?;
+
+    let addr = server.local_addr()?;
+    tokio::spawn(server.serve());
+
+    Ok(addr)
+}
+
+#[tokio::test]
+async fn authenticate_with_public_key() -> DynResult<()> {
+    let signer = Ed25519Signer::generate()?;
+    let addr = spawn_server(TokenVerifier::from(signer.verifier()?)).await?;
+
+    let token = generate_token(1, String::from("test"), vec![]);
+    ClientBuilder::new(
+        addr,
+        KeyMaterial::from_bytes(vec![0; 16]),
+        sign_token(token, &signer)?,
+    )
+    .try_build()
+    .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn reject_foreign_signature() -> DynResult<()> {
+    let signer = EcdsaP256Sha256Signer::generate()?;
+    let addr = spawn_server(TokenVerifier::from(signer.verifier()?)).await?;
+
+    let token = generate_token(1, String::from("test"), vec![]);
+    let result = ClientBuilder::new(
+        addr,
+        KeyMaterial::from_bytes(vec![0; 16]),
+        sign_token(token, &EcdsaP256Sha256Signer::generate()?)?,
+    )
+    .try_build()
+    .await;
+
+    assert!(matches!(
+        result,
+        Err(Error::Authentication(Authenticate::InvalidToken))
+    ));
+
+    Ok(())
+}
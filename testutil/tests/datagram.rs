@@ -0,0 +1,189 @@
+use client::{Client, ClientBuilder, Error, FlowPolicy};
+use crypto::{
+    key::KeyMaterial,
+    sign::{Hs256, TokenVerifier, sign_token},
+};
+use server::ServerBuilder;
+use std::{net::SocketAddr, time::Duration};
+use testutil::{DynResult, generate_token};
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc::{UnboundedReceiver, unbounded_channel},
+    time::{sleep, timeout},
+};
+
+const KEY: [u8; 32] = [11; 32];
+
+async fn spawn_server() -> DynResult<SocketAddr> {
+    let server = ServerBuilder::new(
+        "127.0.0.1:0".parse()?,
+        KeyMaterial::from_bytes(vec![0; 16]),
+        TokenVerifier::from(Hs256::try_new(&KEY)?),
+    )
+    .try_build()
+    .await?;
+
+    let addr = server.local_addr()?;
+    tokio::spawn(server.serve());
+
+    Ok(addr)
+}
+
+async fn connect(
+    addr: SocketAddr,
+    id: u64,
+    shared_port: Option<u16>,
+    flow_policy: FlowPolicy,
+) -> Result<Client, Error> {
+    connect_tagged(
+        addr,
+        id,
+        shared_port,
+        flow_policy,
+        vec![String::from("test")],
+    )
+    .await
+}
+
+async fn connect_tagged(
+    addr: SocketAddr,
+    id: u64,
+    shared_port: Option<u16>,
+    flow_policy: FlowPolicy,
+    tags: Vec<String>,
+) -> Result<Client, Error> {
+    let token = generate_token(id, format!("client-{id}"), tags);
+
+    ClientBuilder {
+        flow_policy,
+        shared_ports: shared_port.into_iter().map(|port| port..=port).collect(),
+        ..ClientBuilder::new(
+            addr,
+            KeyMaterial::from_bytes(vec![0; 16]),
+            sign_token(token, &Hs256::try_new(&KEY)?)?,
+        )
+    }
+    .try_build()
+    .await
+}
+
+/// Spawns a UDP server echoing each datagram, and reporting the source of
+/// each datagram.
+async fn spawn_udp_echo() -> DynResult<(u16, UnboundedReceiver<SocketAddr>)> {
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    let port = socket.local_addr()?.port();
+    let (sources, receiver) = unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut buf = vec![0; 65536];
+        while let Ok((n, source)) = socket.recv_from(&mut buf).await {
+            let _ = sources.send(source);
+            socket.send_to(&buf[..n], source).await?;
+        }
+        Ok::<_, std::io::Error>(())
+    });
+
+    Ok((port, receiver))
+}
+
+async fn echo(socket: &UdpSocket, payload: &[u8]) -> DynResult<()> {
+    socket.send(payload).await?;
+
+    let mut buf = vec![0; 65536];
+    let n = timeout(Duration::from_secs(5), socket.recv(&mut buf)).await??;
+    assert_eq!(&buf[..n], payload);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn forward_udp() -> DynResult<()> {
+    let addr = spawn_server().await?;
+    let (port, mut sources) = spawn_udp_echo().await?;
+
+    let _sharer = connect(addr, 2, Some(port), FlowPolicy::default()).await?;
+    let requester = connect(addr, 1, None, FlowPolicy::default()).await?;
+    let forward = requester
+        .forward_udp("127.0.0.1:0".parse()?, 2, port)
+        .await?;
+
+    let first = UdpSocket::bind("127.0.0.1:0").await?;
+    first.connect(forward.local_addr()).await?;
+    let second = UdpSocket::bind("127.0.0.1:0").await?;
+    second.connect(forward.local_addr()).await?;
+
+    // Message boundaries are preserved, up to the largest UDP payload.
+    for payload in [&b"license"[..], &[0; 1], &[7; 60_000]] {
+        echo(&first, payload).await?;
+    }
+    echo(&second, b"checkout").await?;
+
+    // Each local source has its own flow, relayed from its own socket.
+    let first_source = sources.recv().await.unwrap();
+    for _ in 0..2 {
+        assert_eq!(sources.recv().await, Some(first_source));
+    }
+    assert_ne!(sources.recv().await, Some(first_source));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn idle_flow_expires() -> DynResult<()> {
+    let addr = spawn_server().await?;
+    let (port, mut sources) = spawn_udp_echo().await?;
+
+    let flow_policy = FlowPolicy {
+        idle_timeout: Duration::from_millis(200),
+    };
+    let _sharer = connect(addr, 2, Some(port), flow_policy).await?;
+    let requester = connect(addr, 1, None, flow_policy).await?;
+    let forward = requester
+        .forward_udp("127.0.0.1:0".parse()?, 2, port)
+        .await?;
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    socket.connect(forward.local_addr()).await?;
+
+    echo(&socket, b"first").await?;
+    let first_source = sources.recv().await.unwrap();
+
+    // Traffic keeps the flow alive.
+    for _ in 0..3 {
+        sleep(Duration::from_millis(100)).await;
+        echo(&socket, b"keepalive").await?;
+        assert_eq!(sources.recv().await, Some(first_source));
+    }
+
+    // Once expired, the next datagram opens a new flow.
+    sleep(Duration::from_millis(500)).await;
+    echo(&socket, b"second").await?;
+    assert_ne!(sources.recv().await, Some(first_source));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn flow_needs_permission() -> DynResult<()> {
+    let addr = spawn_server().await?;
+    let (port, mut sources) = spawn_udp_echo().await?;
+
+    // Requesting ports needs a tag matching the scope of the requester.
+    let _untagged = connect_tagged(addr, 2, Some(port), FlowPolicy::default(), vec![]).await?;
+    let requester = connect(addr, 1, None, FlowPolicy::default()).await?;
+    let forward = requester
+        .forward_udp("127.0.0.1:0".parse()?, 2, port)
+        .await?;
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    socket.connect(forward.local_addr()).await?;
+    socket.send(b"denied").await?;
+
+    assert!(
+        timeout(Duration::from_millis(300), sources.recv())
+            .await
+            .is_err()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,288 @@
+use client::{Client, ClientBuilder, Error};
+use crypto::{
+    key::KeyMaterial,
+    sign::{Hs256, TokenVerifier, sign_token},
+};
+use server::ServerBuilder;
+use std::{net::SocketAddr, ops::RangeInclusive, time::Duration};
+use testutil::{DynResult, generate_token};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+const KEY: [u8; 32] = [7; 32];
+
+async fn spawn_server() -> DynResult<SocketAddr> {
+    let server = ServerBuilder::new(
+        "127.0.0.1:0".parse()?,
+        KeyMaterial::from_bytes(vec![0; 16]),
+        TokenVerifier::from(Hs256::try_new(&KEY)?),
+    )
+    .try_build()
+    .await?;
+
+    let addr = server.local_addr()?;
+    tokio::spawn(server.serve());
+
+    Ok(addr)
+}
+
+async fn connect(
+    addr: SocketAddr,
+    id: u64,
+    shared_ports: Vec<RangeInclusive<u16>>,
+) -> Result<Client, Error> {
+    let token = generate_token(id, format!("client-{id}"), vec![String::from("test")]);
+
+    ClientBuilder {
+        shared_ports,
+        ..ClientBuilder::new(
+            addr,
+            KeyMaterial::from_bytes(vec![0; 16]),
+            sign_token(token, &Hs256::try_new(&KEY)?)?,
+        )
+    }
+    .try_build()
+    .await
+}
+
+/// Spawns a TCP server echoing everything it receives.
+async fn spawn_echo() -> DynResult<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    serve_echo(listener);
+
+    Ok(port)
+}
+
+/// Spawns echo servers on two consecutive ports and returns their range.
+async fn spawn_echo_range() -> DynResult<RangeInclusive<u16>> {
+    loop {
+        let first = TcpListener::bind("127.0.0.1:0").await?;
+        let port = first.local_addr()?.port();
+        let Some(next_port) = port.checked_add(1) else {
+            continue;
+        };
+
+        if let Ok(second) = TcpListener::bind(("127.0.0.1", next_port)).await {
+            serve_echo(first);
+            serve_echo(second);
+            return Ok(port..=next_port);
+        }
+    }
+}
+
+fn serve_echo(listener: TcpListener) {
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut r, mut w) = socket.split();
+                let _ = tokio::io::copy(&mut r, &mut w).await;
+            });
+        }
+    });
+}
+
+/// Spawns a TCP server that accepts connections but never reads from them.
+async fn spawn_sink() -> DynResult<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+
+    tokio::spawn(async move {
+        let mut sockets = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            sockets.push(socket);
+        }
+    });
+
+    Ok(port)
+}
+
+async fn echo_round_trip(addr: SocketAddr, payload: &[u8]) -> DynResult<()> {
+    let mut socket = TcpStream::connect(addr).await?;
+    socket.write_all(payload).await?;
+
+    let mut received = vec![0; payload.len()];
+    socket.read_exact(&mut received).await?;
+    assert_eq!(received, payload);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn forward_echo() -> DynResult<()> {
+    let addr = spawn_server().await?;
+    let echo_port = spawn_echo().await?;
+
+    let _sharer = connect(addr, 2, vec![echo_port..=echo_port]).await?;
+    let requester = connect(addr, 1, vec![]).await?;
+
+    let forward = requester
+        .forward("127.0.0.1:0".parse()?, 2, echo_port)
+        .await?;
+
+    // Larger than the initial window, so window updates are required.
+    let payload: Vec<u8> = (0..1024 * 1024).map(|i| i as u8).collect();
+    timeout(
+        Duration::from_secs(10),
+        echo_round_trip(forward.local_addr(), &payload),
+    )
+    .await??;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn forward_port_range() -> DynResult<()> {
+    let addr = spawn_server().await?;
+    let ports = spawn_echo_range().await?;
+
+    let _sharer = connect(addr, 2, vec![ports.clone()]).await?;
+    let requester = connect(addr, 1, vec![]).await?;
+
+    // The shared ports are bound by the echo servers on 127.0.0.1, so the
+    // matching local ports are bound on another loopback address.
+    let local_ip = "127.0.0.2".parse()?;
+    let forwards = requester
+        .forward_ports(local_ip, 2, std::slice::from_ref(&ports))
+        .await?;
+    assert_eq!(forwards.len(), 2);
+
+    for (forward, port) in forwards.iter().zip(ports.clone()) {
+        assert_eq!(forward.local_addr(), SocketAddr::new(local_ip, port));
+        timeout(
+            Duration::from_secs(5),
+            echo_round_trip(forward.local_addr(), &port.to_be_bytes()),
+        )
+        .await??;
+    }
+
+    // A port outside the shared range is refused by the sharing client.
+    let outside = *ports.start() - 1;
+    let forward = requester
+        .forward("127.0.0.1:0".parse()?, 2, outside)
+        .await?;
+    let mut socket = TcpStream::connect(forward.local_addr()).await?;
+    let error = timeout(Duration::from_secs(5), socket.read(&mut [0; 1]))
+        .await?
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::ConnectionReset);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn forward_half_close() -> DynResult<()> {
+    let addr = spawn_server().await?;
+
+    // Reads the whole upload, like `cat > file`, then answers with its size.
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await?;
+        let mut upload = Vec::new();
+        socket.read_to_end(&mut upload).await?;
+        socket.write_all(&upload.len().to_be_bytes()).await?;
+        Ok::<_, std::io::Error>(())
+    });
+
+    let _sharer = connect(addr, 2, vec![port..=port]).await?;
+    let requester = connect(addr, 1, vec![]).await?;
+
+    let forward = requester.forward("127.0.0.1:0".parse()?, 2, port).await?;
+
+    let payload = vec![7; 300 * 1024];
+    let mut socket = TcpStream::connect(forward.local_addr()).await?;
+    timeout(Duration::from_secs(10), socket.write_all(&payload)).await??;
+    socket.shutdown().await?;
+
+    let mut response = Vec::new();
+    timeout(Duration::from_secs(5), socket.read_to_end(&mut response)).await??;
+    assert_eq!(response, payload.len().to_be_bytes());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn refuse_unshared_port() -> DynResult<()> {
+    let addr = spawn_server().await?;
+    let echo_port = spawn_echo().await?;
+
+    let _sharer = connect(addr, 2, vec![]).await?;
+    let requester = connect(addr, 1, vec![]).await?;
+
+    let forward = requester
+        .forward("127.0.0.1:0".parse()?, 2, echo_port)
+        .await?;
+
+    // The refusal resets the local socket.
+    let mut socket = TcpStream::connect(forward.local_addr()).await?;
+    let mut buf = [0; 1];
+    let error = timeout(Duration::from_secs(5), socket.read(&mut buf))
+        .await?
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::ConnectionReset);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn stalled_stream_does_not_block_others() -> DynResult<()> {
+    let addr = spawn_server().await?;
+    let echo_port = spawn_echo().await?;
+    let sink_port = spawn_sink().await?;
+
+    let _sharer = connect(addr, 2, vec![echo_port..=echo_port, sink_port..=sink_port]).await?;
+    let requester = connect(addr, 1, vec![]).await?;
+
+    let sink = requester
+        .forward("127.0.0.1:0".parse()?, 2, sink_port)
+        .await?;
+    let echo = requester
+        .forward("127.0.0.1:0".parse()?, 2, echo_port)
+        .await?;
+
+    let mut sink_socket = TcpStream::connect(sink.local_addr()).await?;
+    let flood = tokio::spawn(async move {
+        let chunk = vec![0; 64 * 1024];
+        loop {
+            if sink_socket.write_all(&chunk).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Give the sink stream time to exhaust its window.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    for _ in 0..8 {
+        timeout(
+            Duration::from_secs(5),
+            echo_round_trip(echo.local_addr(), b"hello"),
+        )
+        .await??;
+    }
+
+    assert!(!flood.is_finished());
+    flood.abort();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn reject_duplicate_client() -> DynResult<()> {
+    let addr = spawn_server().await?;
+
+    let _client = connect(addr, 1, vec![]).await?;
+
+    assert!(matches!(
+        connect(addr, 1, vec![]).await,
+        Err(Error::Authentication(
+            proto_core::sub_protocol::cmd_response::Authenticate::AlreadyConnected
+        ))
+    ));
+
+    Ok(())
+}
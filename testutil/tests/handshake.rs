@@ -1,15 +1,234 @@
-use tokio::io::simplex;
+use client::{Client, ClientBuilder, Error};
+use crypto::{
+    key::KeyMaterial,
+    sign::{Hs256, TokenVerifier, sign_token},
+};
+use proto_core::sub_protocol::handshake::{
+    HandshakeAlert, HandshakeContentType, HandshakeLimits, PayloadLimits, read_handshake_payload,
+};
+use server::ServerBuilder;
+use std::{net::SocketAddr, time::Duration};
+use testutil::{DynResult, generate_token};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, simplex},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+const KEY: [u8; 32] = [4; 32];
+
+async fn spawn_server(handshake_limits: HandshakeLimits) -> DynResult<SocketAddr> {
+    let server = ServerBuilder {
+        handshake_limits,
+        ..ServerBuilder::new(
+            "127.0.0.1:0".parse()?,
+            KeyMaterial::from_bytes(vec![0; 16]),
+            TokenVerifier::from(Hs256::try_new(&KEY)?),
+        )
+    }
+    .try_build()
+    .await?;
+
+    let addr = server.local_addr()?;
+    tokio::spawn(server.serve());
+
+    Ok(addr)
+}
+
+async fn connect(addr: SocketAddr, handshake_limits: HandshakeLimits) -> Result<Client, Error> {
+    let token = generate_token(1, String::from("test"), vec![]);
+
+    ClientBuilder {
+        handshake_limits,
+        ..ClientBuilder::new(
+            addr,
+            KeyMaterial::from_bytes(vec![0; 16]),
+            sign_token(token, &Hs256::try_new(&KEY)?)?,
+        )
+    }
+    .try_build()
+    .await
+}
+
+/// Reads the alert sent by the server, then expects the connection to be
+/// closed.
+async fn recv_alert(stream: &mut TcpStream) -> DynResult<HandshakeAlert> {
+    let (content_type, payload) = read_handshake_payload(stream, &PayloadLimits::default())
+        .await
+        .map_err(|alert| format!("{alert:?}"))?;
+    assert_eq!(content_type, HandshakeContentType::HandshakeAlert);
+
+    let (alert, _) = bincode::serde::decode_from_slice(&payload, bincode::config::standard())?;
+    assert_eq!(stream.read(&mut [0; 1]).await?, 0);
+
+    Ok(alert)
+}
 
 #[tokio::test]
 async fn handshake() {
     let (mut sr, mut cw) = simplex(u16::MAX as usize);
     let (mut cr, mut sw) = simplex(u16::MAX as usize);
 
+    let limits = HandshakeLimits::default();
     let (client_handshake, server_handshake) = tokio::join!(
-        client::connection::do_handshake(&mut cr, &mut cw),
-        server::connection::do_handshake(&mut sr, &mut sw),
+        client::connection::do_handshake(&mut cr, &mut cw, &limits),
+        server::connection::do_handshake(&mut sr, &mut sw, &limits),
     );
 
     client_handshake.unwrap();
     server_handshake.unwrap();
 }
+
+#[tokio::test]
+async fn handshake_timeout() -> DynResult<()> {
+    let addr = spawn_server(HandshakeLimits {
+        timeout: Duration::from_millis(100),
+        ..HandshakeLimits::default()
+    })
+    .await?;
+
+    // A peer sending part of a header and then stalling.
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(&[0]).await?;
+
+    assert!(matches!(
+        recv_alert(&mut stream).await?,
+        HandshakeAlert::Timeout
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn oversized_handshake() -> DynResult<()> {
+    let addr = spawn_server(HandshakeLimits::default()).await?;
+
+    let mut stream = TcpStream::connect(addr).await?;
+    stream
+        .write_all(&[0xff, 0xff, HandshakeContentType::ClientHello as u8])
+        .await?;
+
+    assert!(matches!(
+        recv_alert(&mut stream).await?,
+        HandshakeAlert::PayloadTooLarge {
+            content_type: HandshakeContentType::ClientHello,
+            ..
+        }
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn pending_handshakes_per_ip() -> DynResult<()> {
+    let addr = spawn_server(HandshakeLimits {
+        max_pending_per_ip: 2,
+        ..HandshakeLimits::default()
+    })
+    .await?;
+
+    let mut pending = vec![
+        TcpStream::connect(addr).await?,
+        TcpStream::connect(addr).await?,
+    ];
+
+    let mut stream = TcpStream::connect(addr).await?;
+    assert!(matches!(
+        recv_alert(&mut stream).await?,
+        HandshakeAlert::TooManyHandshakes
+    ));
+
+    // Closing a pending handshake frees its slot once the server notices.
+    pending.pop();
+    let mut client = None;
+    for _ in 0..50 {
+        match connect(addr, HandshakeLimits::default()).await {
+            Err(Error::Handshake(HandshakeAlert::TooManyHandshakes)) => {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            connected => {
+                client = Some(connected?);
+                break;
+            }
+        }
+    }
+    assert!(client.is_some());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn authentication_timeout() -> DynResult<()> {
+    let limits = HandshakeLimits {
+        timeout: Duration::from_millis(200),
+        max_pending_per_ip: 1,
+        ..HandshakeLimits::default()
+    };
+    let addr = spawn_server(limits).await?;
+
+    // A peer completing the handshake and then stalling.
+    let mut stalled = TcpStream::connect(addr).await?;
+    let (mut r, mut w) = stalled.split();
+    client::connection::do_handshake(&mut r, &mut w, &limits).await?;
+
+    // The unauthenticated connection still holds the handshake slot.
+    let mut stream = TcpStream::connect(addr).await?;
+    assert!(matches!(
+        recv_alert(&mut stream).await?,
+        HandshakeAlert::TooManyHandshakes
+    ));
+
+    let closed = timeout(Duration::from_secs(2), stalled.read(&mut [0; 1])).await?;
+    assert!(matches!(closed, Ok(0) | Err(_)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn oversized_frame_before_authentication() -> DynResult<()> {
+    let limits = HandshakeLimits::default();
+    let addr = spawn_server(limits).await?;
+
+    let mut stream = TcpStream::connect(addr).await?;
+    let (mut r, mut w) = stream.split();
+    client::connection::do_handshake(&mut r, &mut w, &limits).await?;
+
+    // The header of a frame above the limit is enough to close the
+    // connection, well before the authentication times out.
+    let len = limits.max_pre_auth_frame_size as u32 + 1;
+    stream.write_all(&len.to_be_bytes()).await?;
+    stream.write_all(&0u64.to_be_bytes()).await?;
+
+    let closed = timeout(Duration::from_secs(2), stream.read(&mut [0; 1])).await?;
+    assert!(matches!(closed, Ok(0) | Err(_)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn client_handshake_timeout() -> DynResult<()> {
+    // A server accepting connections without ever answering.
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        let mut streams = vec![];
+        while let Ok((stream, _)) = listener.accept().await {
+            streams.push(stream);
+        }
+    });
+
+    let connected = connect(
+        addr,
+        HandshakeLimits {
+            timeout: Duration::from_millis(100),
+            ..HandshakeLimits::default()
+        },
+    )
+    .await;
+    assert!(matches!(
+        connected,
+        Err(Error::Handshake(HandshakeAlert::Timeout))
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use client::{Client, ClientBuilder, Error};
+use crypto::{
+    key::KeyMaterial,
+    sign::{Hs256, TokenVerifier, sign_token},
+};
+use proto_core::common::KeepalivePolicy;
+use server::ServerBuilder;
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+use testutil::{DynResult, generate_token};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::oneshot,
+    time::{sleep, timeout},
+};
+
+const KEY: [u8; 32] = [5; 32];
+
+const POLICY: KeepalivePolicy = KeepalivePolicy {
+    interval: Some(Duration::from_millis(50)),
+    max_missed_pongs: 3,
+};
+
+async fn spawn_server(keepalive_policy: KeepalivePolicy) -> DynResult<SocketAddr> {
+    let server = ServerBuilder {
+        keepalive_policy,
+        ..ServerBuilder::new(
+            "127.0.0.1:0".parse()?,
+            KeyMaterial::from_bytes(vec![0; 16]),
+            TokenVerifier::from(Hs256::try_new(&KEY)?),
+        )
+    }
+    .try_build()
+    .await?;
+
+    let addr = server.local_addr()?;
+    tokio::spawn(server.serve());
+
+    Ok(addr)
+}
+
+async fn connect(addr: SocketAddr, keepalive_policy: KeepalivePolicy) -> Result<Client, Error> {
+    let token = generate_token(1, String::from("test"), vec![]);
+
+    ClientBuilder {
+        keepalive_policy,
+        ..ClientBuilder::new(
+            addr,
+            KeyMaterial::from_bytes(vec![0; 16]),
+            sign_token(token, &Hs256::try_new(&KEY)?)?,
+        )
+    }
+    .try_build()
+    .await
+}
+
+/// Copies `r` into `w` until EOF, silently discarding the bytes once
+/// `blackhole` is set.
+async fn pump<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    mut r: R,
+    w: &mut W,
+    blackhole: &AtomicBool,
+) {
+    let mut buf = [0; 4096];
+    while let Ok(n @ 1..) = r.read(&mut buf).await {
+        if !blackhole.load(Ordering::Relaxed) && w.write_all(&buf[..n]).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Proxy to `server` for a single connection, emulating a NAT that silently
+/// drops its state once the returned flag is set. The receiver completes when
+/// the server closes its side, which is never forwarded to the client.
+async fn spawn_proxy(
+    server: SocketAddr,
+) -> DynResult<(SocketAddr, Arc<AtomicBool>, oneshot::Receiver<()>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let blackhole = Arc::new(AtomicBool::new(false));
+    let (closed, server_closed) = oneshot::channel();
+
+    tokio::spawn({
+        let blackhole = Arc::clone(&blackhole);
+        async move {
+            let (client, _) = listener.accept().await?;
+            let (client_r, mut client_w) = client.into_split();
+            let (server_r, mut server_w) = TcpStream::connect(server).await?.into_split();
+
+            tokio::spawn({
+                let blackhole = Arc::clone(&blackhole);
+                async move { pump(client_r, &mut server_w, &blackhole).await }
+            });
+            pump(server_r, &mut client_w, &blackhole).await;
+            let _ = closed.send(());
+
+            // Keep the client side half-open.
+            std::future::pending::<()>().await;
+            Ok::<_, std::io::Error>(())
+        }
+    });
+
+    Ok((addr, blackhole, server_closed))
+}
+
+#[tokio::test]
+async fn rtt() -> DynResult<()> {
+    let addr = spawn_server(KeepalivePolicy::DISABLED).await?;
+    let client = connect(addr, POLICY).await?;
+
+    timeout(Duration::from_secs(5), async {
+        while client.status().keepalive.rtt.is_none() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await?;
+
+    let status = client.status();
+    assert_eq!(status.keepalive.missed_pongs, 0);
+    assert!(!status.closed);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn dead_peer() -> DynResult<()> {
+    let addr = spawn_server(POLICY).await?;
+    let (proxy, blackhole, server_closed) = spawn_proxy(addr).await?;
+    let client = connect(proxy, POLICY).await?;
+
+    // Pings are answered while the path is up.
+    sleep(Duration::from_millis(200)).await;
+    assert!(!client.is_closed());
+
+    blackhole.store(true, Ordering::Relaxed);
+
+    // Both peers notice the missing pongs and close the connection.
+    timeout(Duration::from_secs(5), async {
+        while !client.is_closed() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await?;
+    assert!(client.status().keepalive.missed_pongs >= POLICY.max_missed_pongs);
+    timeout(Duration::from_secs(5), server_closed).await??;
+
+    Ok(())
+}
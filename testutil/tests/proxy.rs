@@ -0,0 +1,221 @@
+use client::{Client, ClientBuilder, Error};
+use crypto::{
+    key::KeyMaterial,
+    sign::{Hs256, TokenVerifier, sign_token},
+};
+use server::ServerBuilder;
+use std::{net::SocketAddr, time::Duration};
+use testutil::{DynResult, generate_token};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+const KEY: [u8; 32] = [9; 32];
+
+async fn spawn_server() -> DynResult<SocketAddr> {
+    let server = ServerBuilder::new(
+        "127.0.0.1:0".parse()?,
+        KeyMaterial::from_bytes(vec![0; 16]),
+        TokenVerifier::from(Hs256::try_new(&KEY)?),
+    )
+    .try_build()
+    .await?;
+
+    let addr = server.local_addr()?;
+    tokio::spawn(server.serve());
+
+    Ok(addr)
+}
+
+async fn connect(addr: SocketAddr, id: u64, shared_ports: Vec<u16>) -> Result<Client, Error> {
+    connect_tagged(addr, id, shared_ports, vec![String::from("test")]).await
+}
+
+async fn connect_tagged(
+    addr: SocketAddr,
+    id: u64,
+    shared_ports: Vec<u16>,
+    tags: Vec<String>,
+) -> Result<Client, Error> {
+    let token = generate_token(id, format!("client-{id}"), tags);
+
+    ClientBuilder {
+        shared_ports: shared_ports.into_iter().map(|port| port..=port).collect(),
+        ..ClientBuilder::new(
+            addr,
+            KeyMaterial::from_bytes(vec![0; 16]),
+            sign_token(token, &Hs256::try_new(&KEY)?)?,
+        )
+    }
+    .try_build()
+    .await
+}
+
+async fn spawn_echo() -> DynResult<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut r, mut w) = socket.split();
+                let _ = tokio::io::copy(&mut r, &mut w).await;
+            });
+        }
+    });
+
+    Ok(port)
+}
+
+async fn echo(socket: &mut TcpStream, payload: &[u8]) -> DynResult<()> {
+    socket.write_all(payload).await?;
+
+    let mut received = vec![0; payload.len()];
+    socket.read_exact(&mut received).await?;
+    assert_eq!(received, payload);
+
+    Ok(())
+}
+
+/// Sends a SOCKS5 CONNECT to `host:port` and returns the reply code.
+async fn socks_connect(proxy: SocketAddr, host: &str, port: u16) -> DynResult<(TcpStream, u8)> {
+    let mut socket = TcpStream::connect(proxy).await?;
+
+    socket.write_all(&[5, 1, 0]).await?;
+    let mut method = [0; 2];
+    socket.read_exact(&mut method).await?;
+    assert_eq!(method, [5, 0]);
+
+    let mut request = vec![5, 1, 0, 3, host.len() as u8];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    socket.write_all(&request).await?;
+
+    let mut reply = [0; 10];
+    socket.read_exact(&mut reply).await?;
+    assert_eq!(reply[0], 5);
+
+    Ok((socket, reply[1]))
+}
+
+#[tokio::test]
+async fn socks5() -> DynResult<()> {
+    let addr = spawn_server().await?;
+    let echo_port = spawn_echo().await?;
+
+    let _sharer = connect(addr, 2, vec![echo_port]).await?;
+    let requester = connect(addr, 1, vec![]).await?;
+    let proxy = requester.socks5("127.0.0.1:0".parse()?).await?;
+
+    // The first stream proves the peer list announced by the server, which
+    // precedes it, has been received.
+    for host in ["2.dehset", "client-2.dehset"] {
+        let (mut socket, reply) = socks_connect(proxy.local_addr(), host, echo_port).await?;
+        assert_eq!(reply, 0, "{host}");
+        timeout(Duration::from_secs(5), echo(&mut socket, host.as_bytes())).await??;
+    }
+
+    // Host unreachable.
+    let (_, reply) = socks_connect(proxy.local_addr(), "unknown.dehset", echo_port).await?;
+    assert_eq!(reply, 4);
+
+    // Connection refused by the sharing client.
+    let (_, reply) = socks_connect(proxy.local_addr(), "client-2.dehset", echo_port + 1).await?;
+    assert_eq!(reply, 5);
+
+    Ok(())
+}
+
+/// Sends an HTTP request head and returns the status line of the response.
+async fn http_request(proxy: SocketAddr, head: &str) -> DynResult<(TcpStream, String)> {
+    let mut socket = TcpStream::connect(proxy).await?;
+    socket.write_all(head.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        response.push(socket.read_u8().await?);
+    }
+    let response = String::from_utf8(response)?;
+    let status_line = response.lines().next().unwrap_or_default().to_owned();
+
+    Ok((socket, status_line))
+}
+
+#[tokio::test]
+async fn http_connect() -> DynResult<()> {
+    let addr = spawn_server().await?;
+    let echo_port = spawn_echo().await?;
+
+    let _sharer = connect(addr, 2, vec![echo_port]).await?;
+    // Requesting ports needs a tag matching the scope of the requester.
+    let _untagged = connect_tagged(addr, 3, vec![echo_port], vec![]).await?;
+    let requester = connect(addr, 1, vec![]).await?;
+    let proxy = requester.http_proxy("127.0.0.1:0".parse()?).await?;
+
+    for host in ["2", "client-2"] {
+        let head = format!("CONNECT {host}:{echo_port} HTTP/1.1\r\nHost: {host}\r\n\r\n");
+        let (mut socket, status_line) = http_request(proxy.local_addr(), &head).await?;
+        assert_eq!(status_line, "HTTP/1.1 200 Connection Established");
+        timeout(Duration::from_secs(5), echo(&mut socket, host.as_bytes())).await??;
+    }
+
+    for (head, expected) in [
+        (
+            format!("CONNECT client-3:{echo_port} HTTP/1.1\r\n\r\n"),
+            "HTTP/1.1 403 Forbidden",
+        ),
+        (
+            format!("CONNECT unknown:{echo_port} HTTP/1.1\r\n\r\n"),
+            "HTTP/1.1 502 Bad Gateway",
+        ),
+        (
+            format!("CONNECT client-2:{} HTTP/1.1\r\n\r\n", echo_port + 1),
+            "HTTP/1.1 502 Bad Gateway",
+        ),
+        (
+            String::from("GET http://client-2/ HTTP/1.1\r\n\r\n"),
+            "HTTP/1.1 405 Method Not Allowed",
+        ),
+    ] {
+        let (_, status_line) = http_request(proxy.local_addr(), &head).await?;
+        assert_eq!(status_line, expected, "{head}");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn socks5_unsupported_requests() -> DynResult<()> {
+    let addr = spawn_server().await?;
+    let requester = connect(addr, 1, vec![]).await?;
+    let proxy = requester.socks5("127.0.0.1:0".parse()?).await?;
+
+    // Username/password authentication only.
+    let mut socket = TcpStream::connect(proxy.local_addr()).await?;
+    socket.write_all(&[5, 1, 2]).await?;
+    let mut method = [0; 2];
+    socket.read_exact(&mut method).await?;
+    assert_eq!(method, [5, 0xff]);
+
+    // IPv4 address.
+    let mut socket = TcpStream::connect(proxy.local_addr()).await?;
+    socket
+        .write_all(&[5, 1, 0, 5, 1, 0, 1, 127, 0, 0, 1, 0, 80])
+        .await?;
+    let mut reply = [0; 12];
+    socket.read_exact(&mut reply).await?;
+    assert_eq!(reply[..4], [5, 0, 5, 8]);
+
+    // BIND command.
+    let mut socket = TcpStream::connect(proxy.local_addr()).await?;
+    socket
+        .write_all(&[5, 1, 0, 5, 2, 0, 3, 1, b'2', 0, 80])
+        .await?;
+    let mut reply = [0; 12];
+    socket.read_exact(&mut reply).await?;
+    assert_eq!(reply[..4], [5, 0, 5, 7]);
+
+    Ok(())
+}
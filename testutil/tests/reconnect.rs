@@ -0,0 +1,199 @@
+use client::{Client, ClientBuilder, ConnectionEvent, Error, ReconnectPolicy};
+use crypto::{
+    key::KeyMaterial,
+    sign::{Hs256, TokenVerifier, sign_token},
+};
+use server::ServerBuilder;
+use std::{net::SocketAddr, time::Duration};
+use testutil::{DynResult, generate_token};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+    task::JoinHandle,
+    time::timeout,
+};
+use tokio_util::sync::CancellationToken;
+
+const KEY: [u8; 32] = [6; 32];
+
+const POLICY: ReconnectPolicy = ReconnectPolicy {
+    max_attempts: None,
+    initial_backoff: Duration::from_millis(20),
+    max_backoff: Duration::from_millis(100),
+};
+
+type ServeHandle = JoinHandle<Result<(), server::Error>>;
+
+async fn spawn_server(addr: SocketAddr) -> DynResult<(SocketAddr, CancellationToken, ServeHandle)> {
+    let server = ServerBuilder::new(
+        addr,
+        KeyMaterial::from_bytes(vec![0; 16]),
+        TokenVerifier::from(Hs256::try_new(&KEY)?),
+    )
+    .try_build()
+    .await?
+    .with_shutdown_timeout(Duration::from_secs(2));
+
+    let addr = server.local_addr()?;
+    let shutdown = server.shutdown_token();
+
+    Ok((addr, shutdown, tokio::spawn(server.serve())))
+}
+
+async fn connect(
+    addr: SocketAddr,
+    id: u64,
+    shared_ports: Vec<u16>,
+    reconnect_policy: ReconnectPolicy,
+) -> Result<Client, Error> {
+    let token = generate_token(id, format!("client-{id}"), vec![String::from("test")]);
+
+    ClientBuilder {
+        reconnect_policy,
+        shared_ports: shared_ports.into_iter().map(|port| port..=port).collect(),
+        ..ClientBuilder::new(
+            addr,
+            KeyMaterial::from_bytes(vec![0; 16]),
+            sign_token(token, &Hs256::try_new(&KEY)?)?,
+        )
+    }
+    .try_build()
+    .await
+}
+
+async fn spawn_echo() -> DynResult<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut r, mut w) = socket.split();
+                let _ = tokio::io::copy(&mut r, &mut w).await;
+            });
+        }
+    });
+
+    Ok(port)
+}
+
+async fn echo(socket: &mut TcpStream, payload: &[u8]) -> DynResult<()> {
+    socket.write_all(payload).await?;
+
+    let mut received = vec![0; payload.len()];
+    socket.read_exact(&mut received).await?;
+    assert_eq!(received, payload);
+
+    Ok(())
+}
+
+/// Waits for `expected`, skipping the other events.
+async fn wait_for(
+    events: &mut broadcast::Receiver<ConnectionEvent>,
+    expected: fn(&ConnectionEvent) -> bool,
+) -> DynResult<ConnectionEvent> {
+    let event = timeout(Duration::from_secs(10), async {
+        loop {
+            let event = events.recv().await?;
+            if expected(&event) {
+                return Ok::<_, broadcast::error::RecvError>(event);
+            }
+        }
+    })
+    .await??;
+
+    Ok(event)
+}
+
+#[tokio::test]
+async fn reconnect_after_server_restart() -> DynResult<()> {
+    let (addr, shutdown, serve) = spawn_server("127.0.0.1:0".parse()?).await?;
+    let echo_port = spawn_echo().await?;
+
+    let sharer = connect(addr, 2, vec![echo_port], POLICY).await?;
+    let requester = connect(addr, 1, vec![], POLICY).await?;
+    let mut sharer_events = sharer.events();
+    let mut requester_events = requester.events();
+
+    let forward = requester
+        .forward("127.0.0.1:0".parse()?, 2, echo_port)
+        .await?;
+    let mut socket = TcpStream::connect(forward.local_addr()).await?;
+    timeout(Duration::from_secs(5), echo(&mut socket, b"before")).await??;
+
+    shutdown.cancel();
+    serve.await??;
+    for events in [&mut sharer_events, &mut requester_events] {
+        wait_for(events, |event| {
+            matches!(event, ConnectionEvent::Disconnected { .. })
+        })
+        .await?;
+    }
+    assert!(!requester.status().connected);
+    assert!(!requester.is_closed());
+
+    // The stream of the lost connection is terminated, but the listener stays
+    // bound, so new connections stall instead of being refused.
+    assert!(matches!(
+        timeout(Duration::from_secs(5), socket.read(&mut [0; 1])).await?,
+        Ok(0) | Err(_)
+    ));
+    let mut stalled = TcpStream::connect(forward.local_addr()).await?;
+    assert!(
+        timeout(Duration::from_millis(100), stalled.read(&mut [0; 1]))
+            .await
+            .is_err()
+    );
+
+    let (_, _shutdown, _serve) = spawn_server(addr).await?;
+    for events in [&mut sharer_events, &mut requester_events] {
+        wait_for(events, |event| *event == ConnectionEvent::Reconnected).await?;
+    }
+    assert!(requester.status().connected);
+
+    let mut socket = TcpStream::connect(forward.local_addr()).await?;
+    timeout(Duration::from_secs(5), echo(&mut socket, b"after")).await??;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn give_up_after_max_attempts() -> DynResult<()> {
+    let (addr, shutdown, serve) = spawn_server("127.0.0.1:0".parse()?).await?;
+
+    let client = connect(
+        addr,
+        1,
+        vec![],
+        ReconnectPolicy {
+            max_attempts: Some(2),
+            ..POLICY
+        },
+    )
+    .await?;
+    let mut events = client.events();
+
+    shutdown.cancel();
+    serve.await??;
+
+    let mut attempts = 0;
+    loop {
+        match wait_for(&mut events, |_| true).await? {
+            ConnectionEvent::Reconnecting { attempt, .. } => attempts = attempt,
+            ConnectionEvent::Closed => break,
+            ConnectionEvent::Disconnected { .. } => {}
+            ConnectionEvent::Reconnected => panic!("Reconnected without a server"),
+        }
+    }
+    assert_eq!(attempts, 2);
+
+    timeout(Duration::from_secs(5), async {
+        while !client.is_closed() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await?;
+
+    Ok(())
+}
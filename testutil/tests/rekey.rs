@@ -0,0 +1,48 @@
+use crypto::{symm::Aes128CbcSha256, tls::SymmTls};
+use proto_core::{
+    random_bytes,
+    tunnel::{RekeyPolicy, Tunnel},
+};
+use std::{sync::Arc, time::Duration};
+use testutil::DynResult;
+use tokio::io::simplex;
+
+fn symm_tls(iv: [u8; 32], key: &[u8]) -> DynResult<SymmTls> {
+    Ok(SymmTls::new(
+        (iv, iv),
+        Arc::new(Aes128CbcSha256::try_new(key)?),
+    ))
+}
+
+#[tokio::test]
+async fn rekey_across_records() -> DynResult<()> {
+    let iv = random_bytes!(32);
+    let key = random_bytes!(16);
+
+    let (r, w) = simplex(usize::MAX);
+    let (_, sink) = simplex(1);
+    let (source, _) = simplex(1);
+
+    let sender = Tunnel::new(source, w, symm_tls(iv, &key)?).with_rekey_policy(RekeyPolicy {
+        max_records: Some(4),
+        max_bytes: Some(1024),
+        max_age: Some(Duration::from_secs(60)),
+    });
+    let receiver = Tunnel::new(r, sink, symm_tls(iv, &key)?);
+
+    let mut payloads = Vec::new();
+    for _ in 0..64 {
+        payloads.push(Vec::from(random_bytes!(100)));
+    }
+
+    // All records are in flight before the receiver decrypts any of them.
+    for payload in &payloads {
+        sender.send(payload).await?;
+    }
+
+    for payload in &payloads {
+        assert_eq!(&receiver.recv().await?, payload);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,130 @@
+use client::{Client, ClientBuilder, Error, Roster};
+use crypto::{
+    key::KeyMaterial,
+    sign::{Hs256, TokenVerifier, sign_token},
+};
+use server::ServerBuilder;
+use std::{net::SocketAddr, time::Duration};
+use testutil::{DynResult, generate_token};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+    time::timeout,
+};
+
+const KEY: [u8; 32] = [10; 32];
+
+async fn spawn_server() -> DynResult<SocketAddr> {
+    let server = ServerBuilder::new(
+        "127.0.0.1:0".parse()?,
+        KeyMaterial::from_bytes(vec![0; 16]),
+        TokenVerifier::from(Hs256::try_new(&KEY)?),
+    )
+    .try_build()
+    .await?;
+
+    let addr = server.local_addr()?;
+    tokio::spawn(server.serve());
+
+    Ok(addr)
+}
+
+async fn connect(
+    addr: SocketAddr,
+    id: u64,
+    name: &str,
+    shared_ports: Vec<u16>,
+) -> Result<Client, Error> {
+    let token = generate_token(id, String::from(name), vec![String::from("test")]);
+
+    ClientBuilder {
+        shared_ports: shared_ports.into_iter().map(|port| port..=port).collect(),
+        ..ClientBuilder::new(
+            addr,
+            KeyMaterial::from_bytes(vec![0; 16]),
+            sign_token(token, &Hs256::try_new(&KEY)?)?,
+        )
+    }
+    .try_build()
+    .await
+}
+
+/// Waits until the roster satisfies `condition`.
+async fn wait_for(
+    roster: &mut watch::Receiver<Roster>,
+    condition: impl FnMut(&Roster) -> bool,
+) -> DynResult<()> {
+    timeout(Duration::from_secs(5), roster.wait_for(condition)).await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn roster() -> DynResult<()> {
+    let addr = spawn_server().await?;
+
+    let first = connect(addr, 1, "first", vec![]).await?;
+    let mut roster = first.watch_roster();
+
+    let second = connect(addr, 2, "license-srv-01", vec![]).await?;
+    wait_for(&mut roster, |roster| roster.get(2).is_some()).await?;
+
+    let peer = first.roster().get(2).cloned().unwrap();
+    assert_eq!(peer.name, "license-srv-01");
+    assert_eq!(peer.tags, ["test"]);
+    assert_eq!(first.roster().by_tag("test").count(), 1);
+    assert_eq!(first.roster().resolve("license-srv-01"), Some(2));
+
+    // The list sent after the authentication includes the earlier clients.
+    let mut second_roster = second.watch_roster();
+    wait_for(&mut second_roster, |roster| {
+        roster.by_name("first").count() == 1
+    })
+    .await?;
+
+    drop(second);
+    wait_for(&mut roster, Roster::is_empty).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn forward_peer() -> DynResult<()> {
+    let addr = spawn_server().await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            socket.write_all(b"license").await?;
+        }
+        Ok::<_, std::io::Error>(())
+    });
+
+    let _sharer = connect(addr, 2, "license-srv-01", vec![port]).await?;
+    let requester = connect(addr, 1, "requester", vec![]).await?;
+    wait_for(&mut requester.watch_roster(), |roster| {
+        roster.get(2).is_some()
+    })
+    .await?;
+
+    let forward = requester
+        .forward_peer("127.0.0.1:0".parse()?, "license-srv-01", port)
+        .await?;
+    let mut socket = TcpStream::connect(forward.local_addr()).await?;
+    let mut greeting = [0; 7];
+    timeout(Duration::from_secs(5), socket.read_exact(&mut greeting)).await??;
+    assert_eq!(&greeting, b"license");
+
+    // Unknown peers reset the local connection.
+    let forward = requester
+        .forward_peer("127.0.0.1:0".parse()?, "unknown", port)
+        .await?;
+    let mut socket = TcpStream::connect(forward.local_addr()).await?;
+    let error = timeout(Duration::from_secs(5), socket.read(&mut [0; 1]))
+        .await?
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::ConnectionReset);
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use client::{Client, ClientBuilder, Error};
+use crypto::{
+    key::KeyMaterial,
+    sign::{Hs256, TokenVerifier, sign_token},
+};
+use server::ServerBuilder;
+use std::{net::SocketAddr, time::Duration};
+use testutil::{DynResult, generate_token};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+    time::timeout,
+};
+use tokio_util::sync::CancellationToken;
+
+const KEY: [u8; 32] = [3; 32];
+
+type ServeHandle = JoinHandle<Result<(), server::Error>>;
+
+async fn spawn_server() -> DynResult<(SocketAddr, CancellationToken, ServeHandle)> {
+    let server = ServerBuilder::new(
+        "127.0.0.1:0".parse()?,
+        KeyMaterial::from_bytes(vec![0; 16]),
+        TokenVerifier::from(Hs256::try_new(&KEY)?),
+    )
+    .try_build()
+    .await?
+    .with_shutdown_timeout(Duration::from_secs(2));
+
+    let addr = server.local_addr()?;
+    let shutdown = server.shutdown_token();
+
+    Ok((addr, shutdown, tokio::spawn(server.serve())))
+}
+
+async fn connect(addr: SocketAddr, id: u64, shared_ports: Vec<u16>) -> Result<Client, Error> {
+    let token = generate_token(id, format!("client-{id}"), vec![String::from("test")]);
+
+    ClientBuilder {
+        shared_ports: shared_ports.into_iter().map(|port| port..=port).collect(),
+        ..ClientBuilder::new(
+            addr,
+            KeyMaterial::from_bytes(vec![0; 16]),
+            sign_token(token, &Hs256::try_new(&KEY)?)?,
+        )
+    }
+    .try_build()
+    .await
+}
+
+async fn spawn_echo() -> DynResult<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut r, mut w) = socket.split();
+                let _ = tokio::io::copy(&mut r, &mut w).await;
+            });
+        }
+    });
+
+    Ok(port)
+}
+
+async fn wait_closed(client: &Client) -> DynResult<()> {
+    timeout(Duration::from_secs(5), async {
+        while !client.is_closed() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn server_shutdown() -> DynResult<()> {
+    let (addr, shutdown, serve) = spawn_server().await?;
+    let echo_port = spawn_echo().await?;
+
+    let sharer = connect(addr, 2, vec![echo_port]).await?;
+    let requester = connect(addr, 1, vec![]).await?;
+    let forward = requester
+        .forward("127.0.0.1:0".parse()?, 2, echo_port)
+        .await?;
+
+    let mut socket = TcpStream::connect(forward.local_addr()).await?;
+    socket.write_all(b"ping").await?;
+    let mut buf = [0; 4];
+    socket.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"ping");
+
+    // A connection that never completes the handshake does not delay the
+    // shutdown.
+    let _idle = TcpStream::connect(addr).await?;
+
+    shutdown.cancel();
+    timeout(Duration::from_secs(5), serve).await???;
+
+    wait_closed(&sharer).await?;
+    wait_closed(&requester).await?;
+
+    // Relayed connections are closed once the clients are disconnected.
+    assert_eq!(
+        timeout(Duration::from_secs(5), socket.read(&mut buf)).await??,
+        0
+    );
+    assert!(TcpStream::connect(addr).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn client_shutdown() -> DynResult<()> {
+    let (addr, shutdown, serve) = spawn_server().await?;
+
+    let mut client = connect(addr, 1, vec![]).await?;
+    client.shutdown().await;
+    assert!(client.is_closed());
+
+    // The server released the token ID of the client.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let _client = connect(addr, 1, vec![]).await?;
+
+    shutdown.cancel();
+    timeout(Duration::from_secs(5), serve).await???;
+
+    Ok(())
+}
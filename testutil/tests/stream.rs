@@ -0,0 +1,235 @@
+use client::{Client, ClientBuilder, Error};
+use crypto::{
+    key::KeyMaterial,
+    sign::{Hs256, TokenVerifier, sign_token},
+};
+use proto_core::{common::INITIAL_WINDOW_SIZE, sub_protocol::application_data::TerminateReason};
+use server::ServerBuilder;
+use std::{net::SocketAddr, time::Duration};
+use testutil::{DynResult, generate_token};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    time::timeout,
+};
+
+const KEY: [u8; 32] = [8; 32];
+
+const ACCEPTED_PORT: u16 = 9;
+
+async fn spawn_server() -> DynResult<SocketAddr> {
+    let server = ServerBuilder::new(
+        "127.0.0.1:0".parse()?,
+        KeyMaterial::from_bytes(vec![0; 16]),
+        TokenVerifier::from(Hs256::try_new(&KEY)?),
+    )
+    .try_build()
+    .await?;
+
+    let addr = server.local_addr()?;
+    tokio::spawn(server.serve());
+
+    Ok(addr)
+}
+
+async fn connect(
+    addr: SocketAddr,
+    id: u64,
+    shared_ports: Vec<u16>,
+    accepted_ports: Vec<u16>,
+) -> Result<Client, Error> {
+    let token = generate_token(id, format!("client-{id}"), vec![String::from("test")]);
+
+    ClientBuilder {
+        shared_ports: shared_ports.into_iter().map(|port| port..=port).collect(),
+        accepted_ports,
+        ..ClientBuilder::new(
+            addr,
+            KeyMaterial::from_bytes(vec![0; 16]),
+            sign_token(token, &Hs256::try_new(&KEY)?)?,
+        )
+    }
+    .try_build()
+    .await
+}
+
+#[tokio::test]
+async fn open_stream_to_shared_port() -> DynResult<()> {
+    let addr = spawn_server().await?;
+
+    // Reads the whole request, then answers with its length.
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await?;
+        let mut request = Vec::new();
+        socket.read_to_end(&mut request).await?;
+        socket.write_all(&request.len().to_be_bytes()).await?;
+        Ok::<_, std::io::Error>(())
+    });
+
+    let _sharer = connect(addr, 2, vec![port], vec![]).await?;
+    let requester = connect(addr, 1, vec![], vec![]).await?;
+
+    let mut stream = requester.open_stream(2, port).await?;
+    assert_eq!(stream.port(), port);
+
+    // Larger than the initial window, so window updates are required.
+    let payload: Vec<u8> = (0..1024 * 1024).map(|i| i as u8).collect();
+    timeout(Duration::from_secs(10), stream.write_all(&payload)).await??;
+    stream.shutdown().await?;
+
+    // The shutdown only closes the writing direction.
+    let mut response = Vec::new();
+    timeout(Duration::from_secs(5), stream.read_to_end(&mut response)).await??;
+    assert_eq!(response, payload.len().to_be_bytes());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn accept_stream() -> DynResult<()> {
+    let addr = spawn_server().await?;
+
+    let acceptor = connect(addr, 2, vec![], vec![ACCEPTED_PORT]).await?;
+    let requester = connect(addr, 1, vec![], vec![]).await?;
+
+    let echo = tokio::spawn(async move {
+        let mut stream = acceptor.accept_stream().await?;
+        assert_eq!(stream.port(), ACCEPTED_PORT);
+
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                stream.shutdown().await?;
+                return Ok::<_, Box<dyn std::error::Error + Send + Sync>>(acceptor);
+            }
+            stream.write_all(&buf[..n]).await?;
+            stream.flush().await?;
+        }
+    });
+
+    let mut stream = requester.open_stream(2, ACCEPTED_PORT).await?;
+    let payload: Vec<u8> = (0..512 * 1024).map(|i| (i / 7) as u8).collect();
+    let (mut r, mut w) = tokio::io::split(&mut stream);
+
+    let (_, received) = timeout(Duration::from_secs(10), async {
+        tokio::try_join!(w.write_all(&payload), async {
+            let mut received = vec![0; payload.len()];
+            r.read_exact(&mut received).await?;
+            Ok(received)
+        })
+    })
+    .await??;
+    assert_eq!(received, payload);
+
+    // The shutdown ends the reads of the acceptor, and its own shutdown ends
+    // ours.
+    stream.shutdown().await?;
+    let _acceptor = timeout(Duration::from_secs(5), echo).await??.unwrap();
+    assert_eq!(
+        timeout(Duration::from_secs(5), stream.read(&mut [0; 1])).await??,
+        0
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn data_precedes_termination() -> DynResult<()> {
+    let addr = spawn_server().await?;
+
+    let acceptor = connect(addr, 2, vec![], vec![ACCEPTED_PORT]).await?;
+    let requester = connect(addr, 1, vec![], vec![]).await?;
+
+    let reader = tokio::spawn(async move {
+        let mut stream = acceptor.accept_stream().await?;
+        let mut received = Vec::new();
+        let error = stream.read_to_end(&mut received).await.unwrap_err();
+        Ok::<_, Error>((received, error.kind()))
+    });
+
+    // Many windows, so data is still queued when the stream is dropped.
+    let payload: Vec<u8> = (0..16 * INITIAL_WINDOW_SIZE)
+        .map(|i| (i / 3) as u8)
+        .collect();
+    let mut stream = requester.open_stream(2, ACCEPTED_PORT).await?;
+    timeout(Duration::from_secs(10), stream.write_all(&payload)).await??;
+    stream.flush().await?;
+    drop(stream);
+
+    let (received, kind) = timeout(Duration::from_secs(10), reader).await???;
+    assert_eq!(kind, std::io::ErrorKind::ConnectionReset);
+    assert_eq!(received.len(), payload.len());
+    assert!(received == payload);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn terminated_stream_fails_reads() -> DynResult<()> {
+    let addr = spawn_server().await?;
+
+    let acceptor = connect(addr, 2, vec![], vec![ACCEPTED_PORT]).await?;
+    let requester = connect(addr, 1, vec![], vec![]).await?;
+
+    let mut stream = requester.open_stream(2, ACCEPTED_PORT).await?;
+    drop(timeout(Duration::from_secs(5), acceptor.accept_stream()).await??);
+
+    let error = timeout(Duration::from_secs(5), stream.read(&mut [0; 1]))
+        .await?
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::ConnectionReset);
+
+    // The reason is mapped to the error kind.
+    let mut stream = requester.open_stream(2, ACCEPTED_PORT).await?;
+    drop(acceptor);
+
+    let error = timeout(Duration::from_secs(5), stream.read(&mut [0; 1]))
+        .await?
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::ConnectionAborted);
+    assert!(matches!(
+        error.into_inner().unwrap().downcast_ref::<Error>(),
+        Some(Error::ConnectionTerminated {
+            reason: TerminateReason::PeerDisconnected,
+            ..
+        })
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn refuse_unaccepted_port() -> DynResult<()> {
+    let addr = spawn_server().await?;
+
+    let _acceptor = connect(addr, 2, vec![], vec![ACCEPTED_PORT]).await?;
+    let requester = connect(addr, 1, vec![], vec![]).await?;
+
+    assert!(matches!(
+        requester.open_stream(2, ACCEPTED_PORT + 1).await,
+        Err(Error::ConnectionTerminated {
+            reason: TerminateReason::PortNotShared,
+            ..
+        })
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn accept_stream_fails_once_closed() -> DynResult<()> {
+    let addr = spawn_server().await?;
+    let mut client = connect(addr, 1, vec![], vec![ACCEPTED_PORT]).await?;
+
+    client.shutdown().await;
+    assert!(
+        timeout(Duration::from_secs(5), client.accept_stream())
+            .await?
+            .is_err()
+    );
+
+    Ok(())
+}